@@ -24,15 +24,15 @@ fn impl_ant_tx(ast: &syn::DeriveInput) -> TokenStream {
         impl TransmitableMessage for #name {
             fn serialize_message(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
                 let len = PackedStructSlice::packed_bytes_size(Some(self))?;
-                self.pack_to_slice(&mut buf[..len])?;
+                self.pack_to_slice(buf.get_mut(..len).ok_or(PackingError::BufferTooSmall)?)?;
                 Ok(len)
             }
             fn get_tx_msg_id(&self) -> TxMessageId {
                 TxMessageId::#name
             }
         }
-        impl From<#name> for TxMessage {
-            fn from(msg: #name) -> TxMessage {
+        impl<const N: usize> From<#name> for TxMessage<N> {
+            fn from(msg: #name) -> TxMessage<N> {
                 TxMessage::#name(msg)
             }
         }
@@ -6,9 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use ant::callback::Callback;
 use ant::channel::{RxError, RxHandler, TxError, TxHandler};
 use ant::drivers::{is_ant_usb_device_from_device, UsbDriver};
-use ant::messages::config::SetNetworkKey;
+use ant::network::{NetworkKey, NetworkRole};
 use ant::plus::profiles::heart_rate::{Display, DisplayConfig, Period};
 use ant::router::Router;
 use dialoguer::Select;
@@ -87,8 +88,10 @@ fn main() -> std::io::Result<()> {
         },
     )
     .unwrap();
-    let snk = SetNetworkKey::new(0, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]); // Get this from thisisant.com
-    router.send(&snk).expect("failed to set network key");
+    let key = NetworkKey::Key64([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]); // Get this from thisisant.com
+    router
+        .set_ant_plus_key(0, key)
+        .expect("failed to set network key");
     let chan = router
         .add_channel(TxSender { sender: router_tx })
         .expect("Add channel failed");
@@ -97,7 +100,7 @@ fn main() -> std::io::Result<()> {
         device_number_extension: 0.into(),
         channel: chan,
         period: Period::FourHz,
-        ant_plus_key_index: 0,
+        ant_plus_key_index: router.network(NetworkRole::AntPlus).unwrap(),
     };
     let mut hr = Display::new(
         config,
@@ -106,8 +109,8 @@ fn main() -> std::io::Result<()> {
             receiver: channel_rx,
         },
     );
-    hr.set_rx_datapage_callback(Some(|x| println!("{:#?}", x)));
-    hr.set_rx_message_callback(Some(|x| println!("{:#?}", x)));
+    hr.set_rx_datapage_callback(Some(Callback::boxed(Box::new(|x| println!("{:#?}", x)))));
+    hr.set_rx_message_callback(Some(Callback::boxed(Box::new(|x| println!("{:#?}", x)))));
     hr.open();
     loop {
         router.process().unwrap();
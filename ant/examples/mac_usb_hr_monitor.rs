@@ -6,9 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use ant::callback::Callback;
 use ant::channel::mpsc::{RxChannel, TxChannel};
 use ant::drivers::{is_ant_usb_device_from_device, UsbDriver};
-use ant::messages::config::SetNetworkKey;
+use ant::network::{NetworkKey, NetworkRole};
 use ant::plus::profiles::heart_rate::{
     Capabilities, CommonData, Features, MainDataPage, ManufacturerInformation,
     ManufacturerSpecific, Monitor, MonitorConfig, PreviousHeartBeat, ProductInformation,
@@ -94,11 +95,13 @@ fn main() -> std::io::Result<()> {
         },
     )
     .unwrap();
-    let snk = SetNetworkKey::new(0, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]); // Get this from thisisant.com
+    let key = NetworkKey::Key64([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]); // Get this from thisisant.com
     let channel = router
         .add_channel(TxChannel { sender: router_tx })
         .expect("Add channel failed");
-    router.send(&snk).expect("failed to set network key");
+    router
+        .set_ant_plus_key(0, key)
+        .expect("failed to set network key");
     let config = MonitorConfig {
         device_number: 12345,
         transmission_type_extension: 12.into(),
@@ -109,7 +112,7 @@ fn main() -> std::io::Result<()> {
         gym_mode_supported: false,
         number_manufacturer_pages: 2,
         background_page_interval: 64,
-        ant_plus_key_index: 0,
+        ant_plus_key_index: router.network(NetworkRole::AntPlus).unwrap(),
         channel: channel,
     };
     let mut hr = Monitor::new(
@@ -118,12 +121,10 @@ fn main() -> std::io::Result<()> {
         RxChannel {
             receiver: monitor_rx,
         },
-        |x| println!("{:#?}", x),
-        |datapage| make_datapage(datapage),
+        Callback::boxed(Box::new(|x| println!("{:#?}", x))),
+        Callback::boxed(Box::new(make_datapage)),
     );
-    // hr.borrow_mut()
-    //     .set_rx_datapage_callback(Some(|x| println!("{:#?}", x)));
-    hr.set_rx_message_callback(Some(|x| println!("{:#?}", x)));
+    hr.set_rx_message_callback(Some(Callback::boxed(Box::new(|x| println!("{:#?}", x)))));
     hr.open();
     loop {
         router.process().unwrap();
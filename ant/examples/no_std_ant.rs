@@ -1,3 +1,155 @@
-// TODO
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
 
-fn main() {}
+//! Heart rate display wired up the way MCU firmware would be, without std or alloc
+//!
+//! The radio is replaced by a mock UART that answers configuration messages and sends a few heart
+//! rate pages once the channel opens, so this runs on the host. Only the entry point relies on the
+//! host's std, everything else is what a no_std target would run.
+//!
+//! Run with `cargo run --example no_std_ant --no-default-features --features thingbuf`
+
+use ant::callback::Callback;
+use ant::channel::thingbuf_mpsc::{RxChannel, TxChannel};
+use ant::drivers::{SerialDriver, StubPin};
+use ant::messages::{AntMessage, TxMessage};
+use ant::network::{NetworkKey, NetworkRole};
+use ant::plus::profiles::heart_rate::{Display, DisplayConfig, MonitorTxDataPage, Period};
+use ant::router::Router;
+use arrayvec::ArrayVec;
+use core::cell::Cell;
+use core::convert::Infallible;
+use embedded_hal_nb::serial::{ErrorType, Read, Write};
+use thingbuf::mpsc::StaticChannel;
+
+const SYNC: u8 = 0xA4;
+const HEART_RATE_PAGES: u8 = 4;
+
+static TO_PROFILE: StaticChannel<AntMessage, 8> = StaticChannel::new();
+static TO_ROUTER: StaticChannel<TxMessage, 8> = StaticChannel::new();
+
+/// UART with a scripted radio on the other end
+#[derive(Default)]
+struct MockRadio {
+    /// Bytes waiting to be read by the driver
+    rx: ArrayVec<u8, 256>,
+    /// Frame being written by the driver
+    frame: ArrayVec<u8, 64>,
+}
+
+impl MockRadio {
+    fn queue(&mut self, id: u8, payload: &[u8]) {
+        let start = self.rx.len();
+        self.rx.push(SYNC);
+        self.rx.push(payload.len() as u8);
+        self.rx.push(id);
+        self.rx.try_extend_from_slice(payload).unwrap();
+        let checksum = self.rx[start..].iter().fold(0, |acc, x| acc ^ x);
+        self.rx.push(checksum);
+    }
+
+    fn respond(&mut self, id: u8, payload: &[u8]) {
+        match id {
+            // Reset, the radio reports it started
+            0x4A => self.queue(0x6F, &[0x00]),
+            // Request, only capabilities are asked for here
+            0x4D => self.queue(0x54, &[8, 3, 0x00, 0xFA]),
+            // Open channel, acknowledge and start receiving a heart rate monitor
+            0x4B => {
+                self.queue(0x40, &[payload[0], id, 0x00]);
+                for beat in 0..HEART_RATE_PAGES {
+                    self.queue(
+                        0x4E,
+                        &[payload[0], 0x00, 0xFF, 0xFF, 0xFF, 0, beat, beat, 60 + beat],
+                    );
+                }
+            }
+            // Everything else is configuration, accept it
+            _ => self.queue(0x40, &[payload[0], id, 0x00]),
+        }
+    }
+}
+
+impl ErrorType for MockRadio {
+    type Error = Infallible;
+}
+
+impl Read<u8> for MockRadio {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        if self.rx.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(self.rx.remove(0))
+    }
+}
+
+impl Write<u8> for MockRadio {
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.frame.push(byte);
+        // Sync, length, id, payload and checksum
+        if self.frame.len() > 1 && self.frame.len() == self.frame[1] as usize + 4 {
+            let frame = self.frame.take();
+            self.respond(frame[2], &frame[3..frame.len() - 1]);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let driver = SerialDriver::new(MockRadio::default(), None::<StubPin>);
+    let (router_tx, profile_rx) = TO_PROFILE.split();
+    let (profile_tx, router_rx) = TO_ROUTER.split();
+
+    let mut router = Router::new(
+        driver,
+        RxChannel {
+            receiver: router_rx,
+        },
+    )
+    .unwrap();
+    router
+        .set_ant_plus_key(0, NetworkKey::Key64([0; 8]))
+        .unwrap();
+    let channel = router.add_channel(TxChannel { sender: router_tx }).unwrap();
+
+    let heart_rate = Cell::new(0);
+    let pages = Cell::new(0);
+    let mut on_page = |page: Result<MonitorTxDataPage, _>| {
+        if let Ok(MonitorTxDataPage::DefaultDataPage(page)) = page {
+            heart_rate.set(page.common.computed_heart_rate);
+            pages.set(pages.get() + 1);
+        }
+    };
+    let mut hr = Display::new(
+        DisplayConfig {
+            device_number: 0,
+            device_number_extension: 0.into(),
+            channel,
+            period: Period::FourHz,
+            ant_plus_key_index: router.network(NetworkRole::AntPlus).unwrap(),
+        },
+        TxChannel { sender: profile_tx },
+        RxChannel {
+            receiver: profile_rx,
+        },
+    );
+    hr.set_rx_datapage_callback(Some(Callback::new(&mut on_page)));
+    hr.open();
+
+    // On target this would be the main loop, the mock has nothing more to say after a few pages
+    for _ in 0..100 {
+        router.process().unwrap();
+        hr.process().unwrap();
+    }
+    assert_eq!(pages.get(), HEART_RATE_PAGES as u32);
+    assert_eq!(heart_rate.get(), 60 + HEART_RATE_PAGES - 1);
+}
@@ -0,0 +1,105 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Heart rate display driven by an [AsyncRouter] on a tokio runtime
+//!
+//! Run with `cargo run --example tokio_usb_hr_display --features tokio`
+
+use ant::async_router::AsyncRouter;
+use ant::callback::Callback;
+use ant::channel::tokio_mpsc::{RxChannel, TxChannel};
+use ant::drivers::{is_ant_usb_device_from_device, PollingDriver, UsbDriver};
+use ant::network::{NetworkKey, NetworkRole};
+use ant::plus::profiles::heart_rate::{Display, DisplayConfig, Period};
+use dialoguer::Select;
+use embedded_hal_async::delay::DelayNs;
+use rusb::{Device, DeviceList};
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+
+struct TokioDelay;
+
+impl DelayNs for TokioDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        sleep(Duration::from_nanos(ns.into())).await;
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let mut devices: Vec<Device<_>> = DeviceList::new()
+        .expect("Unable to lookup usb devices")
+        .iter()
+        .filter(is_ant_usb_device_from_device)
+        .collect();
+
+    if devices.is_empty() {
+        panic!("No devices found");
+    }
+
+    let device = if devices.len() == 1 {
+        devices.remove(0)
+    } else {
+        let selection = Select::new()
+            .with_prompt("Multiple devices found, please select a radio to use.")
+            .items(
+                &devices
+                    .iter()
+                    .map(|x| x.device_descriptor().unwrap())
+                    .map(|x| format!("{:04x}:{:04x}", x.vendor_id(), x.product_id()))
+                    .collect::<Vec<String>>(),
+            )
+            .interact()
+            .expect("Selection failed");
+        devices.remove(selection)
+    };
+
+    // Poll the radio every millisecond, well below the 4Hz period used below
+    let driver = PollingDriver::new(UsbDriver::new(device).unwrap(), TokioDelay, 1000);
+
+    let (channel_tx, router_rx) = channel(8);
+    let (router_tx, channel_rx) = channel(8);
+
+    let mut router = AsyncRouter::new(driver, RxChannel::new(router_rx))
+        .await
+        .unwrap();
+    let key = NetworkKey::Key64([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77]); // Get this from thisisant.com
+    router
+        .set_ant_plus_key(0, key)
+        .await
+        .expect("failed to set network key");
+    let chan = router
+        .add_channel(TxChannel { sender: router_tx })
+        .await
+        .expect("Add channel failed");
+    let config = DisplayConfig {
+        device_number: 0,
+        device_number_extension: 0.into(),
+        channel: chan,
+        period: Period::FourHz,
+        ant_plus_key_index: router.network(NetworkRole::AntPlus).unwrap(),
+    };
+    let mut hr = Display::new(
+        config,
+        TxChannel { sender: channel_tx },
+        RxChannel::new(channel_rx),
+    );
+    hr.set_rx_datapage_callback(Some(Callback::boxed(Box::new(|x| println!("{:#?}", x)))));
+    hr.open();
+
+    let profile = async {
+        loop {
+            hr.process().unwrap();
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::select! {
+        result = router.run() => result.unwrap(),
+        _ = profile => (),
+    }
+}
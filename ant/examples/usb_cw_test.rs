@@ -0,0 +1,90 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Runs a CW tone sweep for RF certification
+//!
+//! Usage: usb_cw_test <transmit power> <dwell ms> <rf frequency>...
+
+use ant::drivers::*;
+use ant::rf_test::{CwStep, CwTestController};
+use embedded_hal::delay::DelayNs;
+use rusb::{Device, DeviceList};
+
+use dialoguer::Select;
+
+use std::env;
+use std::thread::sleep;
+use std::time::Duration;
+
+struct StdDelay;
+
+impl DelayNs for StdDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        sleep(Duration::from_nanos(ns.into()));
+    }
+}
+
+fn parse(arg: &str) -> u32 {
+    arg.parse()
+        .unwrap_or_else(|_| panic!("Expected a number, got {}", arg))
+}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 3 {
+        panic!("Expected arguments <transmit power> <dwell ms> <rf frequency>...")
+    }
+    let power = parse(&args[0]) as u8;
+    let dwell_ms = parse(&args[1]);
+    let steps: Vec<CwStep> = args[2..]
+        .iter()
+        .map(|x| CwStep::new(power, parse(x) as u8, dwell_ms))
+        .collect();
+
+    let mut devices: Vec<Device<_>> = DeviceList::new()
+        .expect("Unable to lookup usb devices")
+        .iter()
+        .filter(is_ant_usb_device_from_device)
+        .collect();
+
+    if devices.is_empty() {
+        panic!("No devices found");
+    }
+
+    let device = if devices.len() == 1 {
+        devices.remove(0)
+    } else {
+        let selection = Select::new()
+            .with_prompt("Multiple devices found, please select a radio to use.")
+            .items(
+                &devices
+                    .iter()
+                    .map(|x| x.device_descriptor().unwrap())
+                    .map(|x| format!("{:04x}:{:04x}", x.vendor_id(), x.product_id()))
+                    .collect::<Vec<String>>(),
+            )
+            .interact()
+            .expect("Dialogue error");
+        devices.remove(selection)
+    };
+
+    let driver = UsbDriver::new(device).unwrap();
+    let mut delay = StdDelay;
+    let mut cw: CwTestController<_, _> =
+        CwTestController::enter(driver, &mut delay).expect("Failed to enter test mode");
+    for step in &steps {
+        println!(
+            "Tone at 24{:02}MHz, power {}, for {}ms",
+            step.rf_frequency, step.transmit_power, step.dwell_ms
+        );
+    }
+    cw.sweep(&steps, &mut delay).expect("Sweep failed");
+    cw.exit(&mut delay).expect("Failed to reset radio");
+    println!("Radio returned to normal mode");
+    Ok(())
+}
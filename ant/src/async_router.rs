@@ -0,0 +1,611 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Router for async executors
+//!
+//! [AsyncRouter] does the same job as [crate::router::Router] but instead of being polled it waits
+//! on the radio and the profiles at the same time, so it can run as a single task next to the
+//! profiles. Both routers keep the radio's state the same way, only how messages are passed on
+//! differs. By default deliveries wait for room in a profile's channel, a slow profile holds up
+//! the radio rather than losing messages.
+//!
+//! Calls waiting on the radio, e.g. [AsyncRouter::set_network_key], have no timeout, wrap them in
+//! your executor's timeout if the radio may not respond.
+
+use crate::burst::AdvancedBurstConfig;
+use crate::callback::RxMessageCallback;
+use crate::channel::{AsyncRxHandler, AsyncTxHandler, ChanError, OverflowPolicy, TxHandler};
+use crate::drivers::AsyncDriver;
+use crate::encryption::EncryptionKeyConfig;
+use crate::messages::config::{
+    ChannelId, ConfigureAdvancedBurst, ConfigureEventFilter, LibConfig, UnAssignChannel,
+};
+use crate::messages::control::{
+    CloseChannel, NvmeRequest, RequestMessage, RequestableMessageId, ResetSystem,
+};
+use crate::messages::requested_response::{
+    AdvancedBurstCapabilities, AntVersion, ChannelStatus, EventBufferConfiguration, EventFilter,
+    RadioCapabilities, SelectiveDataUpdateMaskSetting, SerialNumber, UserNvm,
+};
+use crate::messages::{
+    AntMessage, RxMessage, TransmitableMessage, TxMessage, DEFAULT_PAYLOAD_CAPACITY,
+};
+use crate::network::{NetworkEntry, NetworkKey, NetworkRole};
+use crate::router::{Requested, Route, RouterError, RouterState, MAX_CHANNELS};
+use crate::sdu::SduMasks;
+use crate::stats::LinkStats;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+use std::marker::PhantomData;
+
+/// Routes messages between an async driver and the channels assigned to it
+///
+/// `N` is the capacity of variable length message fields and must match the driver.
+pub struct AsyncRouter<
+    'a,
+    E,
+    D: AsyncDriver<E, N>,
+    T: TxHandler<AntMessage<N>> + AsyncTxHandler<AntMessage<N>>,
+    R: AsyncRxHandler<TxMessage<N>>,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+> {
+    channels: [Option<T>; MAX_CHANNELS],
+    state: RouterState<'a, N>,
+    driver: D,
+    receiver: R,
+    _marker: PhantomData<E>,
+}
+
+impl<
+        'a,
+        E,
+        D: AsyncDriver<E, N>,
+        T: TxHandler<AntMessage<N>> + AsyncTxHandler<AntMessage<N>>,
+        R: AsyncRxHandler<TxMessage<N>>,
+        const N: usize,
+    > AsyncRouter<'a, E, D, T, R, N>
+{
+    /// Reset the radio and wait for it to report its capabilities
+    pub async fn new(mut driver: D, receiver: R) -> Result<Self, RouterError> {
+        driver.send_message(&ResetSystem::new()).await?;
+        driver
+            .send_message(&RequestMessage::new(
+                0,
+                RequestableMessageId::Capabilities,
+                None,
+            ))
+            .await?;
+        let mut router = Self {
+            channels: std::array::from_fn(|_| None),
+            state: RouterState::new(OverflowPolicy::Block),
+            driver,
+            receiver,
+            _marker: PhantomData,
+        };
+        // Anything from before the reset is stale, drop it
+        loop {
+            let msg = router.driver.get_message().await?;
+            if let RxMessage::Capabilities(_) = msg.message {
+                router.state.handle_message(&msg);
+                break;
+            }
+        }
+        Ok(router)
+    }
+
+    /// Capabilities reported by the radio
+    pub fn capabilities(&self) -> Option<RadioCapabilities> {
+        self.state.capabilities()
+    }
+
+    /// Add a channel at next available index
+    ///
+    /// The channel is sent the radio capabilities so it can tell what configuration is supported.
+    pub async fn add_channel(&mut self, channel: T) -> Result<u8, RouterError> {
+        let index = self.channels[..self.state.max_channels()]
+            .iter()
+            .position(|x| x.is_none())
+            .ok_or(RouterError::OutOfChannels())?;
+        self.insert_channel(channel, index).await?;
+        Ok(index as u8)
+    }
+
+    /// Add channel at a specific index
+    pub async fn add_channel_at_index(
+        &mut self,
+        channel: T,
+        index: usize,
+    ) -> Result<(), RouterError> {
+        if index >= self.state.max_channels() {
+            return Err(RouterError::ChannelOutOfBounds());
+        }
+        if self.channels[index].is_some() {
+            return Err(RouterError::ChannelAlreadyAssigned());
+        }
+        self.insert_channel(channel, index).await
+    }
+
+    async fn insert_channel(&mut self, channel: T, index: usize) -> Result<(), RouterError> {
+        for msg in self.state.welcome_messages() {
+            AsyncTxHandler::send(&channel, msg).await?;
+        }
+        self.channels[index] = Some(channel);
+        Ok(())
+    }
+
+    /// Given a reference channel remove it from the router
+    ///
+    /// Messages the radio still sends for the channel, e.g. the responses to closing it, only
+    /// reach the rx message callback.
+    pub async fn remove_channel(&mut self, channel: u8) -> Result<(), RouterError> {
+        self.channels
+            .get_mut(channel as usize)
+            .ok_or(RouterError::ChannelOutOfBounds())?
+            .take()
+            .ok_or(RouterError::ChannelNotAssociated())?;
+        self.driver
+            .send_message(&CloseChannel::new(channel))
+            .await?;
+        self.driver
+            .send_message(&UnAssignChannel::new(channel))
+            .await?;
+        self.state.remove_channel(channel);
+        Ok(())
+    }
+
+    /// Reboot radio via reset message, see [crate::router::Router::reset]
+    pub async fn reset(&mut self, restore: bool) -> Result<(), RouterError> {
+        self.driver.send_message(&ResetSystem::new()).await?;
+        self.state.reset(restore);
+        if !restore {
+            self.channels = std::array::from_fn(|_| None);
+        }
+        Ok(())
+    }
+
+    // Replay radio wide state lost in a reset
+    async fn restore(&mut self) -> Result<(), RouterError> {
+        // A failed message should not leave the rest unrestored, the first error is reported
+        let mut result = Ok(());
+        for msg in self.state.restore_messages() {
+            result = result.and(match msg {
+                Ok(msg) => self
+                    .driver
+                    .send_message(&msg)
+                    .await
+                    .map_err(RouterError::from),
+                Err(e) => Err(e.into()),
+            });
+        }
+        // Channels cleared their filter on startup
+        match self.state.restored_event_filter() {
+            Some(msg) => result.and(self.broadcast_message(msg).await),
+            None => result,
+        }
+    }
+
+    /// Load a key for an application specific network
+    ///
+    /// Waits until the radio accepts the key. The key is restored if the radio resets.
+    pub async fn set_network_key(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+    ) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::Private)
+            .await
+    }
+
+    /// Load the ANT+ key, see [AsyncRouter::set_network_key]
+    pub async fn set_ant_plus_key(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+    ) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::AntPlus)
+            .await
+    }
+
+    /// Load the ANT-FS key, see [AsyncRouter::set_network_key]
+    pub async fn set_ant_fs_key(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+    ) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::AntFs)
+            .await
+    }
+
+    async fn load_network_key(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+        role: NetworkRole,
+    ) -> Result<(), RouterError> {
+        let msg = self.state.network_key_message(network, key)?;
+        self.send_message(&msg).await?;
+        self.state.insert_network_key(network, key, role);
+        while self.state.network_key_pending(network) {
+            self.process().await?;
+        }
+        self.state.network_key_result(network)
+    }
+
+    /// Key loaded at `network`
+    pub fn network_key(&self, network: u8) -> Option<NetworkEntry> {
+        self.state.network_key(network)
+    }
+
+    /// Network index whose accepted key fills `role`
+    pub fn network(&self, role: NetworkRole) -> Option<u8> {
+        self.state.network(role)
+    }
+
+    /// Set which extended data the radio appends to received messages, it is restored if the
+    /// radio resets
+    pub async fn set_lib_config(&mut self, config: LibConfig) -> Result<(), RouterError> {
+        self.send_message(&config).await?;
+        self.state.set_lib_config(config);
+        Ok(())
+    }
+
+    /// Load the encryption key and info shared by all encrypted channels, see
+    /// [crate::router::Router::set_encryption_key]
+    pub async fn set_encryption_key(
+        &mut self,
+        config: &EncryptionKeyConfig,
+    ) -> Result<(), RouterError> {
+        for msg in config.messages::<N>() {
+            self.send_command(&msg).await?;
+        }
+        self.state.set_encryption_key(*config);
+        Ok(())
+    }
+
+    // Send a radio wide command and process messages until the radio answers it
+    async fn send_command(&mut self, msg: &dyn TransmitableMessage) -> Result<(), RouterError> {
+        let id = msg.get_tx_msg_id();
+        self.send_message(msg).await?;
+        self.state.start_command(id);
+        while !self.state.command_answered() {
+            if let Err(e) = self.process().await {
+                self.state.finish_command(id).ok();
+                return Err(e);
+            }
+        }
+        self.state.finish_command(id)
+    }
+
+    /// Advanced burst capabilities reported by the radio, once they have been requested
+    pub fn advanced_burst_capabilities(&self) -> Option<AdvancedBurstCapabilities> {
+        self.state.advanced_burst_capabilities()
+    }
+
+    /// Negotiate and apply an advanced burst configuration, see
+    /// [crate::router::Router::configure_advanced_burst]
+    pub async fn configure_advanced_burst(
+        &mut self,
+        config: &AdvancedBurstConfig,
+        peer: Option<&ConfigureAdvancedBurst>,
+    ) -> Result<ConfigureAdvancedBurst, RouterError> {
+        self.state
+            .check_message(&ConfigureAdvancedBurst::default())?;
+        if self.state.advanced_burst_capabilities().is_none() {
+            self.send_message(&AdvancedBurstConfig::request_capabilities())
+                .await?;
+            while self.state.advanced_burst_capabilities().is_none() {
+                self.process().await?;
+            }
+        }
+        let capabilities = self
+            .state
+            .advanced_burst_capabilities()
+            .ok_or(RouterError::FailedToGetCapabilities())?;
+        let msg = config.negotiate(&capabilities, peer)?;
+        self.send_message(&msg).await?;
+        Ok(msg)
+    }
+
+    /// Events currently suppressed by the radio
+    pub fn event_filter(&self) -> ConfigureEventFilter {
+        self.state.event_filter()
+    }
+
+    /// Configure which events the radio suppresses, all channels are sent the new filter
+    ///
+    /// EVENT_TX can not be filtered while a profile has a master channel assigned, see
+    /// [RouterError::EventTxRequired].
+    pub async fn set_event_filter(
+        &mut self,
+        filter: ConfigureEventFilter,
+    ) -> Result<(), RouterError> {
+        self.state.check_event_filter(filter)?;
+        self.send_message(&filter).await?;
+        match self.state.set_event_filter(filter) {
+            Some(msg) => self.broadcast_message(msg).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Selective data update masks loaded in the radio
+    pub fn sdu_masks(&self) -> &SduMasks {
+        self.state.sdu_masks()
+    }
+
+    /// Load a selective data update mask for data page `page`
+    pub async fn set_sdu_mask(&mut self, page: u8, mask: [u8; 8]) -> Result<(), RouterError> {
+        let (masks, msg) = self.state.sdu_mask_update(page, mask)?;
+        self.send_message(&msg).await?;
+        self.state.set_sdu_masks(masks);
+        Ok(())
+    }
+
+    /// Release the mask of `page`, fails if a channel is still bound to it
+    pub fn remove_sdu_mask(&mut self, page: u8) -> Result<(), RouterError> {
+        self.state.remove_sdu_mask(page)
+    }
+
+    /// Only forward data on `channel` when bytes covered by the mask of `page` change
+    pub async fn bind_sdu_mask(&mut self, channel: u8, page: u8) -> Result<(), RouterError> {
+        self.set_sdu_binding(channel, Some(page)).await
+    }
+
+    /// Forward all data on `channel` again
+    pub async fn unbind_sdu_mask(&mut self, channel: u8) -> Result<(), RouterError> {
+        self.set_sdu_binding(channel, None).await
+    }
+
+    async fn set_sdu_binding(&mut self, channel: u8, page: Option<u8>) -> Result<(), RouterError> {
+        let msg = self.state.sdu_binding_message(channel, page)?;
+        self.send_message(&msg).await?;
+        self.state.set_sdu_binding(channel, page);
+        Ok(())
+    }
+
+    /// Page whose mask is applied to `channel`
+    pub fn sdu_binding(&self, channel: u8) -> Option<u8> {
+        self.state.sdu_binding(channel)
+    }
+
+    /// Set what happens to messages for `channel` when its queue is full
+    ///
+    /// Defaults to [OverflowPolicy::Block], which waits for room in the queue. The policy applies
+    /// to the index, not the channel, and is reset when the channel is removed.
+    pub fn set_overflow_policy(
+        &mut self,
+        channel: u8,
+        policy: OverflowPolicy,
+    ) -> Result<(), RouterError> {
+        self.state.set_overflow_policy(channel, policy)
+    }
+
+    pub fn overflow_policy(&self, channel: u8) -> Option<OverflowPolicy> {
+        self.state.overflow_policy(channel)
+    }
+
+    /// Messages for `channel` discarded since it was added because its queue was full or closed
+    pub fn dropped_messages(&self, channel: u8) -> Option<u32> {
+        self.state.dropped_messages(channel)
+    }
+
+    /// Link statistics gathered for `channel` since it was added or last reset
+    pub fn link_stats(&self, channel: u8) -> Option<LinkStats> {
+        self.state.link_stats(channel)
+    }
+
+    /// Clear the link statistics of `channel`
+    pub fn reset_link_stats(&self, channel: u8) {
+        self.state.reset_link_stats(channel)
+    }
+
+    /// Register a callback to obersve all messages, see
+    /// [crate::router::Router::set_rx_message_callback]
+    pub fn set_rx_message_callback(&mut self, f: Option<RxMessageCallback<'a, N>>) {
+        self.state.set_rx_message_callback(f);
+    }
+
+    /// Send a message straight to the radio
+    ///
+    /// Messages relying on features the radio does not report are rejected with
+    /// [RouterError::Unsupported].
+    pub async fn send_message(&mut self, msg: &dyn TransmitableMessage) -> Result<(), RouterError> {
+        self.state.check_message(msg)?;
+        self.driver.send_message(msg).await?;
+        Ok(())
+    }
+
+    /// Query the radio's version string
+    pub async fn request_version(&mut self) -> Result<AntVersion<N>, RouterError> {
+        self.request(0, None).await
+    }
+
+    pub async fn request_serial_number(&mut self) -> Result<SerialNumber, RouterError> {
+        self.request(0, None).await
+    }
+
+    /// Query capabilities again, the result also replaces [AsyncRouter::capabilities]
+    pub async fn request_capabilities(&mut self) -> Result<RadioCapabilities, RouterError> {
+        self.request(0, None).await
+    }
+
+    pub async fn request_channel_status(
+        &mut self,
+        channel: u8,
+    ) -> Result<ChannelStatus, RouterError> {
+        self.state.check_channel(channel)?;
+        self.request(channel, None).await
+    }
+
+    /// Query the ID of `channel`, for slaves this is the ID of the master it is tracking
+    pub async fn request_channel_id(&mut self, channel: u8) -> Result<ChannelId, RouterError> {
+        self.state.check_channel(channel)?;
+        self.request(channel, None).await
+    }
+
+    pub async fn request_event_buffer_configuration(
+        &mut self,
+    ) -> Result<EventBufferConfiguration, RouterError> {
+        self.request(0, None).await
+    }
+
+    /// Query the event filter, the result also replaces [AsyncRouter::event_filter]
+    pub async fn request_event_filter(&mut self) -> Result<EventFilter, RouterError> {
+        self.request(0, None).await
+    }
+
+    /// Query the mask loaded in SDU mask slot `mask_number`
+    pub async fn request_sdu_mask(
+        &mut self,
+        mask_number: u8,
+    ) -> Result<SelectiveDataUpdateMaskSetting, RouterError> {
+        self.request(mask_number, None).await
+    }
+
+    /// Read `size` bytes of user NVM starting at `addr`, see
+    /// [crate::router::Router::request_user_nvm]
+    pub async fn request_user_nvm(
+        &mut self,
+        addr: u16,
+        size: u8,
+    ) -> Result<UserNvm<N>, RouterError> {
+        let region = RouterState::<N>::user_nvm_region(addr, size)?;
+        self.request(0, Some(region)).await
+    }
+
+    // Send a request and process messages until the matching response arrives
+    async fn request<V: Requested<N>>(
+        &mut self,
+        channel: u8,
+        nvme_region: Option<NvmeRequest>,
+    ) -> Result<V, RouterError> {
+        let msg = self.state.start_request(V::ID, channel, nvme_region);
+        if let Err(e) = self.send_message(&msg).await {
+            self.state.finish_request::<V>().ok();
+            return Err(e);
+        }
+        while !self.state.request_answered() {
+            if let Err(e) = self.process().await {
+                self.state.finish_request::<V>().ok();
+                return Err(e);
+            }
+        }
+        self.state.finish_request()
+    }
+
+    /// Wait for the next message from either the radio or a profile and pass it on
+    ///
+    /// Messages from the radio are handled first when both are ready. Messages for a channel
+    /// whose queue is full are handled according to its [OverflowPolicy].
+    pub async fn process(&mut self) -> Result<(), RouterError> {
+        // Routing carries on past a failed delivery, the first error is reported at the end
+        let mut result = self.flush_backlogs();
+        let next = {
+            let mut incoming = pin!(self.driver.get_message());
+            let mut outgoing = pin!(self.receiver.recv());
+            poll_fn(|cx| {
+                if let Poll::Ready(msg) = incoming.as_mut().poll(cx) {
+                    return Poll::Ready(Ok(msg));
+                }
+                outgoing.as_mut().poll(cx).map(Err)
+            })
+            .await
+        };
+        match next {
+            Ok(msg) => result = result.and(self.handle_message(msg?).await),
+            Err(msg) => {
+                let msg = msg.map_err(|e| RouterError::ChannelBufferError(ChanError::Rx(e)))?;
+                result = result.and(match self.state.check_profile_message(&msg) {
+                    Ok(()) => self
+                        .driver
+                        .send_message(&msg)
+                        .await
+                        .map_err(RouterError::from),
+                    Err(e) => Err(e),
+                });
+            }
+        }
+        if self.state.take_restore_pending() {
+            result = result.and(self.restore().await);
+        }
+        result
+    }
+
+    /// Route messages until an error occurs, e.g. the profiles' sender was dropped
+    pub async fn run(&mut self) -> Result<(), RouterError> {
+        loop {
+            self.process().await?;
+        }
+    }
+
+    /// Teardown router and return driver
+    pub fn release(self) -> D {
+        self.driver
+    }
+
+    fn flush_backlogs(&self) -> Result<(), RouterError> {
+        let mut result = Ok(());
+        for (channel, handler) in self.channels.iter().enumerate() {
+            if let Some(handler) = handler {
+                result = result.and(self.state.flush(channel as u8, handler));
+            }
+        }
+        result
+    }
+
+    async fn handle_message(&self, msg: AntMessage<N>) -> Result<(), RouterError> {
+        match self.state.handle_message(&msg) {
+            Route::Channel(channel) => self.route_message(channel, msg).await,
+            Route::Broadcast => self.broadcast_message(msg).await,
+            Route::Consumed => Ok(()),
+        }
+    }
+
+    async fn route_message(&self, channel: u8, msg: AntMessage<N>) -> Result<(), RouterError> {
+        match self.channels.get(channel as usize) {
+            Some(Some(handler)) => self.deliver(channel, handler, msg).await,
+            // No channel to take it, e.g. the radio closing a removed channel, the rx message
+            // callback already saw it
+            _ => Ok(()),
+        }
+    }
+
+    async fn broadcast_message(&self, msg: AntMessage<N>) -> Result<(), RouterError> {
+        // A closed channel should not starve the rest
+        let mut result = Ok(());
+        for (channel, handler) in self.channels.iter().enumerate() {
+            if let Some(handler) = handler {
+                result = result.and(self.deliver(channel as u8, handler, msg.clone()).await);
+            }
+        }
+        result
+    }
+
+    // Blocking channels wait for room, the other policies behave as in the polled router
+    async fn deliver(
+        &self,
+        channel: u8,
+        handler: &T,
+        msg: AntMessage<N>,
+    ) -> Result<(), RouterError> {
+        if self.state.overflow_policy(channel) != Some(OverflowPolicy::Block) {
+            return self.state.deliver(channel, handler, msg);
+        }
+        // Held back under a previous policy, keep the channel's messages in order
+        while let Some(held) = self.state.take_held(channel) {
+            if let Err(e) = AsyncTxHandler::send(handler, held).await {
+                self.state.drop_message(channel);
+                return Err(e.into());
+            }
+        }
+        AsyncTxHandler::send(handler, msg).await.map_err(|e| {
+            self.state.drop_message(channel);
+            e.into()
+        })
+    }
+}
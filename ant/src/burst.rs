@@ -0,0 +1,664 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Burst transfer segmentation and reassembly
+//!
+//! A burst is sent as a run of packets whose [ChannelSequence] counts 0 for the first packet then
+//! loops 1, 2, 3, 1, ... with the high bit set on the last packet. [BurstSender] splits a buffer into
+//! packets and [BurstReceiver] puts them back together.
+//!
+//! Advanced bursts use the same sequencing with larger packets. [AdvancedBurstConfig] negotiates
+//! the packet size and features against what the radio and the peer support, then
+//! [AdvancedBurstSender] and [AdvancedBurstReceiver] stream [AdvancedBurstData] packets. Packets
+//! are padded to a multiple of 8 bytes and the receiver hands the padding on with the data,
+//! telling the two apart is left to the application protocol.
+
+use crate::messages::channel::MessageCode;
+use crate::messages::config::{
+    AdvancedBurstMaxPacketLength, ConfigureAdvancedBurst, SupportedFeatures,
+};
+use crate::messages::control::{RequestMessage, RequestableMessageId};
+use crate::messages::data::{AdvancedBurstData, BurstTransferData, ChannelSequence};
+use crate::messages::requested_response::AdvancedBurstCapabilities;
+use crate::messages::DEFAULT_PAYLOAD_CAPACITY;
+use arrayvec::ArrayVec;
+
+/// Size of the data in a [BurstTransferData] packet
+pub const BURST_PACKET_SIZE: usize = 8;
+
+/// Default transfer buffer size of [AdvancedBurstSender] and [AdvancedBurstReceiver]
+///
+/// Independent of the message capacity, raise it for larger transfers.
+pub const DEFAULT_ADVANCED_BURST_BUFFER_SIZE: usize = 512;
+
+const LAST_PACKET_FLAG: u8 = 0b100;
+const SEQUENCE_MASK: u8 = 0b011;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BurstError {
+    /// A transfer is already in progress
+    Busy,
+    /// Nothing to send
+    Empty,
+    /// Burst does not fit in the buffer
+    BufferFull,
+    /// Packet was received out of order, carries the expected and received sequence numbers
+    SequenceError { expected: u8, actual: u8 },
+    /// Radio reported the transfer failed
+    TransferFailed,
+    /// Radio or peer lacks advanced burst features marked as required
+    UnsupportedFeatures(SupportedFeatures),
+    /// Peer has advanced burst disabled
+    PeerDisabled,
+}
+
+// Sequence number of the `index`th packet of a burst
+fn sequence_number(index: usize, last: bool) -> u8 {
+    let sequence = match index {
+        0 => 0,
+        i => ((i - 1) % 3) as u8 + 1,
+    };
+    if last {
+        sequence | LAST_PACKET_FLAG
+    } else {
+        sequence
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SenderState {
+    Idle,
+    /// Waiting for a TX event to start
+    Pending,
+    Sending,
+    /// All packets handed off, waiting on the radio to report the result
+    Sent,
+}
+
+/// Splits a buffer of up to `N` bytes into burst packets
+#[derive(Clone, Debug)]
+pub struct BurstSender<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    channel: u8,
+    data: ArrayVec<u8, N>,
+    offset: usize,
+    index: usize,
+    state: SenderState,
+}
+
+impl<const N: usize> BurstSender<N> {
+    pub fn new(channel: u8) -> Self {
+        Self {
+            channel,
+            data: ArrayVec::new(),
+            offset: 0,
+            index: 0,
+            state: SenderState::Idle,
+        }
+    }
+
+    /// Queue `data` to be sent as a burst on the next TX event
+    pub fn start(&mut self, data: &[u8]) -> Result<(), BurstError> {
+        if self.state != SenderState::Idle {
+            return Err(BurstError::Busy);
+        }
+        if data.is_empty() {
+            return Err(BurstError::Empty);
+        }
+        self.data.clear();
+        self.data
+            .try_extend_from_slice(data)
+            .map_err(|_| BurstError::BufferFull)?;
+        self.offset = 0;
+        self.index = 0;
+        self.state = SenderState::Pending;
+        Ok(())
+    }
+
+    /// True if no transfer is queued or in flight
+    pub fn is_idle(&self) -> bool {
+        self.state == SenderState::Idle
+    }
+
+    /// Drop the current transfer
+    pub fn cancel(&mut self) {
+        self.state = SenderState::Idle;
+    }
+
+    /// Next chunk of at most `packet_size` bytes and its sequence
+    ///
+    /// The first packet is held until `tx_ready` so the burst starts on a TX event.
+    pub(crate) fn next_chunk(
+        &mut self,
+        packet_size: usize,
+        tx_ready: bool,
+    ) -> Option<(ChannelSequence, &[u8])> {
+        match self.state {
+            SenderState::Pending if tx_ready => self.state = SenderState::Sending,
+            SenderState::Sending => (),
+            _ => return None,
+        }
+        let end = (self.offset + packet_size).min(self.data.len());
+        let last = end == self.data.len();
+        let sequence = ChannelSequence::new(
+            sequence_number(self.index, last).into(),
+            self.channel.into(),
+        );
+        let chunk = &self.data[self.offset..end];
+        self.offset = end;
+        self.index += 1;
+        if last {
+            self.state = SenderState::Sent;
+        }
+        Some((sequence, chunk))
+    }
+
+    /// Next packet to send, zero padded to [BURST_PACKET_SIZE]
+    pub fn next_packet(&mut self, tx_ready: bool) -> Option<BurstTransferData> {
+        let (sequence, chunk) = self.next_chunk(BURST_PACKET_SIZE, tx_ready)?;
+        let mut data = [0; BURST_PACKET_SIZE];
+        data[..chunk.len()].copy_from_slice(chunk);
+        Some(BurstTransferData::new(sequence, data))
+    }
+
+    /// Track transfer events, returns the transfer result once it has finished
+    pub fn handle_event(&mut self, code: MessageCode) -> Option<Result<(), BurstError>> {
+        if matches!(self.state, SenderState::Idle | SenderState::Pending) {
+            return None;
+        }
+        let result = match code {
+            MessageCode::EventTransferTxCompleted => Ok(()),
+            MessageCode::EventTransferTxFailed => Err(BurstError::TransferFailed),
+            _ => return None,
+        };
+        self.state = SenderState::Idle;
+        Some(result)
+    }
+}
+
+/// Reassembles burst packets into a buffer of up to `N` bytes
+#[derive(Clone, Debug, Default)]
+pub struct BurstReceiver<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    data: ArrayVec<u8, N>,
+    /// Packets received in the current burst
+    index: usize,
+    complete: bool,
+}
+
+impl<const N: usize> BurstReceiver<N> {
+    pub fn new() -> Self {
+        Self {
+            data: ArrayVec::new(),
+            index: 0,
+            complete: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.data.clear();
+        self.index = 0;
+        self.complete = false;
+    }
+
+    /// Add a packet, returns the full burst once the last packet arrives
+    ///
+    /// A packet with sequence 0 always starts a new burst, discarding any partial one.
+    pub(crate) fn receive_chunk(
+        &mut self,
+        sequence: ChannelSequence,
+        data: &[u8],
+    ) -> Result<Option<&[u8]>, BurstError> {
+        if self.complete {
+            self.reset();
+        }
+        let actual: u8 = sequence.sequence_number.into();
+        let last = actual & LAST_PACKET_FLAG != 0;
+        if actual & SEQUENCE_MASK == 0 {
+            self.reset();
+        } else {
+            let expected = sequence_number(self.index, false);
+            if self.index == 0 || actual & SEQUENCE_MASK != expected {
+                self.reset();
+                return Err(BurstError::SequenceError { expected, actual });
+            }
+        }
+        if self.data.try_extend_from_slice(data).is_err() {
+            self.reset();
+            return Err(BurstError::BufferFull);
+        }
+        self.index += 1;
+        if last {
+            self.complete = true;
+            return Ok(Some(&self.data));
+        }
+        Ok(None)
+    }
+
+    /// Add a packet, returns the full burst once the last packet arrives
+    pub fn receive(&mut self, msg: &BurstTransferData) -> Result<Option<&[u8]>, BurstError> {
+        self.receive_chunk(msg.payload.channel_sequence, &msg.payload.data)
+    }
+
+    /// Track transfer events, a failed transfer drops the partial burst
+    pub fn handle_event(&mut self, code: MessageCode) -> Result<(), BurstError> {
+        if code != MessageCode::EventTransferRxFailed {
+            return Ok(());
+        }
+        let in_progress = self.index != 0 && !self.complete;
+        self.reset();
+        if in_progress {
+            return Err(BurstError::TransferFailed);
+        }
+        Ok(())
+    }
+}
+
+/// Desired advanced burst settings, reduced to what the radio supports by
+/// [AdvancedBurstConfig::negotiate]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AdvancedBurstConfig {
+    pub max_packet_length: AdvancedBurstMaxPacketLength,
+    /// Features the transfer cannot run without
+    pub required_features: SupportedFeatures,
+    /// Features used only if supported
+    pub optional_features: SupportedFeatures,
+    pub stall_count: Option<u16>,
+    pub retry_count_extension: Option<u8>,
+}
+
+impl AdvancedBurstConfig {
+    pub fn new(max_packet_length: AdvancedBurstMaxPacketLength) -> Self {
+        Self {
+            max_packet_length,
+            ..Self::default()
+        }
+    }
+
+    /// Request for the radio's [AdvancedBurstCapabilities]
+    pub fn request_capabilities() -> RequestMessage {
+        RequestMessage::new(0, RequestableMessageId::AdvancedBurstCapabilities, None)
+    }
+
+    /// Request for the radio's current advanced burst configuration
+    pub fn request_current_configuration() -> RequestMessage {
+        RequestMessage::new(1, RequestableMessageId::AdvancedBurstCapabilities, None)
+    }
+
+    /// Build the configuration to send given the radio capabilities and the peer's configuration
+    ///
+    /// The packet length is capped to the smallest of the requested, radio and peer maximums and
+    /// optional features are dropped unless both the radio and the peer support them. Features the
+    /// peer requires are required locally too. Fails if a required feature is unsupported on
+    /// either end. Without a `peer` only the radio is negotiated against.
+    pub fn negotiate(
+        &self,
+        capabilities: &AdvancedBurstCapabilities,
+        peer: Option<&ConfigureAdvancedBurst>,
+    ) -> Result<ConfigureAdvancedBurst, BurstError> {
+        let radio_hop = capabilities
+            .supported_features
+            .adv_burst_frequency_hop_enabled;
+        let mut max_packet_length = self.max_packet_length;
+        if capabilities.supported_max_packed_length.bytes() < max_packet_length.bytes() {
+            max_packet_length = capabilities.supported_max_packed_length;
+        }
+        let (peer_hop, peer_requires_hop) = match peer {
+            Some(peer) if !peer.data.enable => return Err(BurstError::PeerDisabled),
+            Some(peer) => {
+                if peer.data.max_packet_length.bytes() < max_packet_length.bytes() {
+                    max_packet_length = peer.data.max_packet_length;
+                }
+                let required = peer.data.required_features.adv_burst_frequency_hop_enabled;
+                (
+                    required || peer.data.optional_features.adv_burst_frequency_hop_enabled,
+                    required,
+                )
+            }
+            None => (true, false),
+        };
+        if peer_requires_hop && !radio_hop {
+            return Err(BurstError::UnsupportedFeatures(SupportedFeatures::new(
+                true,
+            )));
+        }
+        if self.required_features.adv_burst_frequency_hop_enabled && !(radio_hop && peer_hop) {
+            return Err(BurstError::UnsupportedFeatures(self.required_features));
+        }
+        let required_hop =
+            self.required_features.adv_burst_frequency_hop_enabled || peer_requires_hop;
+        let optional_hop = !required_hop
+            && self.optional_features.adv_burst_frequency_hop_enabled
+            && radio_hop
+            && peer_hop;
+        Ok(ConfigureAdvancedBurst::new(
+            true,
+            max_packet_length,
+            SupportedFeatures::new(required_hop),
+            SupportedFeatures::new(optional_hop),
+            self.stall_count,
+            self.retry_count_extension,
+        ))
+    }
+}
+
+/// Splits a buffer of up to `BUF` bytes into advanced burst packets
+///
+/// Packets are sized to the negotiated max packet length and the final packet is zero padded to
+/// a multiple of 8 bytes. `N` is the capacity of the
+/// packets, it only needs to fit the packet length.
+#[derive(Clone, Debug)]
+pub struct AdvancedBurstSender<
+    const BUF: usize = DEFAULT_ADVANCED_BURST_BUFFER_SIZE,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+> {
+    sender: BurstSender<BUF>,
+    packet_length: AdvancedBurstMaxPacketLength,
+}
+
+impl<const BUF: usize, const N: usize> AdvancedBurstSender<BUF, N> {
+    pub fn new(channel: u8, packet_length: AdvancedBurstMaxPacketLength) -> Self {
+        Self {
+            sender: BurstSender::new(channel),
+            packet_length,
+        }
+    }
+
+    /// Use the packet length of a negotiated configuration for following transfers
+    pub fn set_packet_length(&mut self, packet_length: AdvancedBurstMaxPacketLength) {
+        self.packet_length = packet_length;
+    }
+
+    /// Queue `data` to be sent on the next TX event
+    pub fn start(&mut self, data: &[u8]) -> Result<(), BurstError> {
+        if self.packet_length.bytes() > N {
+            return Err(BurstError::BufferFull);
+        }
+        self.sender.start(data)
+    }
+
+    /// True if no transfer is queued or in flight
+    pub fn is_idle(&self) -> bool {
+        self.sender.is_idle()
+    }
+
+    /// Drop the current transfer
+    pub fn cancel(&mut self) {
+        self.sender.cancel()
+    }
+
+    /// Next packet to send
+    pub fn next_packet(&mut self, tx_ready: bool) -> Option<AdvancedBurstData<N>> {
+        let (sequence, chunk) = self
+            .sender
+            .next_chunk(self.packet_length.bytes(), tx_ready)?;
+        let mut data: ArrayVec<u8, N> = chunk.iter().copied().collect();
+        while data.len() % BURST_PACKET_SIZE != 0 {
+            data.push(0);
+        }
+        Some(AdvancedBurstData::new(sequence, data))
+    }
+
+    /// Track transfer events, returns the transfer result once it has finished
+    pub fn handle_event(&mut self, code: MessageCode) -> Option<Result<(), BurstError>> {
+        self.sender.handle_event(code)
+    }
+}
+
+/// Reassembles advanced burst packets into a buffer of up to `BUF` bytes
+///
+/// The transfer is returned as received, including the padding of the final packet.
+#[derive(Clone, Debug, Default)]
+pub struct AdvancedBurstReceiver<const BUF: usize = DEFAULT_ADVANCED_BURST_BUFFER_SIZE> {
+    receiver: BurstReceiver<BUF>,
+}
+
+impl<const BUF: usize> AdvancedBurstReceiver<BUF> {
+    pub fn new() -> Self {
+        Self {
+            receiver: BurstReceiver::new(),
+        }
+    }
+
+    /// Add a packet, returns the full transfer once the last packet arrives
+    pub fn receive<const P: usize>(
+        &mut self,
+        msg: &AdvancedBurstData<P>,
+    ) -> Result<Option<&[u8]>, BurstError> {
+        self.receiver.receive_chunk(msg.channel_sequence, &msg.data)
+    }
+
+    /// Track transfer events, a failed transfer drops the partial data
+    pub fn handle_event(&mut self, code: MessageCode) -> Result<(), BurstError> {
+        self.receiver.handle_event(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(msg: &BurstTransferData) -> u8 {
+        msg.payload.channel_sequence.sequence_number.into()
+    }
+
+    #[test]
+    fn sequence_rollover() {
+        let mut sender = BurstSender::<64>::new(3);
+        let data: ArrayVec<u8, 64> = (0..60).collect();
+        sender.start(&data).unwrap();
+        assert_eq!(sender.next_packet(false), None);
+
+        let mut packets: ArrayVec<BurstTransferData, 8> = ArrayVec::new();
+        let mut tx_ready = true;
+        while let Some(packet) = sender.next_packet(tx_ready) {
+            tx_ready = false;
+            assert_eq!(packet.payload.channel_sequence.channel_number, 3.into());
+            packets.push(packet);
+        }
+        let sequences: ArrayVec<u8, 8> = packets.iter().map(sequence).collect();
+        assert_eq!(sequences.as_slice(), &[0, 1, 2, 3, 1, 2, 3, 5]);
+        assert_eq!(packets[7].payload.data, [56, 57, 58, 59, 0, 0, 0, 0]);
+
+        assert_eq!(sender.start(&data), Err(BurstError::Busy));
+        assert_eq!(sender.handle_event(MessageCode::EventTx), None);
+        assert_eq!(
+            sender.handle_event(MessageCode::EventTransferTxCompleted),
+            Some(Ok(()))
+        );
+        assert!(sender.is_idle());
+    }
+
+    #[test]
+    fn single_packet() {
+        let mut sender = BurstSender::<64>::new(0);
+        sender.start(&[1, 2]).unwrap();
+        let packet = sender.next_packet(true).unwrap();
+        assert_eq!(sequence(&packet), LAST_PACKET_FLAG);
+        assert_eq!(sender.next_packet(true), None);
+        assert_eq!(
+            sender.handle_event(MessageCode::EventTransferTxFailed),
+            Some(Err(BurstError::TransferFailed))
+        );
+        assert_eq!(
+            BurstSender::<4>::new(0).start(&[0; 5]),
+            Err(BurstError::BufferFull)
+        );
+        assert_eq!(BurstSender::<4>::new(0).start(&[]), Err(BurstError::Empty));
+    }
+
+    #[test]
+    fn reassembly() {
+        let mut sender = BurstSender::<64>::new(1);
+        let data: ArrayVec<u8, 64> = (0..40).collect();
+        sender.start(&data).unwrap();
+        let mut receiver = BurstReceiver::<64>::new();
+        let mut result = None;
+        while let Some(packet) = sender.next_packet(true) {
+            if let Some(data) = receiver.receive(&packet).unwrap() {
+                result = Some(ArrayVec::<u8, 64>::try_from(data).unwrap());
+            }
+        }
+        assert_eq!(result.unwrap(), data);
+    }
+
+    #[test]
+    fn reassembly_errors() {
+        let mut receiver = BurstReceiver::<16>::new();
+        let packet =
+            |seq: u8| BurstTransferData::new(ChannelSequence::new(seq.into(), 0.into()), [0; 8]);
+
+        // Continuation without a start
+        assert_eq!(
+            receiver.receive(&packet(1)),
+            Err(BurstError::SequenceError {
+                expected: 0,
+                actual: 1
+            })
+        );
+        // Skipped packet
+        assert_eq!(receiver.receive(&packet(0)), Ok(None));
+        assert_eq!(
+            receiver.receive(&packet(2)),
+            Err(BurstError::SequenceError {
+                expected: 1,
+                actual: 2
+            })
+        );
+        // Overflow
+        assert_eq!(receiver.receive(&packet(0)), Ok(None));
+        assert_eq!(receiver.receive(&packet(1)), Ok(None));
+        assert_eq!(receiver.receive(&packet(2)), Err(BurstError::BufferFull));
+        // Radio failure
+        assert_eq!(receiver.receive(&packet(0)), Ok(None));
+        assert_eq!(
+            receiver.handle_event(MessageCode::EventTransferRxFailed),
+            Err(BurstError::TransferFailed)
+        );
+        assert_eq!(
+            receiver.handle_event(MessageCode::EventTransferRxFailed),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn advanced_burst_negotiation() {
+        use packed_struct::PackedStruct;
+
+        let caps = AdvancedBurstCapabilities::unpack(&[0, 2, 0, 0, 0]).unwrap();
+        let mut config = AdvancedBurstConfig::new(AdvancedBurstMaxPacketLength::Max24Byte);
+        config.optional_features = SupportedFeatures::new(true);
+        let msg = config.negotiate(&caps, None).unwrap();
+        assert!(msg.data.enable);
+        assert_eq!(
+            msg.data.max_packet_length,
+            AdvancedBurstMaxPacketLength::Max16Byte
+        );
+        assert_eq!(msg.data.optional_features, SupportedFeatures::new(false));
+
+        config.required_features = SupportedFeatures::new(true);
+        assert_eq!(
+            config.negotiate(&caps, None),
+            Err(BurstError::UnsupportedFeatures(SupportedFeatures::new(
+                true
+            )))
+        );
+
+        let config = AdvancedBurstConfig::new(AdvancedBurstMaxPacketLength::Max8Byte);
+        let msg = config.negotiate(&caps, None).unwrap();
+        assert_eq!(
+            msg.data.max_packet_length,
+            AdvancedBurstMaxPacketLength::Max8Byte
+        );
+    }
+
+    #[test]
+    fn advanced_burst_peer_negotiation() {
+        use packed_struct::PackedStruct;
+
+        let caps = AdvancedBurstCapabilities::unpack(&[0, 3, 1, 0, 0]).unwrap();
+        let mut config = AdvancedBurstConfig::new(AdvancedBurstMaxPacketLength::Max24Byte);
+        config.optional_features = SupportedFeatures::new(true);
+        let none = SupportedFeatures::new(false);
+        let hop = SupportedFeatures::new(true);
+
+        // Peer caps the packet length and does not hop
+        let peer = ConfigureAdvancedBurst::new(
+            true,
+            AdvancedBurstMaxPacketLength::Max16Byte,
+            none,
+            none,
+            None,
+            None,
+        );
+        let msg = config.negotiate(&caps, Some(&peer)).unwrap();
+        assert_eq!(
+            msg.data.max_packet_length,
+            AdvancedBurstMaxPacketLength::Max16Byte
+        );
+        assert_eq!(msg.data.optional_features, none);
+
+        // Peer requires hopping, so it is required here too
+        let peer = ConfigureAdvancedBurst::new(
+            true,
+            AdvancedBurstMaxPacketLength::Max24Byte,
+            hop,
+            none,
+            None,
+            None,
+        );
+        let msg = config.negotiate(&caps, Some(&peer)).unwrap();
+        assert_eq!(msg.data.required_features, hop);
+        assert_eq!(msg.data.optional_features, none);
+        let no_hop = AdvancedBurstCapabilities::unpack(&[0, 3, 0, 0, 0]).unwrap();
+        assert_eq!(
+            config.negotiate(&no_hop, Some(&peer)),
+            Err(BurstError::UnsupportedFeatures(hop))
+        );
+
+        let disabled = ConfigureAdvancedBurst::default();
+        assert_eq!(
+            config.negotiate(&caps, Some(&disabled)),
+            Err(BurstError::PeerDisabled)
+        );
+    }
+
+    #[test]
+    fn advanced_burst_transfer() {
+        // Transfer larger than the 24 byte packets carrying it
+        let mut sender =
+            AdvancedBurstSender::<128, 24>::new(2, AdvancedBurstMaxPacketLength::Max24Byte);
+        let data: ArrayVec<u8, 100> = (0..100).collect();
+        sender.start(&data).unwrap();
+        let mut receiver = AdvancedBurstReceiver::<128>::new();
+        let mut lengths: ArrayVec<usize, 8> = ArrayVec::new();
+        let mut result = None;
+        while let Some(packet) = sender.next_packet(true) {
+            lengths.push(packet.data.len());
+            if let Some(data) = receiver.receive(&packet).unwrap() {
+                result = Some(ArrayVec::<u8, 128>::try_from(data).unwrap());
+            }
+        }
+        assert_eq!(lengths.as_slice(), &[24, 24, 24, 24, 8]);
+        // Raw payload on the air, the padding is handed on with it
+        let mut padded: ArrayVec<u8, 128> = data.iter().copied().collect();
+        padded.extend([0; 4]);
+        assert_eq!(result.unwrap(), padded);
+        assert_eq!(
+            sender.handle_event(MessageCode::EventTransferTxCompleted),
+            Some(Ok(()))
+        );
+
+        // Packets must fit the message capacity and the transfer the buffer
+        let mut sender =
+            AdvancedBurstSender::<64, 16>::new(2, AdvancedBurstMaxPacketLength::Max24Byte);
+        assert_eq!(sender.start(&[1]), Err(BurstError::BufferFull));
+        let mut sender =
+            AdvancedBurstSender::<16, 64>::new(2, AdvancedBurstMaxPacketLength::Max8Byte);
+        assert_eq!(sender.start(&[1; 17]), Err(BurstError::BufferFull));
+        assert_eq!(sender.start(&[1; 16]), Ok(()));
+    }
+}
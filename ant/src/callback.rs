@@ -0,0 +1,94 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! User callback storage
+//!
+//! Callbacks are `FnMut` closures so they can capture state. They are held in a [Callback], which
+//! has the same type with or without the `alloc` feature. [Callback::new] borrows the closure,
+//! e.g. `Some(Callback::new(&mut closure))`, which then must outlive the profile or router holding
+//! it. With `alloc`, `Callback::boxed` takes ownership instead, e.g.
+//! `Some(Callback::boxed(Box::new(|x| ...)))`.
+
+use crate::channel::LifecycleEvent;
+use crate::messages::{AntMessage, TxMessageChannelConfig};
+use core::ops::{Deref, DerefMut};
+
+/// A borrowed or, with the `alloc` feature, owned callback
+pub struct Callback<'a, F: ?Sized> {
+    inner: Inner<'a, F>,
+}
+
+enum Inner<'a, F: ?Sized> {
+    Borrowed(&'a mut F),
+    #[cfg(feature = "alloc")]
+    Owned(alloc::boxed::Box<F>),
+}
+
+impl<'a, F: ?Sized> Callback<'a, F> {
+    /// Borrow `f` for as long as the callback is installed
+    pub fn new(f: &'a mut F) -> Self {
+        Self {
+            inner: Inner::Borrowed(f),
+        }
+    }
+
+    /// Take ownership of `f`
+    #[cfg(feature = "alloc")]
+    pub fn boxed(f: alloc::boxed::Box<F>) -> Self {
+        Self {
+            inner: Inner::Owned(f),
+        }
+    }
+}
+
+impl<F: ?Sized> Deref for Callback<'_, F> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        match &self.inner {
+            Inner::Borrowed(f) => f,
+            #[cfg(feature = "alloc")]
+            Inner::Owned(f) => f,
+        }
+    }
+}
+
+impl<F: ?Sized> DerefMut for Callback<'_, F> {
+    fn deref_mut(&mut self) -> &mut F {
+        match &mut self.inner {
+            Inner::Borrowed(f) => f,
+            #[cfg(feature = "alloc")]
+            Inner::Owned(f) => f,
+        }
+    }
+}
+
+/// Declare an alias of [Callback] for a closure signature
+macro_rules! callback_type {
+    ($(#[$meta:meta])* $vis:vis $name:ident<$lt:lifetime $(, const $n:ident: usize)?> = $($sig:tt)+) => {
+        $(#[$meta])*
+        $vis type $name<$lt $(, const $n: usize)?> = $crate::callback::Callback<$lt, dyn $($sig)+ + $lt>;
+    };
+}
+
+pub(crate) use callback_type;
+
+callback_type!(
+    /// Observes every message received
+    pub RxMessageCallback<'a, const N: usize> = FnMut(&AntMessage<N>)
+);
+
+callback_type!(
+    /// Polled every cycle for channel specific config messages to send
+    pub TxMessageCallback<'a> = FnMut() -> Option<TxMessageChannelConfig>
+);
+
+callback_type!(
+    /// Observes changes in a channel's link
+    pub LifecycleCallback<'a> = FnMut(LifecycleEvent)
+);
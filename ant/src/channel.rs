@@ -7,6 +7,8 @@
 // except according to those terms.
 
 use const_utils::u64::min;
+#[cfg(feature = "async")]
+use core::future::Future;
 use core::time::Duration;
 
 /// Helper to convert durations to search timeouts.
@@ -16,6 +18,28 @@ pub const fn duration_to_search_timeout(t: Duration) -> u8 {
     min((t.as_secs() * 10) / (25), 255) as u8
 }
 
+/// Changes in a channel's link, in the order the radio reports them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LifecycleEvent {
+    /// Channel opened and is looking for its peer
+    Searching,
+    /// First message exchanged with the peer since opening
+    Tracking,
+    /// Too many messages missed, the channel went back to searching
+    SignalLost,
+    /// Peer found again after [LifecycleEvent::SignalLost]
+    Reacquired,
+    /// Search gave up, the channel will close
+    SearchTimeout,
+    Closed,
+    /// Another channel was scheduled in the same slot and this one was skipped
+    Collision,
+    /// Acknowledged or burst transfer was not acknowledged by the peer
+    TxFailed,
+    /// Paired device was not found, the channel reopened searching for any device
+    WildcardFallback,
+}
+
 #[derive(Clone, Debug)]
 pub enum RxError {
     Empty,
@@ -30,6 +54,23 @@ pub enum TxError {
     UnknownError,
 }
 
+/// What to do with a message for a channel whose queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowPolicy {
+    /// Hold the message back, discarding the oldest held message once the backlog is full
+    DropOldest,
+    /// Discard the message that did not fit
+    DropNewest,
+    /// Hold the message back and stop reading from the radio until the channel catches up
+    ///
+    /// Nothing is discarded, the radio's own buffer fills instead and the radio may drop
+    /// messages of its own once it is full.
+    Block,
+    /// Discard the message and report [TxError::Full]
+    #[default]
+    Error,
+}
+
 #[derive(Clone, Debug)]
 pub enum ChanError {
     Rx(RxError),
@@ -49,15 +90,28 @@ impl From<TxError> for ChanError {
 }
 
 pub trait TxHandler<T> {
-    // TODO async versions
     fn try_send(&self, msg: T) -> Result<(), TxError>;
 }
 
 pub trait RxHandler<T> {
-    // TODO async versions
     fn try_recv(&self) -> Result<T, RxError>;
 }
 
+/// Async version of [TxHandler], waits for room instead of failing with [TxError::Full]
+#[cfg(feature = "async")]
+pub trait AsyncTxHandler<T> {
+    fn send(&self, msg: T) -> impl Future<Output = Result<(), TxError>>;
+}
+
+/// Async version of [RxHandler], waits for a message instead of failing with [RxError::Empty]
+///
+/// The returned future must be cancel safe, a message may only be taken from the channel when
+/// the future completes.
+#[cfg(feature = "async")]
+pub trait AsyncRxHandler<T> {
+    fn recv(&mut self) -> impl Future<Output = Result<T, RxError>>;
+}
+
 #[cfg(feature = "std")]
 pub mod mpsc {
     use super::*;
@@ -109,3 +163,169 @@ pub mod mpsc {
         }
     }
 }
+
+#[cfg(feature = "tokio")]
+pub mod tokio_mpsc {
+    use super::*;
+    use std::cell::RefCell;
+    use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+    use tokio::sync::mpsc::{Receiver, Sender};
+
+    /// Abstraction implementation for tokio::sync::mpsc::Sender
+    ///
+    /// Usable from both sync and async code, e.g. the sync side of a profile talking to an
+    /// [crate::async_router::AsyncRouter].
+    pub struct TxChannel<T> {
+        pub sender: Sender<T>,
+    }
+
+    /// Abstraction implementation for tokio::sync::mpsc::Receiver
+    ///
+    /// Usable from both sync and async code.
+    pub struct RxChannel<T> {
+        receiver: RefCell<Receiver<T>>,
+    }
+
+    impl<T> RxChannel<T> {
+        pub fn new(receiver: Receiver<T>) -> Self {
+            Self {
+                receiver: RefCell::new(receiver),
+            }
+        }
+    }
+
+    impl<T> TxHandler<T> for TxChannel<T> {
+        fn try_send(&self, msg: T) -> Result<(), TxError> {
+            match self.sender.try_send(msg) {
+                Ok(_) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(TxError::Full),
+                Err(TrySendError::Closed(_)) => Err(TxError::Closed),
+            }
+        }
+    }
+
+    impl<T> AsyncTxHandler<T> for TxChannel<T> {
+        async fn send(&self, msg: T) -> Result<(), TxError> {
+            self.sender.send(msg).await.map_err(|_| TxError::Closed)
+        }
+    }
+
+    impl<T> RxHandler<T> for RxChannel<T> {
+        fn try_recv(&self) -> Result<T, RxError> {
+            match self.receiver.borrow_mut().try_recv() {
+                Ok(m) => Ok(m),
+                Err(TryRecvError::Empty) => Err(RxError::Empty),
+                Err(TryRecvError::Disconnected) => Err(RxError::Closed),
+            }
+        }
+    }
+
+    impl<T> AsyncRxHandler<T> for RxChannel<T> {
+        async fn recv(&mut self) -> Result<T, RxError> {
+            self.receiver.get_mut().recv().await.ok_or(RxError::Closed)
+        }
+    }
+}
+
+#[cfg(feature = "embassy")]
+pub mod embassy {
+    use super::*;
+    use embassy_sync::blocking_mutex::raw::RawMutex;
+    use embassy_sync::channel::{Receiver, Sender, TrySendError};
+
+    /// Abstraction implementation for embassy_sync::channel::Sender
+    ///
+    /// Embassy channels never close, sends only fail when the channel is full.
+    pub struct TxChannel<'a, M: RawMutex, T, const C: usize> {
+        pub sender: Sender<'a, M, T, C>,
+    }
+
+    /// Abstraction implementation for embassy_sync::channel::Receiver
+    pub struct RxChannel<'a, M: RawMutex, T, const C: usize> {
+        pub receiver: Receiver<'a, M, T, C>,
+    }
+
+    impl<M: RawMutex, T, const C: usize> TxHandler<T> for TxChannel<'_, M, T, C> {
+        fn try_send(&self, msg: T) -> Result<(), TxError> {
+            match self.sender.try_send(msg) {
+                Ok(_) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(TxError::Full),
+            }
+        }
+    }
+
+    impl<M: RawMutex, T, const C: usize> AsyncTxHandler<T> for TxChannel<'_, M, T, C> {
+        async fn send(&self, msg: T) -> Result<(), TxError> {
+            self.sender.send(msg).await;
+            Ok(())
+        }
+    }
+
+    impl<M: RawMutex, T, const C: usize> RxHandler<T> for RxChannel<'_, M, T, C> {
+        fn try_recv(&self) -> Result<T, RxError> {
+            self.receiver.try_receive().map_err(|_| RxError::Empty)
+        }
+    }
+
+    impl<M: RawMutex, T, const C: usize> AsyncRxHandler<T> for RxChannel<'_, M, T, C> {
+        async fn recv(&mut self) -> Result<T, RxError> {
+            Ok(self.receiver.receive().await)
+        }
+    }
+}
+
+#[cfg(feature = "thingbuf")]
+pub mod thingbuf_mpsc {
+    use super::*;
+    use thingbuf::mpsc::errors::{TryRecvError, TrySendError};
+    use thingbuf::mpsc::{StaticReceiver, StaticSender};
+
+    /// Abstraction implementation for thingbuf::mpsc::StaticSender
+    ///
+    /// Static channels need neither std nor alloc, the queue lives in a
+    /// thingbuf::mpsc::StaticChannel declared as a `static`.
+    pub struct TxChannel<T: 'static> {
+        pub sender: StaticSender<T>,
+    }
+
+    /// Abstraction implementation for thingbuf::mpsc::StaticReceiver
+    pub struct RxChannel<T: 'static> {
+        pub receiver: StaticReceiver<T>,
+    }
+
+    impl<T: Default + Clone> TxHandler<T> for TxChannel<T> {
+        fn try_send(&self, msg: T) -> Result<(), TxError> {
+            match self.sender.try_send(msg) {
+                Ok(_) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(TxError::Full),
+                Err(TrySendError::Closed(_)) => Err(TxError::Closed),
+                Err(_) => Err(TxError::UnknownError),
+            }
+        }
+    }
+
+    impl<T: Default + Clone> RxHandler<T> for RxChannel<T> {
+        fn try_recv(&self) -> Result<T, RxError> {
+            match self.receiver.try_recv() {
+                Ok(m) => Ok(m),
+                Err(TryRecvError::Empty) => Err(RxError::Empty),
+                Err(TryRecvError::Closed) => Err(RxError::Closed),
+                Err(_) => Err(RxError::UnknownError),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl<T: Default + Clone> AsyncTxHandler<T> for TxChannel<T> {
+        async fn send(&self, msg: T) -> Result<(), TxError> {
+            self.sender.send(msg).await.map_err(|_| TxError::Closed)
+        }
+    }
+
+    #[cfg(feature = "async")]
+    impl<T: Default + Clone> AsyncRxHandler<T> for RxChannel<T> {
+        async fn recv(&mut self) -> Result<T, RxError> {
+            self.receiver.recv().await.ok_or(RxError::Closed)
+        }
+    }
+}
@@ -6,15 +6,33 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[cfg(feature = "async")]
+mod polling;
 mod serial;
 #[cfg(feature = "usb")]
 mod usb;
 
+#[cfg(feature = "async")]
+pub use polling::*;
 pub use serial::*;
 #[cfg(feature = "usb")]
 pub use usb::*;
 
 use crate::messages::channel::{ChannelEvent, ChannelResponse};
+use crate::messages::config::{
+    AddChannelIdToList, AssignChannel, ChannelId as TxChannelId, ChannelPeriod, ChannelRfFrequency,
+    ChannelSearchPriority, ChannelSearchSharing, ConfigIdList, ConfigureAdvancedBurst,
+    ConfigureEventBuffer, ConfigureEventFilter, ConfigureSelectiveDataUpdates, CrystalEnable,
+    EnableExtRxMessages, EnableLed, EnableSingleChannelEncryption, FrequencyAgility,
+    HighDutySearch, LibConfig, LoadEncryptionKeyFromNvm, LowPrioritySearchTimeout, ProximitySearch,
+    SearchTimeout, SearchWaveform, SerialNumberSetChannelId, Set128BitNetworkKey,
+    SetChannelTransmitPower, SetEncryptionInfoEncryptionId, SetEncryptionInfoRandomSeed,
+    SetEncryptionInfoUserInformationString, SetEncryptionKey, SetNetworkKey,
+    SetSelectiveDataUpdateMask, StoreEncryptionKeyInNvm, TransmitPower, UnAssignChannel,
+};
+use crate::messages::control::{
+    CloseChannel, OpenChannel, OpenRxScanMode, RequestMessage, ResetSystem, SleepMessage,
+};
 use crate::messages::data::{
     AcknowledgedData, AdvancedBurstData, BroadcastData, BurstTransferData,
 };
@@ -24,25 +42,66 @@ use crate::messages::requested_response::{
     ChannelId, ChannelStatus, EncryptionModeParameters, EventBufferConfiguration, EventFilter,
     SelectiveDataUpdateMaskSetting, SerialNumber, UserNvm,
 };
+use crate::messages::test_mode::{CwInit, CwTest};
 use crate::messages::{
     AntMessage, RxMessage, RxMessageHeader, RxMessageId, RxSyncByte, TransmitableMessage,
-    TxMessageHeader, TxSyncByte, MAX_MESSAGE_DATA_SIZE,
+    TxMessage, TxMessageHeader, TxMessageId, TxSyncByte, DEFAULT_PAYLOAD_CAPACITY,
 };
 
-use arrayvec::{ArrayVec, CapacityError};
+use arrayvec::CapacityError;
+#[cfg(feature = "async")]
+use core::future::Future;
 use embedded_hal::digital::PinState;
 use packed_struct::prelude::{PackedStructSlice, PackingError};
 use std::array::TryFromSliceError;
 use std::cmp;
 
-pub trait Driver<E> {
-    fn get_message(&mut self) -> Result<Option<AntMessage>, DriverError<E>>;
+/// Transport to an ANT radio
+///
+/// `N` is the capacity of variable length fields in received messages, see
+/// [DEFAULT_PAYLOAD_CAPACITY].
+pub trait Driver<E, const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    fn get_message(&mut self) -> Result<Option<AntMessage<N>>, DriverError<E>>;
     fn send_message(&mut self, msg: &dyn TransmitableMessage) -> Result<(), DriverError<E>>;
 }
 
-// TODO finalize
-const ANT_MESSAGE_SIZE: usize = MAX_MESSAGE_DATA_SIZE;
-const CHECKSUM_SIZE: usize = 1;
+/// Async transport to an ANT radio
+///
+/// Unlike [Driver::get_message] this waits for a message to arrive. The future returned by
+/// [AsyncDriver::get_message] must be cancel safe, it is dropped whenever something else becomes
+/// ready first. Any [Driver] can be used through [PollingDriver].
+#[cfg(feature = "async")]
+pub trait AsyncDriver<E, const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    fn get_message(&mut self) -> impl Future<Output = Result<AntMessage<N>, DriverError<E>>>;
+    fn send_message(
+        &mut self,
+        msg: &dyn TransmitableMessage,
+    ) -> impl Future<Output = Result<(), DriverError<E>>>;
+}
+
+pub(crate) const CHECKSUM_SIZE: usize = 1;
+
+// Largest payload without variable length fields, an encryption negotiation event carrying the
+// encryption ID and user information string
+const MAX_FIXED_PAYLOAD_SIZE: usize = 26;
+
+/// Buffer size needed to hold any frame whose variable length fields are at most `capacity` bytes
+///
+/// Never smaller than the largest fixed size frame, e.g. encryption setup and events, so a small
+/// `capacity` only limits variable length messages.
+pub const fn frame_buffer_size(capacity: usize) -> usize {
+    // Variable length fields are preceded by a single sequence or header byte
+    let variable = 1 + capacity;
+    let payload = if variable > MAX_FIXED_PAYLOAD_SIZE {
+        variable
+    } else {
+        MAX_FIXED_PAYLOAD_SIZE
+    };
+    HEADER_SIZE + payload + CHECKSUM_SIZE
+}
+
+/// Frame buffer size matching [DEFAULT_PAYLOAD_CAPACITY]
+pub const DEFAULT_FRAME_BUFFER_SIZE: usize = frame_buffer_size(DEFAULT_PAYLOAD_CAPACITY);
 
 #[derive(Debug)]
 pub enum DriverError<E> {
@@ -99,7 +158,10 @@ fn align_buffer(buf: &[u8]) -> usize {
     0
 }
 
-fn update_buffer<E>(msg: &Result<Option<AntMessage>, DriverError<E>>, buf: &[u8]) -> usize {
+fn update_buffer<E, const N: usize>(
+    msg: &Result<Option<AntMessage<N>>, DriverError<E>>,
+    buf: &[u8],
+) -> usize {
     if msg.is_err() {
         // It was a corrupted message, skip first byte to resposition buf and move on
         return 1;
@@ -120,7 +182,10 @@ fn create_packed_message<'a>(
     buf: &'a mut [u8],
     msg: &dyn TransmitableMessage,
 ) -> Result<&'a [u8], PackingError> {
-    let msg_len = msg.serialize_message(&mut buf[HEADER_SIZE..])?;
+    let msg_len = msg.serialize_message(
+        buf.get_mut(HEADER_SIZE..)
+            .ok_or(PackingError::BufferTooSmall)?,
+    )?;
     let header = TxMessageHeader {
         sync: TxSyncByte::Value,
         msg_length: msg_len as u8,
@@ -129,17 +194,35 @@ fn create_packed_message<'a>(
 
     let padded_len = msg_len + HEADER_SIZE;
     header.pack_to_slice(&mut buf[..HEADER_SIZE])?;
-    buf[padded_len] = calculate_checksum(&buf[..padded_len]);
+    *buf.get_mut(padded_len)
+        .ok_or(PackingError::BufferTooSmall)? = calculate_checksum(&buf[..padded_len]);
 
     Ok(&buf[..padded_len + 1])
 }
 
-const HEADER_SIZE: usize = 3;
+pub(crate) const HEADER_SIZE: usize = 3;
+
+/// Validate the length and checksum of a frame, returns the message body and its checksum
+fn split_frame<E>(buf: &[u8], msg_length: u8) -> Result<Option<(&[u8], u8)>, DriverError<E>> {
+    let body_end = (msg_length as usize) + HEADER_SIZE;
 
-type Buffer = ArrayVec<u8, ANT_MESSAGE_SIZE>;
+    if buf.len() < body_end + CHECKSUM_SIZE {
+        return Ok(None);
+    }
+
+    let expected_checksum = calculate_checksum(&buf[..body_end]);
+    let checksum = buf[body_end];
+    if expected_checksum != checksum {
+        return Err(DriverError::BadChecksum(checksum, expected_checksum));
+    }
+
+    Ok(Some((&buf[HEADER_SIZE..body_end], checksum)))
+}
 
 /// Parse raw bytes into an `AntMessage`
-pub fn parse_buffer<E>(buf: &[u8]) -> Result<Option<AntMessage>, DriverError<E>> {
+pub fn parse_buffer<E, const N: usize>(
+    buf: &[u8],
+) -> Result<Option<AntMessage<N>>, DriverError<E>> {
     // Not enough bytes
     if buf.len() < HEADER_SIZE {
         return Ok(None);
@@ -147,24 +230,16 @@ pub fn parse_buffer<E>(buf: &[u8]) -> Result<Option<AntMessage>, DriverError<E>>
 
     // no need to check sync byte as we already used that to position ourselves
     let header = RxMessageHeader::unpack_from_slice(&buf[..HEADER_SIZE])?;
-    let msg_size = (header.msg_length as usize) + HEADER_SIZE + CHECKSUM_SIZE;
 
     // TODO
     // if buf.capacity() < msg_size {
     //     return Err(DriverError::BufferTooSmall(msg_size, buf.capacity()));
     // }
 
-    if buf.len() < msg_size {
-        return Ok(None);
-    }
-
-    let expected_checksum = calculate_checksum(&buf[..(header.msg_length as usize) + HEADER_SIZE]);
-    let checksum = buf[(header.msg_length as usize) + HEADER_SIZE];
-    if expected_checksum != checksum {
-        return Err(DriverError::BadChecksum(checksum, expected_checksum));
-    }
-
-    let msg_slice = &buf[HEADER_SIZE..(header.msg_length as usize) + HEADER_SIZE];
+    let (msg_slice, checksum) = match split_frame(buf, header.msg_length)? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
 
     let body = match header.msg_id {
         RxMessageId::StartUpMessage => {
@@ -217,14 +292,16 @@ pub fn parse_buffer<E>(buf: &[u8]) -> Result<Option<AntMessage>, DriverError<E>>
             EventBufferConfiguration::unpack_from_slice(msg_slice)?,
         ),
 
-        RxMessageId::AdvancedBurstCapabilities => match buf.len() {
+        // Capabilities are a fixed 5 bytes, the current configuration is 9 bytes plus optional
+        // stall and retry counts
+        RxMessageId::AdvancedBurstCapabilities => match msg_slice.len() {
             5 => RxMessage::AdvancedBurstCapabilities(
                 AdvancedBurstCapabilities::unpack_from_slice(msg_slice)?,
             ),
-            12 => RxMessage::AdvancedBurstCurrentConfiguration(
+            9 | 11 | 12 => RxMessage::AdvancedBurstCurrentConfiguration(
                 AdvancedBurstCurrentConfiguration::unpack_from_slice(msg_slice)?,
             ),
-            _ => return Err(DriverError::BadLength(0, buf.len())),
+            _ => return Err(DriverError::BadLength(0, msg_slice.len())),
         },
 
         RxMessageId::EventFilter => {
@@ -249,12 +326,129 @@ pub fn parse_buffer<E>(buf: &[u8]) -> Result<Option<AntMessage>, DriverError<E>>
     }))
 }
 
+/// Parse raw host to radio bytes into a `TxMessage`
+///
+/// Messages that share an ID are told apart by their sub-ID byte and length. The encryption ID
+/// list messages are the exception, they share both ID and layout with the channel ID list
+/// messages so they are always reported as [TxMessage::AddChannelIdToList] and
+/// [TxMessage::ConfigIdList].
+pub fn parse_tx_buffer<E, const N: usize>(
+    buf: &[u8],
+) -> Result<Option<TxMessage<N>>, DriverError<E>> {
+    // Not enough bytes
+    if buf.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let header = TxMessageHeader::unpack_from_slice(&buf[..HEADER_SIZE])?;
+    let msg_slice = match split_frame(buf, header.msg_length)? {
+        Some((msg_slice, _)) => msg_slice,
+        None => return Ok(None),
+    };
+
+    let msg = match header.msg_id {
+        // Config Messages
+        TxMessageId::UnAssignChannel => UnAssignChannel::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::AssignChannel => AssignChannel::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ChannelId => TxChannelId::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ChannelPeriod => ChannelPeriod::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SearchTimeout => SearchTimeout::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ChannelRfFrequency => ChannelRfFrequency::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SetNetworkKey => SetNetworkKey::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::TransmitPower => TransmitPower::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SearchWaveform => SearchWaveform::unpack_from_slice(msg_slice)?.into(),
+        // Indistinguishable from AddEncryptionIdToList
+        TxMessageId::AddChannelIdToList => AddChannelIdToList::unpack_from_slice(msg_slice)?.into(),
+        // Indistinguishable from ConfigEncryptionIdList
+        TxMessageId::ConfigIdList => ConfigIdList::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SetChannelTransmitPower => {
+            SetChannelTransmitPower::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::LowPrioritySearchTimeout => {
+            LowPrioritySearchTimeout::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::SerialNumberSetChannelId => {
+            SerialNumberSetChannelId::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::EnableExtRxMessages => {
+            EnableExtRxMessages::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::EnableLed => EnableLed::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::CrystalEnable => CrystalEnable::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::LibConfig => LibConfig::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::FrequencyAgility => FrequencyAgility::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ProximitySearch => ProximitySearch::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ConfigureEventBuffer => {
+            ConfigureEventBuffer::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::ChannelSearchPriority => {
+            ChannelSearchPriority::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::Set128BitNetworkKey => {
+            Set128BitNetworkKey::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::HighDutySearch => HighDutySearch::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::ConfigureAdvancedBurst => {
+            ConfigureAdvancedBurst::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::ConfigureEventFilter => {
+            ConfigureEventFilter::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::ConfigureSelectiveDataUpdates => {
+            ConfigureSelectiveDataUpdates::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::SetSelectiveDataUpdateMask => {
+            SetSelectiveDataUpdateMask::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::EnableSingleChannelEncryption => {
+            EnableSingleChannelEncryption::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::SetEncryptionKey => SetEncryptionKey::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SetEncryptionInfo => match msg_slice.first() {
+            Some(0) => SetEncryptionInfoEncryptionId::unpack_from_slice(msg_slice)?.into(),
+            Some(1) => SetEncryptionInfoUserInformationString::unpack_from_slice(msg_slice)?.into(),
+            Some(2) => SetEncryptionInfoRandomSeed::unpack_from_slice(msg_slice)?.into(),
+            _ => return Err(DriverError::InvalidData()),
+        },
+        TxMessageId::ChannelSearchSharing => {
+            ChannelSearchSharing::unpack_from_slice(msg_slice)?.into()
+        }
+        TxMessageId::LoadStoreEncryptionKeyFromNvm => match msg_slice.first() {
+            Some(0) => LoadEncryptionKeyFromNvm::unpack_from_slice(msg_slice)?.into(),
+            Some(1) => StoreEncryptionKeyInNvm::unpack_from_slice(msg_slice)?.into(),
+            _ => return Err(DriverError::InvalidData()),
+        },
+
+        // Control Messages
+        TxMessageId::ResetSystem => ResetSystem::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::OpenChannel => OpenChannel::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::CloseChannel => CloseChannel::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::RequestMessage => RequestMessage::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::OpenRxScanMode => OpenRxScanMode::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::SleepMessage => SleepMessage::unpack_from_slice(msg_slice)?.into(),
+
+        // Data Messages, extended info is RX only so there is nothing else to parse
+        TxMessageId::BroadcastData => BroadcastData::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::AcknowledgedData => AcknowledgedData::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::BurstTransferData => BurstTransferData::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::AdvancedBurstData => AdvancedBurstData::unpack_from_slice(msg_slice)?.into(),
+
+        // Test Mode Messages
+        TxMessageId::CwInit => CwInit::unpack_from_slice(msg_slice)?.into(),
+        TxMessageId::CwTest => CwTest::unpack_from_slice(msg_slice)?.into(),
+    };
+
+    Ok(Some(msg))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::messages::config::{
         AddChannelIdToList, DeviceType, TransmissionChannelType, TransmissionType,
     };
+    use crate::messages::MAX_FRAME_SIZE;
+    use arrayvec::ArrayVec;
 
     #[test]
     fn checksum() {
@@ -286,4 +480,381 @@ mod tests {
 
         assert_eq!(buf, [0xA4, 6, 0x59, 2, 0x44, 0x33, 120, 34, 2, 214, 0, 0]);
     }
+
+    fn tx_round_trip<T: Into<TxMessage>>(msg: T) {
+        let msg = msg.into();
+        let mut buf = [0; DEFAULT_FRAME_BUFFER_SIZE];
+        let packed = create_packed_message(&mut buf, &msg).unwrap();
+        assert_eq!(
+            parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(packed).unwrap(),
+            Some(msg)
+        );
+    }
+
+    // Aliased messages that can only be checked at the byte level
+    fn tx_byte_round_trip<T: TransmitableMessage>(msg: T) {
+        let mut buf = [0; 32];
+        let mut reparsed_buf = [0; 32];
+        let packed = create_packed_message(&mut buf, &msg).unwrap();
+        let parsed = parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(packed)
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.get_tx_msg_id(), msg.get_tx_msg_id());
+        assert_eq!(
+            create_packed_message(&mut reparsed_buf, &parsed).unwrap(),
+            packed
+        );
+    }
+
+    #[test]
+    fn parse_tx_partial() {
+        let mut buf = [0; 8];
+        let packed = create_packed_message(&mut buf, &OpenChannel::new(1)).unwrap();
+        assert_eq!(
+            parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&packed[..2]),
+            Ok(None)
+        );
+        assert_eq!(
+            parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&packed[..4]),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn parse_tx_bad_checksum() {
+        assert_eq!(
+            parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&[0xA4, 1, 0x4B, 1, 0]),
+            Err(DriverError::BadChecksum(0, 0xEF))
+        );
+    }
+
+    #[test]
+    fn parse_tx_bad_sub_id() {
+        let data = [0xA4, 5, 0x7F, 3, 1, 2, 3, 4];
+        let mut buf = [0; 9];
+        buf[..8].copy_from_slice(&data);
+        buf[8] = calculate_checksum(&data);
+        assert_eq!(
+            parse_tx_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&buf),
+            Err(DriverError::InvalidData())
+        );
+    }
+
+    #[test]
+    fn config_round_trip() {
+        use crate::messages::config::*;
+
+        tx_round_trip(UnAssignChannel::new(3));
+        tx_round_trip(AssignChannel::new(
+            1,
+            ChannelType::SharedBidirectionalSlave,
+            3,
+            None,
+        ));
+        let mut ext = ExtendedAssignment::default();
+        ext.always_search = true;
+        ext.fast_initiation_mode = true;
+        tx_round_trip(AssignChannel::new(
+            1,
+            ChannelType::MasterTransmitOnly,
+            0,
+            Some(ext),
+        ));
+        tx_round_trip(ChannelId::new(
+            2,
+            0xABCD,
+            DeviceType::new(120.into(), true),
+            TransmissionType::default(),
+        ));
+        tx_round_trip(ChannelPeriod::new(1, 8070));
+        tx_round_trip(SearchTimeout::new(1, 12));
+        tx_round_trip(ChannelRfFrequency::new(1, 57));
+        tx_round_trip(SetNetworkKey::new(0, [1, 2, 3, 4, 5, 6, 7, 8]));
+        tx_round_trip(TransmitPower::new(3));
+        tx_round_trip(SearchWaveform::new(1, SearchWaveformValue::Fast.into()));
+        tx_round_trip(SearchWaveform::new(
+            1,
+            packed_struct::EnumCatchAll::CatchAll(200),
+        ));
+        tx_round_trip(AddChannelIdToList::new(
+            2,
+            0x3344,
+            DeviceType::new(120.into(), false),
+            TransmissionType::default(),
+            2,
+        ));
+        tx_round_trip(ConfigIdList::new(2, 4, ListExclusion::Exclude));
+        tx_round_trip(SetChannelTransmitPower::new(1, 2));
+        tx_round_trip(LowPrioritySearchTimeout::new(1, 20));
+        tx_round_trip(SerialNumberSetChannelId::new(
+            1,
+            DeviceType::new(11.into(), false),
+            TransmissionType::default(),
+        ));
+        tx_round_trip(EnableExtRxMessages::new(true));
+        tx_round_trip(EnableLed::new(true));
+        tx_round_trip(CrystalEnable::new());
+        tx_round_trip(LibConfig::new(true, false, true));
+        tx_round_trip(FrequencyAgility::new(1, 3, 39, 75));
+        tx_round_trip(ProximitySearch::new(1, 5));
+        tx_round_trip(ConfigureEventBuffer::new(
+            EventBufferConfig::BufferAllEvents,
+            0x1234,
+            0x5678,
+        ));
+        tx_round_trip(ChannelSearchPriority::new(1, 2));
+        tx_round_trip(Set128BitNetworkKey::new(1, [0xAA; 16]));
+        tx_round_trip(HighDutySearch::new(true, None));
+        tx_round_trip(HighDutySearch::new(
+            true,
+            Some(HighDutySearchSuppressionCycle::new(4)),
+        ));
+        tx_round_trip(ConfigureAdvancedBurst::new(
+            true,
+            AdvancedBurstMaxPacketLength::Max24Byte,
+            SupportedFeatures::new(true),
+            SupportedFeatures::new(false),
+            None,
+            None,
+        ));
+        tx_round_trip(ConfigureAdvancedBurst::new(
+            true,
+            AdvancedBurstMaxPacketLength::Max16Byte,
+            SupportedFeatures::new(false),
+            SupportedFeatures::new(true),
+            Some(0x1234),
+            Some(3),
+        ));
+        tx_round_trip(ConfigureEventFilter::new(
+            true, false, true, false, true, false, true, false, true, false,
+        ));
+        tx_round_trip(ConfigureSelectiveDataUpdates::new(1, 2));
+        tx_round_trip(SetSelectiveDataUpdateMask::new(
+            1,
+            [0xFF, 0, 0xFF, 0, 1, 2, 3, 4],
+        ));
+        tx_round_trip(EnableSingleChannelEncryption::new(
+            1,
+            EncryptionMode::EnabledAndIncludeUserInformationString,
+            2,
+        ));
+        tx_round_trip(SetEncryptionKey::new([0x55; 16]));
+        tx_round_trip(SetEncryptionInfoEncryptionId::new([1, 2, 3, 4]));
+        tx_round_trip(SetEncryptionInfoUserInformationString::new([0x11; 19]));
+        tx_round_trip(SetEncryptionInfoRandomSeed::new([0x22; 16]));
+        tx_round_trip(ChannelSearchSharing::new(1, 3));
+        tx_round_trip(LoadEncryptionKeyFromNvm::new(4));
+        tx_round_trip(StoreEncryptionKeyInNvm::new(4, [0x33; 16]));
+    }
+
+    #[test]
+    fn aliased_list_round_trip() {
+        use crate::messages::config::*;
+
+        tx_byte_round_trip(AddEncryptionIdToList::new(1, [1, 2, 3, 4], 3));
+        tx_byte_round_trip(ConfigEncryptionIdList::new(1, 4, ListType::Blacklist));
+    }
+
+    #[test]
+    fn control_round_trip() {
+        use crate::messages::control::*;
+
+        tx_round_trip(ResetSystem::new());
+        tx_round_trip(OpenChannel::new(2));
+        tx_round_trip(CloseChannel::new(2));
+        tx_round_trip(RequestMessage::new(
+            1,
+            RequestableMessageId::Capabilities,
+            None,
+        ));
+        tx_round_trip(RequestMessage::new(
+            0,
+            RequestableMessageId::SerialNumber,
+            Some(NvmeRequest::new(0x1234, 8)),
+        ));
+        tx_round_trip(OpenRxScanMode::new(None));
+        tx_round_trip(OpenRxScanMode::new(Some(true)));
+        tx_round_trip(SleepMessage::new());
+    }
+
+    #[test]
+    fn data_round_trip() {
+        use crate::messages::data::*;
+
+        tx_round_trip(BroadcastData::new(1, [1, 2, 3, 4, 5, 6, 7, 8]));
+        tx_round_trip(AcknowledgedData::new(1, [8, 7, 6, 5, 4, 3, 2, 1]));
+        tx_round_trip(BurstTransferData::new(
+            ChannelSequence::new(5.into(), 2.into()),
+            [1, 2, 3, 4, 5, 6, 7, 8],
+        ));
+        let mut data = ArrayVec::new();
+        data.try_extend_from_slice(&[0xA5; 24]).unwrap();
+        tx_round_trip(AdvancedBurstData::new(
+            ChannelSequence::new(1.into(), 2.into()),
+            data,
+        ));
+    }
+
+    // Checks both directions, raw bytes to message to raw bytes and message to bytes to message
+    fn rx_round_trip(msg_id: RxMessageId, payload: &[u8]) {
+        let mut frame = ArrayVec::<u8, MAX_FRAME_SIZE>::new();
+        frame
+            .try_extend_from_slice(&[RxSyncByte::Write as u8, payload.len() as u8, msg_id as u8])
+            .unwrap();
+        frame.try_extend_from_slice(payload).unwrap();
+        frame.push(calculate_checksum(&frame));
+
+        let msg = parse_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&frame)
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg.to_bytes().unwrap(), frame);
+        assert_eq!(AntMessage::new(msg.message.clone()).unwrap(), msg);
+        assert_eq!(
+            parse_buffer::<(), DEFAULT_PAYLOAD_CAPACITY>(&msg.to_bytes().unwrap()).unwrap(),
+            Some(msg)
+        );
+    }
+
+    #[test]
+    fn notification_rx_round_trip() {
+        rx_round_trip(RxMessageId::StartUpMessage, &[0x20]);
+        rx_round_trip(RxMessageId::SerialErrorMessage, &[0x02]);
+    }
+
+    #[test]
+    fn data_rx_round_trip() {
+        let data = [1, 1, 2, 3, 4, 5, 6, 7, 8];
+        rx_round_trip(RxMessageId::BroadcastData, &data);
+        rx_round_trip(RxMessageId::AcknowledgedData, &data);
+        rx_round_trip(
+            RxMessageId::BurstTransferData,
+            &[0x41, 1, 2, 3, 4, 5, 6, 7, 8],
+        );
+        rx_round_trip(
+            RxMessageId::AdvancedBurstData,
+            &[0x41, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16],
+        );
+
+        // Channel ID, dBm RSSI and timestamp
+        rx_round_trip(
+            RxMessageId::BroadcastData,
+            &[
+                1, 1, 2, 3, 4, 5, 6, 7, 8, 0xE0, 0x44, 0x33, 120, 1, 0x20, 0xCE, 0x80, 0xAA, 0xBB,
+            ],
+        );
+        // AGC RSSI
+        rx_round_trip(
+            RxMessageId::AcknowledgedData,
+            &[1, 1, 2, 3, 4, 5, 6, 7, 8, 0x40, 0x10, 0xCE, 0x80, 0x60],
+        );
+        rx_round_trip(
+            RxMessageId::BurstTransferData,
+            &[0x41, 1, 2, 3, 4, 5, 6, 7, 8, 0x20, 0xAA, 0xBB],
+        );
+    }
+
+    #[test]
+    fn channel_rx_round_trip() {
+        rx_round_trip(RxMessageId::ChannelEvent, &[1, 1, 0x03]);
+        rx_round_trip(RxMessageId::ChannelEvent, &[1, 1, 0x39, 1, 2, 3, 4]);
+        rx_round_trip(RxMessageId::ChannelEvent, &[1, 1, 0x38, 1, 2, 3, 4]);
+        let mut event = [0x55; 26];
+        event[..3].copy_from_slice(&[1, 1, 0x38]);
+        rx_round_trip(RxMessageId::ChannelEvent, &event);
+        rx_round_trip(RxMessageId::ChannelEvent, &[1, 0x6E, 0x00]);
+        rx_round_trip(RxMessageId::ChannelEvent, &[1, 0x42, 0x15]);
+    }
+
+    #[test]
+    fn requested_response_rx_round_trip() {
+        rx_round_trip(RxMessageId::ChannelStatus, &[1, 0x27]);
+        rx_round_trip(RxMessageId::ChannelId, &[1, 0x44, 0x33, 120, 34]);
+        rx_round_trip(RxMessageId::AntVersion, b"AJK1.04RAF\0");
+        rx_round_trip(RxMessageId::Capabilities, &[16, 8, 0x00, 0xBA]);
+        rx_round_trip(RxMessageId::Capabilities, &[16, 8, 0x00, 0xBA, 0x36]);
+        rx_round_trip(RxMessageId::Capabilities, &[16, 8, 0x00, 0xBA, 0x36, 0]);
+        rx_round_trip(
+            RxMessageId::Capabilities,
+            &[16, 8, 0x00, 0xBA, 0x36, 0, 0xDF],
+        );
+        rx_round_trip(
+            RxMessageId::Capabilities,
+            &[16, 8, 0x00, 0xBA, 0x36, 0, 0xDF, 0x01],
+        );
+        rx_round_trip(RxMessageId::SerialNumber, &[1, 2, 3, 4]);
+        rx_round_trip(
+            RxMessageId::EventBufferConfiguration,
+            &[0, 1, 0x34, 0x12, 0x78, 0x56],
+        );
+        rx_round_trip(RxMessageId::AdvancedBurstCapabilities, &[0, 3, 1, 0, 0]);
+        rx_round_trip(
+            RxMessageId::AdvancedBurstCapabilities,
+            &[0, 1, 3, 1, 0, 0, 0, 0, 0],
+        );
+        rx_round_trip(
+            RxMessageId::AdvancedBurstCapabilities,
+            &[0, 1, 3, 1, 0, 0, 0, 0, 0, 0x34, 0x12, 3],
+        );
+        rx_round_trip(RxMessageId::EventFilter, &[0, 0xA5, 0x03]);
+        rx_round_trip(
+            RxMessageId::SelectiveDataUpdateMaskSetting,
+            &[1, 0xFF, 0, 0xFF, 0, 1, 2, 3, 4],
+        );
+        rx_round_trip(RxMessageId::UserNvm, &[0, 1, 2, 3, 4, 5]);
+        rx_round_trip(RxMessageId::EncryptionModeParameters, &[0, 2]);
+        rx_round_trip(RxMessageId::EncryptionModeParameters, &[1, 1, 2, 3, 4]);
+        rx_round_trip(RxMessageId::EncryptionModeParameters, &[2; 20]);
+    }
+
+    #[test]
+    fn rx_serialize_errors() {
+        use crate::messages::requested_response::{
+            Capabilities, EncryptionModeParameters, RequestedEncryptionParameter,
+            RequestedEncryptionParameterData,
+        };
+
+        let mut capabilities = Capabilities::unpack_from_slice(&[16, 8, 0, 0xBA]).unwrap();
+        capabilities.max_sensrcore_channels = Some(2);
+        assert_eq!(
+            <AntMessage>::new(RxMessage::Capabilities(capabilities)),
+            Err(PackingError::InvalidValue)
+        );
+
+        let params = EncryptionModeParameters {
+            requested_encryption_parameter: RequestedEncryptionParameter::EncryptionId,
+            requested_encryption_parameter_data:
+                RequestedEncryptionParameterData::UserInformationString([0; 19]),
+        };
+        assert_eq!(
+            <AntMessage>::new(RxMessage::EncryptionModeParameters(params)),
+            Err(PackingError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn rx_serialize_matches_fixture() {
+        let msg = <AntMessage>::new(RxMessage::ChannelId(TxChannelId::new(
+            1,
+            0x3344,
+            DeviceType::new(120.into(), false),
+            TransmissionType::new(
+                TransmissionChannelType::SharedChannel1ByteAddress,
+                Default::default(),
+                2.into(),
+            ),
+        )))
+        .unwrap();
+        assert_eq!(
+            msg.to_bytes().unwrap().as_slice(),
+            [0xA4, 5, 0x51, 1, 0x44, 0x33, 120, 34, 220]
+        );
+    }
+
+    #[test]
+    fn test_mode_round_trip() {
+        use crate::messages::test_mode::*;
+
+        tx_round_trip(CwInit::new());
+        tx_round_trip(CwTest::new(3, 57));
+    }
 }
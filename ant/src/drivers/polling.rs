@@ -0,0 +1,55 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::drivers::{AsyncDriver, Driver, DriverError};
+use crate::messages::{AntMessage, TransmitableMessage};
+use embedded_hal_async::delay::DelayNs;
+
+/// Adapts a [Driver] to [AsyncDriver] by polling it
+///
+/// Drivers only report what already arrived, so the driver is checked again every `interval_us`
+/// until a message shows up. Pick an interval well below the shortest channel period in use.
+pub struct PollingDriver<D, DELAY> {
+    driver: D,
+    delay: DELAY,
+    interval_us: u32,
+}
+
+impl<D, DELAY: DelayNs> PollingDriver<D, DELAY> {
+    pub fn new(driver: D, delay: DELAY, interval_us: u32) -> Self {
+        Self {
+            driver,
+            delay,
+            interval_us,
+        }
+    }
+
+    pub fn release(self) -> (D, DELAY) {
+        (self.driver, self.delay)
+    }
+}
+
+impl<E, D, DELAY, const N: usize> AsyncDriver<E, N> for PollingDriver<D, DELAY>
+where
+    D: Driver<E, N>,
+    DELAY: DelayNs,
+{
+    async fn get_message(&mut self) -> Result<AntMessage<N>, DriverError<E>> {
+        loop {
+            // Only the delay is ever pending so dropping the future never loses a message
+            if let Some(msg) = self.driver.get_message()? {
+                return Ok(msg);
+            }
+            self.delay.delay_us(self.interval_us).await;
+        }
+    }
+
+    async fn send_message(&mut self, msg: &dyn TransmitableMessage) -> Result<(), DriverError<E>> {
+        self.driver.send_message(msg)
+    }
+}
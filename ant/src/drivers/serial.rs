@@ -7,19 +7,30 @@
 // except according to those terms.
 
 use crate::drivers::{
-    align_buffer, create_packed_message, parse_buffer, update_buffer, Buffer, Driver, DriverError,
-    ANT_MESSAGE_SIZE,
+    align_buffer, create_packed_message, parse_buffer, update_buffer, Driver, DriverError,
+    CHECKSUM_SIZE, DEFAULT_FRAME_BUFFER_SIZE, HEADER_SIZE,
 };
-use crate::messages::{AntMessage, TransmitableMessage};
+use crate::messages::{AntMessage, TransmitableMessage, DEFAULT_PAYLOAD_CAPACITY};
+use arrayvec::ArrayVec;
 use embedded_hal::digital::{ErrorType, OutputPin, PinState};
 use embedded_hal_nb::serial::Read;
 use embedded_hal_nb::serial::Write;
 use nb;
 
-pub struct SerialDriver<SERIAL, PIN> {
+/// Driver for radios connected over a UART
+///
+/// `N` is the capacity of variable length fields in received messages and `B` the size of the
+/// frame buffer, use [crate::drivers::frame_buffer_size] to size `B` for a given `N`. Frames that
+/// do not fit in `B` are dropped and reported as [DriverError::BufferTooSmall].
+pub struct SerialDriver<
+    SERIAL,
+    PIN,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+    const B: usize = DEFAULT_FRAME_BUFFER_SIZE,
+> {
     serial: SERIAL,
     sleep: Option<PIN>,
-    buffer: Buffer, // TODO change this dependency injection so user controls the size
+    buffer: ArrayVec<u8, B>,
 }
 
 impl<SERIAL, SLEEP> SerialDriver<SERIAL, SLEEP>
@@ -27,11 +38,22 @@ where
     SERIAL: Read<u8> + Write<u8>,
     SLEEP: OutputPin,
 {
-    pub fn new(serial: SERIAL, sleep: Option<SLEEP>) -> SerialDriver<SERIAL, SLEEP> {
+    pub fn new(serial: SERIAL, sleep: Option<SLEEP>) -> Self {
+        Self::new_sized(serial, sleep)
+    }
+}
+
+impl<SERIAL, SLEEP, const N: usize, const B: usize> SerialDriver<SERIAL, SLEEP, N, B>
+where
+    SERIAL: Read<u8> + Write<u8>,
+    SLEEP: OutputPin,
+{
+    /// Construct a driver with a non default `N` or `B`
+    pub fn new_sized(serial: SERIAL, sleep: Option<SLEEP>) -> Self {
         SerialDriver {
             serial,
             sleep,
-            buffer: Buffer::new(),
+            buffer: ArrayVec::new(),
         }
     }
 
@@ -40,12 +62,13 @@ where
     }
 }
 
-impl<SERIAL, SLEEP> Driver<SERIAL::Error> for SerialDriver<SERIAL, SLEEP>
+impl<SERIAL, SLEEP, const N: usize, const B: usize> Driver<SERIAL::Error, N>
+    for SerialDriver<SERIAL, SLEEP, N, B>
 where
     SERIAL: Read<u8> + Write<u8>,
     SLEEP: OutputPin,
 {
-    fn get_message(&mut self) -> Result<Option<AntMessage>, DriverError<SERIAL::Error>> {
+    fn get_message(&mut self) -> Result<Option<AntMessage<N>>, DriverError<SERIAL::Error>> {
         let buf = &mut self.buffer;
 
         loop {
@@ -63,7 +86,12 @@ where
 
         buf.drain(..align_buffer(buf));
 
-        let msg_result = parse_buffer(buf);
+        let mut msg_result = parse_buffer(buf);
+        if matches!(msg_result, Ok(None)) && buf.is_full() {
+            // The frame can never complete, report it and let the buffer move past it
+            let frame_size = buf[1] as usize + HEADER_SIZE + CHECKSUM_SIZE;
+            msg_result = Err(DriverError::BufferTooSmall(frame_size, buf.capacity()));
+        }
 
         buf.drain(..update_buffer(&msg_result, buf));
 
@@ -74,9 +102,8 @@ where
         &mut self,
         msg: &dyn TransmitableMessage,
     ) -> Result<(), DriverError<SERIAL::Error>> {
-        // TODO update with variable sized buf
         // TODO fix io error propotation
-        let mut buf: [u8; ANT_MESSAGE_SIZE] = [0; ANT_MESSAGE_SIZE];
+        let mut buf = [0; B];
 
         let buf_slice = create_packed_message(&mut buf, msg)?;
 
@@ -132,12 +159,14 @@ impl OutputPin for StubPin {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::channel::{ChannelEvent, MessageCode};
     use crate::messages::config::{
-        AddChannelIdToList, ChannelType, DeviceType, TransmissionChannelType,
+        AddChannelIdToList, ChannelType, DeviceType, Set128BitNetworkKey,
+        SetEncryptionInfoUserInformationString, TransmissionChannelType,
         TransmissionGlobalDataPages, TransmissionType,
     };
     use crate::messages::requested_response::{ChannelId, ChannelState, ChannelStatus};
-    use crate::messages::{RxMessage, RxMessageHeader, RxMessageId, RxSyncByte};
+    use crate::messages::{RxMessage, RxMessageHeader, RxMessageId, RxSyncByte, MAX_FRAME_SIZE};
     use embedded_hal_nb::serial;
 
     enum TestData {
@@ -227,13 +256,16 @@ mod tests {
         [2, 3, 4, 5, 6].iter().for_each(|x| buf.push(*x));
         assert_eq!(
             1,
-            update_buffer::<serial::ErrorKind>(&Err(DriverError::BadChecksum(0, 0)), &mut buf)
+            update_buffer::<serial::ErrorKind, DEFAULT_PAYLOAD_CAPACITY>(
+                &Err(DriverError::BadChecksum(0, 0)),
+                &mut buf
+            )
         );
         buf.clear();
         [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]
             .iter()
             .for_each(|x| buf.push(*x));
-        let remove = update_buffer::<serial::ErrorKind>(
+        let remove = update_buffer::<serial::ErrorKind, DEFAULT_PAYLOAD_CAPACITY>(
             &Ok(Some(AntMessage {
                 header: RxMessageHeader {
                     sync: RxSyncByte::Write,
@@ -253,7 +285,7 @@ mod tests {
         assert_eq!(remove, 10);
         buf.clear();
         [2, 3, 4, 5, 6, 7].iter().for_each(|x| buf.push(*x));
-        update_buffer::<serial::ErrorKind>(&Ok(None), &mut buf);
+        update_buffer::<serial::ErrorKind, DEFAULT_PAYLOAD_CAPACITY>(&Ok(None), &mut buf);
         assert_eq!(buf.as_slice(), [2, 3, 4, 5, 6, 7]);
     }
 
@@ -361,6 +393,43 @@ mod tests {
         driver.serial.validate();
     }
 
+    #[test]
+    fn serial_read_oversized_frame() {
+        // Advanced burst frame with 48 bytes of data does not fit an 8 byte capacity
+        let mut frame = vec![0xA4, 49, 0x72, 1];
+        frame.extend_from_slice(&[0; 48]);
+        frame.push(crate::drivers::calculate_checksum(&frame));
+        frame.extend_from_slice(&[0xA4, 5, 0x51, 1, 0x44, 0x33, 120, 34, 220]);
+        let context = ValidationContext {
+            in_bytes: vec![TestData::Data(frame)],
+            out_bytes: vec![],
+        };
+        let mut driver =
+            SerialDriver::<_, StubPin, 8, { crate::drivers::frame_buffer_size(8) }>::new_sized(
+                context, None,
+            );
+        assert_eq!(
+            driver.get_message(),
+            Err(DriverError::BufferTooSmall(53, 30))
+        );
+        // Driver recovers once the oversized frame has been skipped
+        let mut msg = Ok(None);
+        for _ in 0..32 {
+            msg = driver.get_message();
+            if let Ok(Some(_)) = msg {
+                break;
+            }
+        }
+        assert!(matches!(
+            msg,
+            Ok(Some(AntMessage {
+                message: RxMessage::ChannelId(_),
+                ..
+            }))
+        ));
+        driver.serial.validate();
+    }
+
     #[test]
     fn serial_write_out() {
         let context = ValidationContext {
@@ -422,4 +491,37 @@ mod tests {
         );
         driver.serial.validate();
     }
+
+    #[test]
+    fn serial_small_capacity_fixed_size_frames() {
+        // Fixed size frames are larger than the variable length fields of an 8 byte capacity
+        let key = Set128BitNetworkKey::new(0, [0x11; 16]);
+        let uis = SetEncryptionInfoUserInformationString::new([0x22; 19]);
+        let mut out_bytes = vec![];
+        for msg in [&key as &dyn TransmitableMessage, &uis] {
+            let mut buf = [0; MAX_FRAME_SIZE];
+            out_bytes.push(TestData::Data(
+                create_packed_message(&mut buf, msg).unwrap().to_vec(),
+            ));
+        }
+        let mut event = vec![1, 1, MessageCode::EncryptNegotiationSuccess as u8];
+        event.extend_from_slice(&[0x33; 4]);
+        event.extend_from_slice(&[0x44; 19]);
+        let event = AntMessage::<8>::new(RxMessage::ChannelEvent(
+            ChannelEvent::unpack_from_slice(&event).unwrap(),
+        ))
+        .unwrap();
+        let context = ValidationContext {
+            in_bytes: vec![TestData::Data(event.to_bytes().unwrap().to_vec())],
+            out_bytes,
+        };
+        let mut driver =
+            SerialDriver::<_, StubPin, 8, { crate::drivers::frame_buffer_size(8) }>::new_sized(
+                context, None,
+            );
+        driver.send_message(&key).unwrap();
+        driver.send_message(&uis).unwrap();
+        assert_eq!(driver.get_message(), Ok(Some(event)));
+        driver.serial.validate();
+    }
 }
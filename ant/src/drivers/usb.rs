@@ -11,16 +11,20 @@
 
 use crate::drivers::{
     align_buffer, create_packed_message, parse_buffer, update_buffer, Driver, DriverError,
-    ANT_MESSAGE_SIZE,
 };
-use crate::messages::{AntMessage, TransmitableMessage};
+use crate::messages::{AntMessage, TransmitableMessage, DEFAULT_PAYLOAD_CAPACITY, MAX_FRAME_SIZE};
 use rusb::{Device, DeviceHandle, Direction, Interface, TransferType, UsbContext};
 use std::cmp::min;
+use std::marker::PhantomData;
 use std::time::Duration;
 
 pub type UsbDriverError = DriverError<rusb::Error>;
 
-pub struct UsbDriver<T: UsbContext> {
+/// Driver for USB sticks
+///
+/// `N` is the capacity of variable length fields in received messages, the stick can send user
+/// NVM contents of up to 254 bytes. Transfer buffers are heap allocated so any frame is received.
+pub struct UsbDriver<T: UsbContext, const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     handle: DeviceHandle<T>,
     in_address: u8,
     out_address: u8,
@@ -29,10 +33,11 @@ pub struct UsbDriver<T: UsbContext> {
     out_buf: Vec<u8>,
     in_max_packet_size: usize,
     out_max_packet_size: usize,
+    _marker: PhantomData<[u8; N]>,
 }
 
-impl<T: UsbContext> Driver<rusb::Error> for UsbDriver<T> {
-    fn get_message(&mut self) -> Result<Option<AntMessage>, UsbDriverError> {
+impl<T: UsbContext, const N: usize> Driver<rusb::Error, N> for UsbDriver<T, N> {
+    fn get_message(&mut self) -> Result<Option<AntMessage<N>>, UsbDriverError> {
         if let Err(x) = self.read() {
             return Err(DriverError::SystemError(x));
         }
@@ -45,7 +50,7 @@ impl<T: UsbContext> Driver<rusb::Error> for UsbDriver<T> {
     }
 
     fn send_message(&mut self, msg: &dyn TransmitableMessage) -> Result<(), UsbDriverError> {
-        let mut buf: [u8; ANT_MESSAGE_SIZE] = [0; ANT_MESSAGE_SIZE];
+        let mut buf = [0; MAX_FRAME_SIZE];
         let buf_slice = create_packed_message(&mut buf, msg)?;
 
         self.out_buf.extend_from_slice(buf_slice);
@@ -123,6 +128,13 @@ fn find_endpoint(
 
 impl<T: UsbContext> UsbDriver<T> {
     pub fn new(device: Device<T>) -> Result<Self, UsbError> {
+        Self::new_sized(device)
+    }
+}
+
+impl<T: UsbContext, const N: usize> UsbDriver<T, N> {
+    /// Construct a driver with a non default `N`
+    pub fn new_sized(device: Device<T>) -> Result<Self, UsbError> {
         let handle = match device.open() {
             Ok(h) => h,
             Err(e) => return Err(UsbError::FailedToOpenDevice(e)),
@@ -174,6 +186,7 @@ impl<T: UsbContext> UsbDriver<T> {
             out_buf: Vec::new(),
             in_max_packet_size,
             out_max_packet_size,
+            _marker: PhantomData,
         })
     }
 
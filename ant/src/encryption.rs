@@ -0,0 +1,180 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encrypted channel setup
+//!
+//! Encryption is configured in two parts. The key and encryption info live in the radio's volatile
+//! key slot and are shared by all channels, see [EncryptionKeyConfig]. Each channel then opts in
+//! with its own mode and optional encryption ID list, see [ChannelEncryption], which is applied
+//! by [crate::plus::common::msg_handler::MessageHandler] as part of channel configuration.
+
+use crate::messages::config::{
+    AddEncryptionIdToList, ConfigEncryptionIdList, EnableSingleChannelEncryption, EncryptionId,
+    EncryptionMode, ListType, LoadEncryptionKeyFromNvm, SetEncryptionInfoEncryptionId,
+    SetEncryptionInfoRandomSeed, SetEncryptionInfoUserInformationString, SetEncryptionKey,
+    UserInformationString,
+};
+use crate::messages::TxMessage;
+use arrayvec::ArrayVec;
+
+/// Largest encryption ID list a channel can hold per ANT spec
+pub const MAX_ENCRYPTION_ID_LIST_SIZE: usize = 4;
+
+/// Where the radio should source the 128-bit encryption key from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EncryptionKey {
+    /// Key is sent over the wire and held in RAM
+    Ram([u8; 16]),
+    /// Key was previously stored in NVM at the given index
+    Nvm(u8),
+}
+
+/// Radio wide encryption key and info
+///
+/// Sent once before any encrypted channel is opened, see [crate::router::Router::set_encryption_key].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncryptionKeyConfig {
+    pub key: EncryptionKey,
+    /// ID this device presents during negotiation
+    pub encryption_id: Option<EncryptionId>,
+    /// Shared with the remote device when [EncryptionMode::EnabledAndIncludeUserInformationString]
+    /// is used
+    pub user_information_string: Option<UserInformationString>,
+    pub random_seed: Option<[u8; 16]>,
+}
+
+impl EncryptionKeyConfig {
+    pub fn new(key: EncryptionKey) -> Self {
+        Self {
+            key,
+            encryption_id: None,
+            user_information_string: None,
+            random_seed: None,
+        }
+    }
+
+    /// Messages to send, in order, to load the key and info into the radio
+    pub fn messages<const N: usize>(&self) -> impl Iterator<Item = TxMessage<N>> {
+        let key: TxMessage<N> = match self.key {
+            EncryptionKey::Ram(key) => SetEncryptionKey::new(key).into(),
+            EncryptionKey::Nvm(index) => LoadEncryptionKeyFromNvm::new(index).into(),
+        };
+        [
+            Some(key),
+            self.encryption_id
+                .map(|id| SetEncryptionInfoEncryptionId::new(id).into()),
+            self.user_information_string
+                .map(|uis| SetEncryptionInfoUserInformationString::new(uis).into()),
+            self.random_seed
+                .map(|seed| SetEncryptionInfoRandomSeed::new(seed).into()),
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+/// Encryption IDs a channel will accept or reject during negotiation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EncryptionIdList {
+    pub list_type: ListType,
+    pub ids: ArrayVec<EncryptionId, MAX_ENCRYPTION_ID_LIST_SIZE>,
+}
+
+impl EncryptionIdList {
+    pub fn new(list_type: ListType) -> Self {
+        Self {
+            list_type,
+            ids: ArrayVec::new(),
+        }
+    }
+
+    /// Add an ID to the list, returns the ID back if the list is full
+    pub fn push(&mut self, id: EncryptionId) -> Result<(), EncryptionId> {
+        self.ids.try_push(id).map_err(|e| e.element())
+    }
+
+    pub(crate) fn add_message<const N: usize>(
+        &self,
+        channel: u8,
+        index: usize,
+    ) -> Option<TxMessage<N>> {
+        self.ids
+            .get(index)
+            .map(|id| AddEncryptionIdToList::new(channel, *id, index as u8).into())
+    }
+
+    pub(crate) fn config_message<const N: usize>(&self, channel: u8) -> TxMessage<N> {
+        ConfigEncryptionIdList::new(channel, self.ids.len() as u8, self.list_type).into()
+    }
+}
+
+/// Per channel encryption settings
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelEncryption {
+    pub mode: EncryptionMode,
+    /// Ratio of channel period to encrypted period, 1 encrypts every message
+    pub decimation_rate: u8,
+    pub id_list: Option<EncryptionIdList>,
+}
+
+impl ChannelEncryption {
+    pub fn new(mode: EncryptionMode) -> Self {
+        Self {
+            mode,
+            decimation_rate: 1,
+            id_list: None,
+        }
+    }
+
+    pub(crate) fn enable_message<const N: usize>(&self, channel: u8) -> TxMessage<N> {
+        EnableSingleChannelEncryption::new(channel, self.mode, self.decimation_rate).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{TransmitableMessage, TxMessageId};
+
+    #[test]
+    fn key_config_messages() {
+        let ram = EncryptionKeyConfig::new(EncryptionKey::Ram([1; 16]));
+        let ids: ArrayVec<TxMessageId, 4> =
+            ram.messages::<64>().map(|m| m.get_tx_msg_id()).collect();
+        assert_eq!(ids.as_slice(), &[TxMessageId::SetEncryptionKey]);
+
+        let mut nvm = EncryptionKeyConfig::new(EncryptionKey::Nvm(2));
+        nvm.encryption_id = Some([1, 2, 3, 4]);
+        nvm.random_seed = Some([5; 16]);
+        let msgs: ArrayVec<TxMessage, 4> = nvm.messages().collect();
+        assert_eq!(
+            msgs[0],
+            TxMessage::LoadEncryptionKeyFromNvm(LoadEncryptionKeyFromNvm::new(2))
+        );
+        assert_eq!(
+            msgs[1],
+            TxMessage::SetEncryptionInfoEncryptionId(SetEncryptionInfoEncryptionId::new([
+                1, 2, 3, 4
+            ]))
+        );
+        assert_eq!(
+            msgs[2],
+            TxMessage::SetEncryptionInfoRandomSeed(SetEncryptionInfoRandomSeed::new([5; 16]))
+        );
+        assert_eq!(msgs.len(), 3);
+    }
+
+    #[test]
+    fn id_list_full() {
+        let mut list = EncryptionIdList::new(ListType::Blacklist);
+        for i in 0..MAX_ENCRYPTION_ID_LIST_SIZE as u8 {
+            assert_eq!(list.push([i; 4]), Ok(()));
+        }
+        assert_eq!(list.push([9; 4]), Err([9; 4]));
+    }
+}
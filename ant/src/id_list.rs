@@ -0,0 +1,157 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Channel ID inclusion and exclusion lists
+//!
+//! A wildcard search binds to the first matching device it hears. A [ChannelIdList] narrows that
+//! down, either to only the listed devices or to anything but them. Lists are applied by
+//! [crate::plus::common::msg_handler::MessageHandler] as part of channel configuration and are
+//! reapplied whenever the channel is configured again.
+
+use crate::messages::config::{
+    AddChannelIdToList, ConfigIdList, DeviceType, ListExclusion, TransmissionType,
+};
+use crate::messages::TxMessage;
+use arrayvec::ArrayVec;
+
+/// Largest channel ID list a channel can hold per ANT spec
+pub const MAX_CHANNEL_ID_LIST_SIZE: usize = 4;
+
+/// Channel ID to match, zero fields are wildcards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ListedDevice {
+    pub device_number: u16,
+    pub device_type: DeviceType,
+    pub transmission_type: TransmissionType,
+}
+
+impl ListedDevice {
+    pub fn new(device_number: u16, device_type: u8) -> Self {
+        Self {
+            device_number,
+            device_type: DeviceType::new(device_type.into(), false),
+            transmission_type: TransmissionType::new_wildcard(),
+        }
+    }
+}
+
+/// Devices a wildcard channel will or will not connect to
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelIdList {
+    pub exclusion: ListExclusion,
+    devices: ArrayVec<ListedDevice, MAX_CHANNEL_ID_LIST_SIZE>,
+}
+
+impl ChannelIdList {
+    pub fn new(exclusion: ListExclusion) -> Self {
+        Self {
+            exclusion,
+            devices: ArrayVec::new(),
+        }
+    }
+
+    /// Add a device to the list, returns the device back if the list is full
+    pub fn push(&mut self, device: ListedDevice) -> Result<(), ListedDevice> {
+        self.devices.try_push(device).map_err(|e| e.element())
+    }
+
+    /// Remove a device from the list, returns false if it was not listed
+    pub fn remove(&mut self, device: &ListedDevice) -> bool {
+        match self.devices.iter().position(|listed| listed == device) {
+            Some(index) => {
+                self.devices.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, device: &ListedDevice) -> bool {
+        self.devices.contains(device)
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+
+    pub fn devices(&self) -> &[ListedDevice] {
+        &self.devices
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.devices.is_full()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.devices.capacity()
+    }
+
+    pub(crate) fn add_message<const N: usize>(
+        &self,
+        channel: u8,
+        index: usize,
+    ) -> Option<TxMessage<N>> {
+        self.devices.get(index).map(|device| {
+            AddChannelIdToList::new(
+                channel,
+                device.device_number,
+                device.device_type,
+                device.transmission_type,
+                index as u8,
+            )
+            .into()
+        })
+    }
+
+    /// An empty list turns list matching off
+    pub(crate) fn config_message<const N: usize>(&self, channel: u8) -> TxMessage<N> {
+        ConfigIdList::new(channel, self.devices.len() as u8, self.exclusion).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_contents() {
+        let mut list = ChannelIdList::new(ListExclusion::Exclude);
+        for i in 0..MAX_CHANNEL_ID_LIST_SIZE as u16 {
+            assert_eq!(list.push(ListedDevice::new(i + 1, 120)), Ok(()));
+        }
+        assert!(list.is_full());
+        assert_eq!(
+            list.push(ListedDevice::new(9, 120)),
+            Err(ListedDevice::new(9, 120))
+        );
+        assert!(list.remove(&ListedDevice::new(2, 120)));
+        assert!(!list.remove(&ListedDevice::new(2, 120)));
+        assert!(!list.contains(&ListedDevice::new(2, 120)));
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.capacity(), MAX_CHANNEL_ID_LIST_SIZE);
+        assert_eq!(
+            list.add_message::<64>(1, 1),
+            Some(TxMessage::AddChannelIdToList(AddChannelIdToList::new(
+                1,
+                3,
+                DeviceType::new(120.into(), false),
+                TransmissionType::new_wildcard(),
+                1
+            )))
+        );
+        assert_eq!(list.add_message::<64>(1, 3), None);
+    }
+}
@@ -32,7 +32,6 @@
 //! ## Roadmap
 //!  * Softdevice support
 //!  * SPI support
-//!  * Large message configurable buffer maximums
 //!  * USB support conditional compilation
 //!  * Safe processing of data (no_panic)
 //!  * Extended format support
@@ -41,14 +40,23 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-#[cfg(all(not(feature = "std"), feature = "alloc"))]
+#[cfg(feature = "alloc")]
 extern crate alloc;
 #[cfg(not(feature = "std"))]
 extern crate core as std;
 
+#[cfg(feature = "async")]
+pub mod async_router;
+pub mod burst;
+pub mod callback;
 pub mod channel;
 pub mod drivers;
+pub mod encryption;
+pub mod id_list;
 pub mod messages;
+pub mod network;
 pub mod plus;
-#[cfg(feature = "alloc")]
+pub mod rf_test;
 pub mod router;
+pub mod sdu;
+pub mod stats;
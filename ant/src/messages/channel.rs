@@ -76,19 +76,104 @@ pub struct ChannelEvent {
     pub extended_info: Option<ChannelEventExtension>,
 }
 
+impl ChannelEventPayload {
+    const PACKING_SIZE: usize = 3;
+}
+
+impl ChannelEventExtension {
+    const ENCRYPTION_ID_SIZE: usize = 4;
+    const USER_INFORMATION_STRING_SIZE: usize = 19;
+}
+
 impl ChannelEvent {
     pub(crate) const MSG_ID: u8 = 1;
     pub(crate) const MSG_ID_INDEX: usize = 1;
 
     pub(crate) fn unpack_from_slice(data: &[u8]) -> Result<Self, PackingError> {
-        let payload = ChannelEventPayload::unpack_from_slice(data)?;
+        let payload_buf = data.get(..ChannelEventPayload::PACKING_SIZE).ok_or(
+            PackingError::BufferSizeMismatch {
+                expected: ChannelEventPayload::PACKING_SIZE,
+                actual: data.len(),
+            },
+        )?;
+        let payload = ChannelEventPayload::unpack_from_slice(payload_buf)?;
+        let data = &data[ChannelEventPayload::PACKING_SIZE..];
+
+        let encryption_id = || -> Result<EncryptionId, PackingError> {
+            data[..ChannelEventExtension::ENCRYPTION_ID_SIZE]
+                .try_into()
+                .map_err(|_| PackingError::SliceIndexingError {
+                    slice_len: data.len(),
+                })
+        };
+
+        let extended_info = match (payload.message_code, data.len()) {
+            (_, 0) => None,
+            (MessageCode::EncryptNegotiationSuccess, ChannelEventExtension::ENCRYPTION_ID_SIZE) => {
+                Some(ChannelEventExtension::EncryptNegotiationSuccess(
+                    encryption_id()?,
+                    None,
+                ))
+            }
+            (MessageCode::EncryptNegotiationSuccess, len)
+                if len
+                    == ChannelEventExtension::ENCRYPTION_ID_SIZE
+                        + ChannelEventExtension::USER_INFORMATION_STRING_SIZE =>
+            {
+                let user_information_string = data[ChannelEventExtension::ENCRYPTION_ID_SIZE..]
+                    .try_into()
+                    .map_err(|_| PackingError::SliceIndexingError {
+                        slice_len: data.len(),
+                    })?;
+                Some(ChannelEventExtension::EncryptNegotiationSuccess(
+                    encryption_id()?,
+                    Some(user_information_string),
+                ))
+            }
+            (MessageCode::EncryptNegotiationFail, ChannelEventExtension::ENCRYPTION_ID_SIZE) => {
+                Some(ChannelEventExtension::EncryptNegotiationFail(
+                    encryption_id()?,
+                ))
+            }
+            (_, len) => {
+                return Err(PackingError::BufferSizeMismatch {
+                    expected: ChannelEventPayload::PACKING_SIZE,
+                    actual: ChannelEventPayload::PACKING_SIZE + len,
+                })
+            }
+        };
 
         Ok(ChannelEvent {
             payload,
-            // TODO extended_info,
-            extended_info: None,
+            extended_info,
         })
     }
+
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        self.payload.pack_to_slice(
+            buf.get_mut(..ChannelEventPayload::PACKING_SIZE)
+                .ok_or(PackingError::BufferTooSmall)?,
+        )?;
+        let buf = &mut buf[ChannelEventPayload::PACKING_SIZE..];
+
+        let (encryption_id, user_information_string) = match &self.extended_info {
+            None => return Ok(ChannelEventPayload::PACKING_SIZE),
+            Some(ChannelEventExtension::EncryptNegotiationSuccess(id, uis)) => (id, uis.as_ref()),
+            Some(ChannelEventExtension::EncryptNegotiationFail(id)) => (id, None),
+        };
+
+        let mut len = ChannelEventPayload::PACKING_SIZE + encryption_id.len();
+        buf.get_mut(..encryption_id.len())
+            .ok_or(PackingError::BufferTooSmall)?
+            .copy_from_slice(encryption_id);
+        if let Some(uis) = user_information_string {
+            buf.get_mut(encryption_id.len()..encryption_id.len() + uis.len())
+                .ok_or(PackingError::BufferTooSmall)?
+                .copy_from_slice(uis);
+            len += uis.len();
+        }
+        Ok(len)
+    }
 }
 
 #[derive(PackedStruct, Debug, Clone, PartialEq)]
@@ -102,6 +187,69 @@ pub struct ChannelResponse {
     pub message_code: MessageCode,
 }
 
+/// Failure reported by the radio in response to a command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandError {
+    ChannelInWrongState,
+    ChannelNotOpened,
+    ChannelIdNotSet,
+    CloseAllChannels,
+    TransferInProgress,
+    TransferSequenceNumberError,
+    TransferInError,
+    MessageSizeExceedsLimit,
+    InvalidMessage,
+    InvalidNetworkNumber,
+    InvalidListId,
+    InvalidScanTxChannel,
+    InvalidParameterProvided,
+    NvmFullError,
+    NvmWriteError,
+    UsbStringWriteFail,
+    SerialError,
+    /// Code is an event rather than a command response
+    UnexpectedCode(MessageCode),
+}
+
+impl CommandError {
+    /// Map a response code to a result, [MessageCode::ResponseNoError] is the only success
+    pub fn from_code(code: MessageCode) -> Result<(), CommandError> {
+        Err(match code {
+            MessageCode::ResponseNoError => return Ok(()),
+            MessageCode::ChannelInWrongState => CommandError::ChannelInWrongState,
+            MessageCode::ChannelNotOpened => CommandError::ChannelNotOpened,
+            MessageCode::ChannelIdNotSet => CommandError::ChannelIdNotSet,
+            MessageCode::CloseAllChannels => CommandError::CloseAllChannels,
+            MessageCode::TransferInProgress => CommandError::TransferInProgress,
+            MessageCode::TransferSequenceNumberError => CommandError::TransferSequenceNumberError,
+            MessageCode::TransferInError => CommandError::TransferInError,
+            MessageCode::MessageSizeExceedsLimit => CommandError::MessageSizeExceedsLimit,
+            MessageCode::InvalidMessage => CommandError::InvalidMessage,
+            MessageCode::InvalidNetworkNumber => CommandError::InvalidNetworkNumber,
+            MessageCode::InvalidListId => CommandError::InvalidListId,
+            MessageCode::InvalidScanTxChannel => CommandError::InvalidScanTxChannel,
+            MessageCode::InvalidParameterProvided => CommandError::InvalidParameterProvided,
+            MessageCode::NvmFullError => CommandError::NvmFullError,
+            MessageCode::NvmWriteError => CommandError::NvmWriteError,
+            MessageCode::UsbStringWriteFail => CommandError::UsbStringWriteFail,
+            MessageCode::MesgSerialErrorId => CommandError::SerialError,
+            code => CommandError::UnexpectedCode(code),
+        })
+    }
+}
+
+impl ChannelResponse {
+    /// Outcome of the command this response is for
+    pub fn result(&self) -> Result<(), CommandError> {
+        CommandError::from_code(self.message_code)
+    }
+
+    /// Outcome of the command if this response is for `message_id`, otherwise `None`
+    pub fn result_for(&self, message_id: TxMessageId) -> Option<Result<(), CommandError>> {
+        (self.message_id == message_id).then(|| self.result())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,12 +260,57 @@ mod tests {
         assert_eq!(unpacked.channel_number, 1);
         assert_eq!(unpacked.message_id, TxMessageId::LibConfig);
         assert_eq!(unpacked.message_code, MessageCode::ResponseNoError);
+        assert_eq!(unpacked.result(), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn command_result() -> Result<(), PackingError> {
+        let unpacked = ChannelResponse::unpack(&[2, 0x42, 0x29])?;
+        assert_eq!(unpacked.result(), Err(CommandError::InvalidNetworkNumber));
+        assert_eq!(
+            unpacked.result_for(TxMessageId::AssignChannel),
+            Some(Err(CommandError::InvalidNetworkNumber))
+        );
+        assert_eq!(unpacked.result_for(TxMessageId::ChannelId), None);
+
+        let unpacked = ChannelResponse::unpack(&[2, 0x4B, 0x15])?;
+        assert_eq!(unpacked.result(), Err(CommandError::ChannelInWrongState));
+        let unpacked = ChannelResponse::unpack(&[2, 0x5A, 0x30])?;
+        assert_eq!(unpacked.result(), Err(CommandError::InvalidListId));
+        let unpacked = ChannelResponse::unpack(&[2, 0x4E, 0x03])?;
+        assert_eq!(
+            unpacked.result(),
+            Err(CommandError::UnexpectedCode(MessageCode::EventTx))
+        );
         Ok(())
     }
 
     #[test]
     fn channel_event() -> Result<(), PackingError> {
-        // TODO test
+        let unpacked = ChannelEvent::unpack_from_slice(&[1, 1, 0x03])?;
+        assert_eq!(unpacked.payload.channel_number, 1);
+        assert_eq!(unpacked.payload.message_code, MessageCode::EventTx);
+        assert_eq!(unpacked.extended_info, None);
+
+        let unpacked = ChannelEvent::unpack_from_slice(&[1, 1, 0x39, 1, 2, 3, 4])?;
+        assert_eq!(
+            unpacked.extended_info,
+            Some(ChannelEventExtension::EncryptNegotiationFail([1, 2, 3, 4]))
+        );
+
+        let mut data = [7; 26];
+        data[..7].copy_from_slice(&[1, 1, 0x38, 1, 2, 3, 4]);
+        let unpacked = ChannelEvent::unpack_from_slice(&data)?;
+        assert_eq!(
+            unpacked.extended_info,
+            Some(ChannelEventExtension::EncryptNegotiationSuccess(
+                [1, 2, 3, 4],
+                Some([7; 19])
+            ))
+        );
+
+        assert!(ChannelEvent::unpack_from_slice(&[1, 1, 0x03, 1]).is_err());
         Ok(())
     }
 }
@@ -6,6 +6,8 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::messages::channel::MessageCode;
+use crate::messages::requested_response::RadioCapabilities;
 use crate::messages::{AntAutoPackWithExtention, TransmitableMessage, TxMessage, TxMessageId};
 use ant_derive::AntTx;
 use derive_new::new;
@@ -37,6 +39,18 @@ pub enum ChannelType {
     MasterTransmitOnly = 5,
 }
 
+impl ChannelType {
+    /// Returns true if the channel sets the timing, masters transmit every period
+    pub fn is_master(&self) -> bool {
+        matches!(
+            self,
+            ChannelType::BidirectionalMaster
+                | ChannelType::SharedBidirectionalMaster
+                | ChannelType::MasterTransmitOnly
+        )
+    }
+}
+
 /// Mandatory fields for [AssignChannel] messages
 #[derive(PackedStruct, Clone, Copy, Debug, Default, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "3")]
@@ -669,6 +683,17 @@ pub enum AdvancedBurstMaxPacketLength {
     Max24Byte = 0x03,
 }
 
+impl AdvancedBurstMaxPacketLength {
+    /// Packet payload size in bytes
+    pub fn bytes(&self) -> usize {
+        match self {
+            AdvancedBurstMaxPacketLength::Max8Byte => 8,
+            AdvancedBurstMaxPacketLength::Max16Byte => 16,
+            AdvancedBurstMaxPacketLength::Max24Byte => 24,
+        }
+    }
+}
+
 #[derive(PackedStruct, new, Copy, Clone, Debug, Default, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "3")]
 pub struct SupportedFeatures {
@@ -750,6 +775,12 @@ impl TransmitableMessage for ConfigureAdvancedBurst {
     }
 }
 
+impl<const N: usize> From<ConfigureAdvancedBurst> for TxMessage<N> {
+    fn from(msg: ConfigureAdvancedBurst) -> TxMessage<N> {
+        TxMessage::ConfigureAdvancedBurst(msg)
+    }
+}
+
 impl ConfigureAdvancedBurst {
     /// Constructs a new `ConfigureAdvancedBurst`.
     pub fn new(
@@ -787,8 +818,8 @@ impl ConfigureAdvancedBurst {
         };
 
         let buf = match buf {
-            Some(x) => x,
-            None => return Ok(msg),
+            Some(x) if !x.is_empty() => x,
+            _ => return Ok(msg),
         };
 
         if buf.len() < 2 {
@@ -871,6 +902,25 @@ pub struct ConfigureEventFilter {
     _reserved1: ReservedZeroes<packed_bits::Bits<8>>,
 }
 
+impl ConfigureEventFilter {
+    /// Returns true if events with `code` are suppressed by the radio
+    pub fn filters(&self, code: MessageCode) -> bool {
+        match code {
+            MessageCode::EventRxSearchTimeout => self.filter_event_rx_search_timeout,
+            MessageCode::EventRxFail => self.filter_event_rx_fail,
+            MessageCode::EventTx => self.filter_event_tx,
+            MessageCode::EventTransferRxFailed => self.filter_event_transfer_rx_failed,
+            MessageCode::EventTransferTxCompleted => self.filter_event_transfer_tx_completed,
+            MessageCode::EventTransferTxFailed => self.filter_event_transfer_tx_failed,
+            MessageCode::EventChannelClosed => self.filter_event_channel_closed,
+            MessageCode::EventRxFailGoToSearch => self.filter_event_rx_fail_go_to_search,
+            MessageCode::EventChannelCollision => self.filter_event_channel_collision,
+            MessageCode::EventTransferTxStart => self.filter_event_transfer_tx_start,
+            _ => false,
+        }
+    }
+}
+
 /// Represents a Configure Selective Data Updates message (0x7A)
 #[derive(PackedStruct, AntTx, new, Clone, Copy, Debug, Default, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "2")]
@@ -878,12 +928,20 @@ pub struct ConfigureSelectiveDataUpdates {
     /// Channel to be configured
     #[packed_field(bytes = "0")]
     pub channel_number: u8,
+    /// SDU mask number applied to the channel, or [ConfigureSelectiveDataUpdates::DISABLED]
     #[packed_field(bytes = "1")]
     pub selected_data: u8,
-    // TODO figure out this field and write a test, it has reserved bits that are changeable when
-    // invalidating a config
 }
-// TODO test
+
+impl ConfigureSelectiveDataUpdates {
+    /// `selected_data` value that turns selective data updates off for the channel
+    pub const DISABLED: u8 = 0xFF;
+
+    /// Stop filtering data on `channel_number`
+    pub fn disabled(channel_number: u8) -> Self {
+        Self::new(channel_number, Self::DISABLED)
+    }
+}
 
 /// Represents a Set Selective Data Update Mask message (0x7B)
 #[derive(PackedStruct, AntTx, new, Clone, Copy, Debug, Default, PartialEq)]
@@ -1028,6 +1086,163 @@ pub struct StoreEncryptionKeyInNvm {
 
 // TODO SetUsbDescriptorString
 
+/// Highest RF channel offset from 2400MHz the radio accepts
+pub const MAX_RF_FREQUENCY: u8 = 124;
+/// Highest transmit power setting
+pub const MAX_TRANSMIT_POWER: u8 = 4;
+/// Highest proximity search threshold bin
+pub const MAX_PROXIMITY_SEARCH_THRESHOLD: u8 = 10;
+/// Suppression cycle value for full high priority search suppression
+pub const MAX_SUPPRESSION_CYCLE: u8 = 5;
+
+/// Reasons a configuration message is rejected before it is sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConfigError {
+    /// Channel number is not below the radio's channel count
+    InvalidChannel { channel: u8, max_channels: u8 },
+    /// Network number is not below the radio's network count
+    InvalidNetwork { network: u8, max_networks: u8 },
+    /// Value outside of the range accepted by the radio
+    OutOfRange { value: u16, min: u16, max: u16 },
+}
+
+pub(crate) fn check_range(value: u8, min: u8, max: u8) -> Result<(), ConfigError> {
+    if (min..=max).contains(&value) {
+        return Ok(());
+    }
+    Err(ConfigError::OutOfRange {
+        value: value.into(),
+        min: min.into(),
+        max: max.into(),
+    })
+}
+
+impl ChannelPeriod {
+    /// Constructs a new `ChannelPeriod`, rejecting a zero period
+    pub fn try_new(channel_number: u8, channel_period: u16) -> Result<Self, ConfigError> {
+        if channel_period == 0 {
+            return Err(ConfigError::OutOfRange {
+                value: 0,
+                min: 1,
+                max: u16::MAX,
+            });
+        }
+        Ok(Self::new(channel_number, channel_period))
+    }
+}
+
+impl ChannelRfFrequency {
+    /// Constructs a new `ChannelRfFrequency`, rejecting frequencies above [MAX_RF_FREQUENCY]
+    pub fn try_new(channel_number: u8, rf_frequency: u8) -> Result<Self, ConfigError> {
+        check_range(rf_frequency, 0, MAX_RF_FREQUENCY)?;
+        Ok(Self::new(channel_number, rf_frequency))
+    }
+}
+
+impl TransmitPower {
+    /// Constructs a new `TransmitPower`, rejecting powers above [MAX_TRANSMIT_POWER]
+    pub fn try_new(tx_power: u8) -> Result<Self, ConfigError> {
+        check_range(tx_power, 0, MAX_TRANSMIT_POWER)?;
+        Ok(Self::new(tx_power))
+    }
+}
+
+impl SetChannelTransmitPower {
+    /// Constructs a new `SetChannelTransmitPower`, rejecting powers above [MAX_TRANSMIT_POWER]
+    pub fn try_new(channel_number: u8, transmit_power: u8) -> Result<Self, ConfigError> {
+        check_range(transmit_power, 0, MAX_TRANSMIT_POWER)?;
+        Ok(Self::new(channel_number, transmit_power))
+    }
+}
+
+impl FrequencyAgility {
+    /// Constructs a new `FrequencyAgility`, rejecting frequencies above [MAX_RF_FREQUENCY]
+    pub fn try_new(
+        channel_number: u8,
+        frequency_1: u8,
+        frequency_2: u8,
+        frequency_3: u8,
+    ) -> Result<Self, ConfigError> {
+        for frequency in [frequency_1, frequency_2, frequency_3] {
+            check_range(frequency, 0, MAX_RF_FREQUENCY)?;
+        }
+        Ok(Self::new(
+            channel_number,
+            frequency_1,
+            frequency_2,
+            frequency_3,
+        ))
+    }
+}
+
+impl ProximitySearch {
+    /// Constructs a new `ProximitySearch`, rejecting thresholds above
+    /// [MAX_PROXIMITY_SEARCH_THRESHOLD]
+    pub fn try_new(channel_number: u8, search_threshold: u8) -> Result<Self, ConfigError> {
+        check_range(search_threshold, 0, MAX_PROXIMITY_SEARCH_THRESHOLD)?;
+        Ok(Self::new(channel_number, search_threshold))
+    }
+}
+
+impl HighDutySearchSuppressionCycle {
+    /// Constructs a new `HighDutySearchSuppressionCycle`, rejecting cycles above
+    /// [MAX_SUPPRESSION_CYCLE]
+    pub fn try_new(suppression_cycle: u8) -> Result<Self, ConfigError> {
+        check_range(suppression_cycle, 0, MAX_SUPPRESSION_CYCLE)?;
+        Ok(Self::new(suppression_cycle))
+    }
+}
+
+impl AssignChannel {
+    /// Verify the channel and network exist on the radio
+    pub fn validate(&self, capabilities: &RadioCapabilities) -> Result<(), ConfigError> {
+        capabilities.check_channel(self.data.channel_number)?;
+        capabilities.check_network(self.data.network_number)
+    }
+}
+
+impl SetNetworkKey {
+    /// Verify the network exists on the radio
+    pub fn validate(&self, capabilities: &RadioCapabilities) -> Result<(), ConfigError> {
+        capabilities.check_network(self.network_number)
+    }
+}
+
+impl Set128BitNetworkKey {
+    /// Verify the network exists on the radio
+    pub fn validate(&self, capabilities: &RadioCapabilities) -> Result<(), ConfigError> {
+        capabilities.check_network(self.network_number)
+    }
+}
+
+// Messages whose only radio dependent limit is the channel number
+macro_rules! impl_validate_channel {
+    ($($msg:ty),* $(,)?) => {
+        $(
+            impl $msg {
+                /// Verify the channel exists on the radio
+                pub fn validate(&self, capabilities: &RadioCapabilities) -> Result<(), ConfigError> {
+                    capabilities.check_channel(self.channel_number)
+                }
+            }
+        )*
+    };
+}
+
+impl_validate_channel!(
+    UnAssignChannel,
+    ChannelId,
+    ChannelPeriod,
+    SearchTimeout,
+    ChannelRfFrequency,
+    SetChannelTransmitPower,
+    LowPrioritySearchTimeout,
+    FrequencyAgility,
+    ProximitySearch,
+    ChannelSearchPriority,
+    ChannelSearchSharing,
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1275,8 +1490,7 @@ mod tests {
 
     #[test]
     fn configure_advanced_burst() {
-        use crate::messages::MAX_MESSAGE_DATA_SIZE;
-        let mut buf: [u8; MAX_MESSAGE_DATA_SIZE] = [0; MAX_MESSAGE_DATA_SIZE];
+        let mut buf = [0; 16];
         let packed = ConfigureAdvancedBurst::new(
             true,
             AdvancedBurstMaxPacketLength::Max24Byte,
@@ -1298,6 +1512,26 @@ mod tests {
         assert_eq!(packed.pack().unwrap(), [0, 0x81, 0x3]);
     }
 
+    #[test]
+    fn event_filter_codes() {
+        let filter = ConfigureEventFilter::new(
+            true, false, true, false, false, false, false, false, false, true,
+        );
+        assert!(filter.filters(MessageCode::EventRxSearchTimeout));
+        assert!(filter.filters(MessageCode::EventTx));
+        assert!(filter.filters(MessageCode::EventTransferTxStart));
+        assert!(!filter.filters(MessageCode::EventRxFail));
+        assert!(!filter.filters(MessageCode::ResponseNoError));
+    }
+
+    #[test]
+    fn configure_selective_data_updates() {
+        let packed = ConfigureSelectiveDataUpdates::new(2, 3);
+        assert_eq!(packed.pack().unwrap(), [2, 3]);
+        let packed = ConfigureSelectiveDataUpdates::disabled(1);
+        assert_eq!(packed.pack().unwrap(), [1, 0xFF]);
+    }
+
     #[test]
     fn set_selective_data_update_mask() {
         let packed = SetSelectiveDataUpdateMask::new(0, [1, 2, 3, 4, 5, 6, 7, 8]);
@@ -1370,4 +1604,53 @@ mod tests {
             [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]
         );
     }
+
+    #[test]
+    fn checked_constructors() {
+        assert!(ChannelRfFrequency::try_new(0, 124).is_ok());
+        assert_eq!(
+            ChannelRfFrequency::try_new(0, 125),
+            Err(ConfigError::OutOfRange {
+                value: 125,
+                min: 0,
+                max: 124
+            })
+        );
+        assert!(ChannelPeriod::try_new(0, 0).is_err());
+        assert!(ChannelPeriod::try_new(0, 8070).is_ok());
+        assert!(TransmitPower::try_new(5).is_err());
+        assert!(SetChannelTransmitPower::try_new(1, 4).is_ok());
+        assert!(SetChannelTransmitPower::try_new(1, 5).is_err());
+        assert!(FrequencyAgility::try_new(0, 3, 200, 75).is_err());
+        assert!(ProximitySearch::try_new(0, 11).is_err());
+        assert!(HighDutySearchSuppressionCycle::try_new(6).is_err());
+    }
+
+    #[test]
+    fn capability_limits() {
+        use crate::messages::requested_response::Capabilities;
+
+        // 16 channels, 4 networks
+        let caps: RadioCapabilities = Capabilities::unpack_from_slice(&[16, 4, 0x15, 0x82])
+            .unwrap()
+            .into();
+        let msg = AssignChannel::new(15, ChannelType::BidirectionalSlave, 3, None);
+        assert_eq!(msg.validate(&caps), Ok(()));
+        let msg = AssignChannel::new(16, ChannelType::BidirectionalSlave, 0, None);
+        assert_eq!(
+            msg.validate(&caps),
+            Err(ConfigError::InvalidChannel {
+                channel: 16,
+                max_channels: 16
+            })
+        );
+        assert_eq!(
+            SetNetworkKey::new(4, [0; 8]).validate(&caps),
+            Err(ConfigError::InvalidNetwork {
+                network: 4,
+                max_networks: 4
+            })
+        );
+        assert!(ChannelPeriod::new(20, 8070).validate(&caps).is_err());
+    }
 }
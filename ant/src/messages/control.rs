@@ -42,6 +42,11 @@ pub enum RequestableMessageId {
     SerialNumber = 0x61,
     EventBufferConfiguration = 0x74,
     AdvancedBurstCapabilities = 0x78,
+    EventFilter = 0x79,
+    /// Channel field holds the mask number
+    SelectiveDataUpdateMaskSetting = 0x7B,
+    /// Requires the NVM region to read
+    UserNvm = 0x7C,
 }
 
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq)]
@@ -116,6 +121,25 @@ impl TransmitableMessage for OpenRxScanMode {
     }
 }
 
+impl<const N: usize> From<OpenRxScanMode> for TxMessage<N> {
+    fn from(msg: OpenRxScanMode) -> TxMessage<N> {
+        TxMessage::OpenRxScanMode(msg)
+    }
+}
+
+impl OpenRxScanMode {
+    pub(crate) fn unpack_from_slice(buf: &[u8]) -> Result<OpenRxScanMode, PackingError> {
+        match buf {
+            [_] => Ok(OpenRxScanMode::new(None)),
+            [_, sync_packets] => Ok(OpenRxScanMode::new(Some(*sync_packets != 0))),
+            _ => Err(PackingError::BufferSizeMismatch {
+                expected: 2,
+                actual: buf.len(),
+            }),
+        }
+    }
+}
+
 #[derive(PackedStruct, AntTx, new, Clone, Copy, Debug, Default, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "1")]
 pub struct SleepMessage {
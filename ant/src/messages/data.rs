@@ -6,30 +6,17 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::messages::{TransmitableMessage, TxMessage, TxMessageData, TxMessageId};
+use crate::messages::{
+    TransmitableMessage, TxMessage, TxMessageData, TxMessageId, DEFAULT_PAYLOAD_CAPACITY,
+};
 use arrayvec::ArrayVec;
-use const_utils::{max, min};
 use derive_new::new;
-use konst::{option::unwrap_or, primitive::parse_usize, unwrap_ctx};
 use packed_struct::prelude::*;
 
 pub use crate::messages::config::{
     DeviceType, TransmissionChannelType, TransmissionGlobalDataPages, TransmissionType,
 };
 
-// TODO make this crash compilation if out of bounds rather than silently correct
-// TODO skip this if NVM is enabled
-pub(crate) const ADVANCED_BURST_BUFFER_SIZE: usize = min(
-    max(
-        unwrap_ctx!(parse_usize(unwrap_or!(
-            option_env!("ADV_BURST_BUF_SIZE"),
-            "64"
-        ))),
-        24,
-    ),
-    254,
-);
-
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "4")]
 pub struct ChannelIdOutput {
@@ -109,6 +96,28 @@ impl RssiOutput {
             measurement_value,
         })
     }
+
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        let (type_buf, buf) = buf.split_first_mut().ok_or(PackingError::BufferTooSmall)?;
+        *type_buf = self.measurement_type.to_primitive();
+        match (self.measurement_type, self.measurement_value) {
+            (RssiMeasurementType::Agc, RssiMeasurementValue::Agc(agc)) => {
+                agc.pack_to_slice(
+                    buf.get_mut(..MeasurementValueAgc::PACKING_SIZE - 1)
+                        .ok_or(PackingError::BufferTooSmall)?,
+                )?;
+                Ok(MeasurementValueAgc::PACKING_SIZE)
+            }
+            (RssiMeasurementType::Dbm, RssiMeasurementValue::Dbm(dbm)) => {
+                dbm.pack_to_slice(
+                    buf.get_mut(..MeasurementValueDbm::PACKING_SIZE - 1)
+                        .ok_or(PackingError::BufferTooSmall)?,
+                )?;
+                Ok(MeasurementValueDbm::PACKING_SIZE)
+            }
+            _ => Err(PackingError::InvalidValue),
+        }
+    }
 }
 
 #[derive(PackedStruct, Clone, Copy, Debug, PartialEq)]
@@ -148,6 +157,14 @@ pub struct ExtendedInfo {
 }
 
 impl ExtendedInfo {
+    /// Signal strength in dBm, None if the radio did not report it or reported an AGC reading
+    pub fn rssi_dbm(&self) -> Option<i8> {
+        match self.rssi_output?.measurement_value {
+            RssiMeasurementValue::Dbm(value) => Some(value.rssi_value),
+            RssiMeasurementValue::Agc(_) => None,
+        }
+    }
+
     pub(crate) fn unpack_from_slice(data: &[u8]) -> Result<Option<ExtendedInfo>, PackingError> {
         if data.is_empty() {
             return Ok(None);
@@ -237,6 +254,54 @@ impl ExtendedInfo {
 
         Ok(Some(extended_info))
     }
+
+    /// Serialize the extended info, the flag byte is derived from which outputs are present
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        let flag_byte = FlagByte {
+            channel_id_output: self.channel_id_output.is_some(),
+            rssi_output: self.rssi_output.is_some(),
+            timestamp_output: self.timestamp_output.is_some(),
+            _reserved: Default::default(),
+        };
+        flag_byte.pack_to_slice(
+            buf.get_mut(..FlagByte::PACKING_SIZE)
+                .ok_or(PackingError::BufferTooSmall)?,
+        )?;
+        let mut len = FlagByte::PACKING_SIZE;
+
+        if let Some(channel_id) = self.channel_id_output {
+            channel_id.pack_to_slice(
+                buf.get_mut(len..len + ChannelIdOutput::PACKING_SIZE)
+                    .ok_or(PackingError::BufferTooSmall)?,
+            )?;
+            len += ChannelIdOutput::PACKING_SIZE;
+        }
+
+        if let Some(rssi) = self.rssi_output {
+            len += rssi.pack_to_slice(buf.get_mut(len..).ok_or(PackingError::BufferTooSmall)?)?;
+        }
+
+        if let Some(timestamp) = self.timestamp_output {
+            timestamp.pack_to_slice(
+                buf.get_mut(len..len + TimestampOutput::PACKING_SIZE)
+                    .ok_or(PackingError::BufferTooSmall)?,
+            )?;
+            len += TimestampOutput::PACKING_SIZE;
+        }
+
+        Ok(len)
+    }
+
+    /// Helper for the data messages, serialize the extended info if any after the payload
+    fn pack_optional(
+        extended_info: &Option<ExtendedInfo>,
+        buf: &mut [u8],
+    ) -> Result<usize, PackingError> {
+        match extended_info {
+            Some(info) => info.pack_to_slice(buf),
+            None => Ok(0),
+        }
+    }
 }
 
 #[derive(PackedStruct, Copy, Clone, Debug, Default, PartialEq)]
@@ -271,14 +336,14 @@ impl TransmitableMessage for BroadcastData {
     }
 }
 
-impl From<BroadcastData> for TxMessage {
-    fn from(msg: BroadcastData) -> TxMessage {
+impl<const N: usize> From<BroadcastData> for TxMessage<N> {
+    fn from(msg: BroadcastData) -> TxMessage<N> {
         TxMessage::BroadcastData(msg)
     }
 }
 
-impl From<BroadcastData> for TxMessageData {
-    fn from(msg: BroadcastData) -> TxMessageData {
+impl<const N: usize> From<BroadcastData> for TxMessageData<N> {
+    fn from(msg: BroadcastData) -> TxMessageData<N> {
         TxMessageData::BroadcastData(msg)
     }
 }
@@ -308,6 +373,17 @@ impl BroadcastData {
             extended_info: ExtendedInfo::unpack_from_slice(extended)?,
         })
     }
+
+    /// Serialize the message as received from the radio, including any extended info
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        if buf.len() < BroadcastDataPayload::PACKING_SIZE {
+            return Err(PackingError::BufferTooSmall);
+        }
+        let (payload_buf, extended_buf) = buf.split_at_mut(BroadcastDataPayload::PACKING_SIZE);
+        self.payload.pack_to_slice(payload_buf)?;
+        Ok(BroadcastDataPayload::PACKING_SIZE
+            + ExtendedInfo::pack_optional(&self.extended_info, extended_buf)?)
+    }
 }
 
 // Same byte payload, just different name
@@ -331,14 +407,14 @@ impl TransmitableMessage for AcknowledgedData {
     }
 }
 
-impl From<AcknowledgedData> for TxMessage {
-    fn from(msg: AcknowledgedData) -> TxMessage {
+impl<const N: usize> From<AcknowledgedData> for TxMessage<N> {
+    fn from(msg: AcknowledgedData) -> TxMessage<N> {
         TxMessage::AcknowledgedData(msg)
     }
 }
 
-impl From<AcknowledgedData> for TxMessageData {
-    fn from(msg: AcknowledgedData) -> TxMessageData {
+impl<const N: usize> From<AcknowledgedData> for TxMessageData<N> {
+    fn from(msg: AcknowledgedData) -> TxMessageData<N> {
         TxMessageData::AcknowledgedData(msg)
     }
 }
@@ -368,6 +444,17 @@ impl AcknowledgedData {
             extended_info: ExtendedInfo::unpack_from_slice(extended)?,
         })
     }
+
+    /// Serialize the message as received from the radio, including any extended info
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        if buf.len() < BroadcastDataPayload::PACKING_SIZE {
+            return Err(PackingError::BufferTooSmall);
+        }
+        let (payload_buf, extended_buf) = buf.split_at_mut(BroadcastDataPayload::PACKING_SIZE);
+        self.payload.pack_to_slice(payload_buf)?;
+        Ok(BroadcastDataPayload::PACKING_SIZE
+            + ExtendedInfo::pack_optional(&self.extended_info, extended_buf)?)
+    }
 }
 
 #[derive(PackedStruct, new, Clone, Copy, Debug, Default, PartialEq)]
@@ -410,8 +497,8 @@ impl TransmitableMessage for BurstTransferData {
     }
 }
 
-impl From<BurstTransferData> for TxMessage {
-    fn from(msg: BurstTransferData) -> TxMessage {
+impl<const N: usize> From<BurstTransferData> for TxMessage<N> {
+    fn from(msg: BurstTransferData) -> TxMessage<N> {
         TxMessage::BurstTransferData(msg)
     }
 }
@@ -441,20 +528,32 @@ impl BurstTransferData {
             extended_info: ExtendedInfo::unpack_from_slice(extended)?,
         })
     }
+
+    /// Serialize the message as received from the radio, including any extended info
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        if buf.len() < BurstTransferDataPayload::PACKING_SIZE {
+            return Err(PackingError::BufferTooSmall);
+        }
+        let (payload_buf, extended_buf) = buf.split_at_mut(BurstTransferDataPayload::PACKING_SIZE);
+        self.payload.pack_to_slice(payload_buf)?;
+        Ok(BurstTransferDataPayload::PACKING_SIZE
+            + ExtendedInfo::pack_optional(&self.extended_info, extended_buf)?)
+    }
 }
 
+/// Advanced burst packet holding up to `N` bytes of data
+///
+/// Packets are 8, 16 or 24 bytes depending on the negotiated configuration, a radio delivering
+/// packets larger than `N` will fail to unpack.
 #[derive(Clone, Debug, Default, PartialEq)]
-pub struct AdvancedBurstData {
+pub struct AdvancedBurstData<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     pub channel_sequence: ChannelSequence,
-    pub data: ArrayVec<u8, ADVANCED_BURST_BUFFER_SIZE>,
+    pub data: ArrayVec<u8, N>,
 }
 
-impl AdvancedBurstData {
+impl<const N: usize> AdvancedBurstData<N> {
     /// Constructs a new `AdvancedBurstData`.
-    pub fn new(
-        channel_sequence: ChannelSequence,
-        data: ArrayVec<u8, ADVANCED_BURST_BUFFER_SIZE>,
-    ) -> Self {
+    pub fn new(channel_sequence: ChannelSequence, data: ArrayVec<u8, N>) -> Self {
         Self {
             channel_sequence,
             data,
@@ -481,14 +580,17 @@ impl AdvancedBurstData {
     }
 }
 
-impl TransmitableMessage for AdvancedBurstData {
+impl<const N: usize> TransmitableMessage for AdvancedBurstData<N> {
     fn serialize_message(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
         let sequence_size = ChannelSequence::packed_bytes_size(None)?;
         let len = sequence_size + self.data.len();
+        if buf.len() < len {
+            return Err(PackingError::BufferTooSmall);
+        }
 
         self.channel_sequence
             .pack_to_slice(&mut buf[..sequence_size])?;
-        buf[sequence_size..sequence_size + self.data.len()].copy_from_slice(&self.data);
+        buf[sequence_size..len].copy_from_slice(&self.data);
         Ok(len)
     }
     fn get_tx_msg_id(&self) -> TxMessageId {
@@ -496,8 +598,8 @@ impl TransmitableMessage for AdvancedBurstData {
     }
 }
 
-impl From<AdvancedBurstData> for TxMessage {
-    fn from(msg: AdvancedBurstData) -> TxMessage {
+impl<const N: usize> From<AdvancedBurstData<N>> for TxMessage<N> {
+    fn from(msg: AdvancedBurstData<N>) -> TxMessage<N> {
         TxMessage::AdvancedBurstData(msg)
     }
 }
@@ -687,7 +789,7 @@ mod tests {
     #[test]
     fn advanced_burst_data() {
         let unpacked = AdvancedBurstData::unpack_from_slice(&[10, 1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
-        let mut buf = ArrayVec::<u8, ADVANCED_BURST_BUFFER_SIZE>::new();
+        let mut buf = ArrayVec::<u8, DEFAULT_PAYLOAD_CAPACITY>::new();
         [1, 2, 3, 4, 5, 6, 7, 8].iter().for_each(|x| buf.push(*x));
         assert_eq!(
             unpacked,
@@ -699,6 +801,8 @@ mod tests {
                 data: buf,
             }
         );
+        assert!(AdvancedBurstData::<8>::unpack_from_slice(&[10, 1, 2, 3, 4, 5, 6, 7, 8]).is_ok());
+        assert!(AdvancedBurstData::<8>::unpack_from_slice(&[10; 17]).is_err());
         // TODO TX
     }
 }
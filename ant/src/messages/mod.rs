@@ -6,6 +6,7 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::drivers::{calculate_checksum, CHECKSUM_SIZE, HEADER_SIZE};
 use crate::messages::config::{
     AddChannelIdToList, AddEncryptionIdToList, AssignChannel, ChannelId, ChannelPeriod,
     ChannelRfFrequency, ChannelSearchPriority, ChannelSearchSharing, ConfigEncryptionIdList,
@@ -18,12 +19,12 @@ use crate::messages::config::{
     SetEncryptionInfoUserInformationString, SetEncryptionKey, SetNetworkKey,
     SetSelectiveDataUpdateMask, StoreEncryptionKeyInNvm, TransmitPower, UnAssignChannel,
 };
+use arrayvec::ArrayVec;
 use channel::{ChannelEvent, ChannelResponse};
-use control::{CloseChannel, OpenChannel, RequestMessage, ResetSystem, SleepMessage};
-use data::{
-    AcknowledgedData, AdvancedBurstData, BroadcastData, BurstTransferData,
-    ADVANCED_BURST_BUFFER_SIZE,
+use control::{
+    CloseChannel, OpenChannel, OpenRxScanMode, RequestMessage, ResetSystem, SleepMessage,
 };
+use data::{AcknowledgedData, AdvancedBurstData, BroadcastData, BurstTransferData};
 use notifications::{SerialErrorMessage, StartUpMessage};
 use packed_struct::prelude::*;
 use requested_response::{
@@ -41,12 +42,21 @@ pub mod notifications;
 pub mod requested_response;
 pub mod test_mode;
 
-// TODO fixup
-pub(crate) const MAX_MESSAGE_DATA_SIZE: usize = ADVANCED_BURST_BUFFER_SIZE + 1;
+/// Default capacity of variable length message fields
+///
+/// Messages carrying variable length data, e.g. [AdvancedBurstData], [AntVersion] and [UserNvm],
+/// take their capacity as a const generic parameter `N`, this is the value used when it is
+/// omitted.
+pub const DEFAULT_PAYLOAD_CAPACITY: usize = 64;
+
+/// Largest possible frame on the wire, the length field is a single byte
+pub const MAX_FRAME_SIZE: usize = HEADER_SIZE + u8::MAX as usize + CHECKSUM_SIZE;
 
 /// All supported RX messages
+///
+/// `N` is the capacity of variable length fields, see [DEFAULT_PAYLOAD_CAPACITY].
 #[derive(Clone, PartialEq, Debug)]
-pub enum RxMessage {
+pub enum RxMessage<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     // Notification Messages
     StartUpMessage(StartUpMessage),
     // #define SERIAL_ERROR_MESSAGE                0xAE
@@ -54,7 +64,7 @@ pub enum RxMessage {
     BroadcastData(BroadcastData),
     AcknowledgedData(AcknowledgedData),
     BurstTransferData(BurstTransferData),
-    AdvancedBurstData(AdvancedBurstData),
+    AdvancedBurstData(AdvancedBurstData<N>),
     // Channel Messages
     ChannelEvent(ChannelEvent),
     ChannelResponse(ChannelResponse),
@@ -62,7 +72,7 @@ pub enum RxMessage {
     // Requested Response Messages
     ChannelStatus(ChannelStatus),
     ChannelId(ChannelId),
-    AntVersion(AntVersion),
+    AntVersion(AntVersion<N>),
     Capabilities(Capabilities),
     SerialNumber(SerialNumber),
     EventBufferConfiguration(EventBufferConfiguration),
@@ -70,7 +80,7 @@ pub enum RxMessage {
     AdvancedBurstCurrentConfiguration(AdvancedBurstCurrentConfiguration),
     EventFilter(EventFilter),
     SelectiveDataUpdateMaskSetting(SelectiveDataUpdateMaskSetting),
-    UserNvm(UserNvm),
+    UserNvm(UserNvm<N>),
     EncryptionModeParameters(EncryptionModeParameters),
     // Extended Data Messages (Legacy)
     // #define EXTENDED_BROADCAST_DATA             0x5D
@@ -78,8 +88,75 @@ pub enum RxMessage {
     // #define EXTENDED_BURST_DATA                 0x5F
 }
 
-#[derive(Clone, Debug)]
-pub enum TxMessage {
+impl<const N: usize> RxMessage<N> {
+    /// Serialize the message body as the radio would send it, returns the bytes written
+    pub fn serialize_message(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        match self {
+            RxMessage::StartUpMessage(msg) => pack_fixed(msg, buf),
+            RxMessage::BroadcastData(msg) => msg.pack_to_slice(buf),
+            RxMessage::AcknowledgedData(msg) => msg.pack_to_slice(buf),
+            RxMessage::BurstTransferData(msg) => msg.pack_to_slice(buf),
+            RxMessage::AdvancedBurstData(msg) => msg.serialize_message(buf),
+            RxMessage::ChannelEvent(msg) => msg.pack_to_slice(buf),
+            RxMessage::ChannelResponse(msg) => pack_fixed(msg, buf),
+            RxMessage::SerialErrorMessage(msg) => pack_fixed(msg, buf),
+            RxMessage::ChannelStatus(msg) => pack_fixed(msg, buf),
+            RxMessage::ChannelId(msg) => pack_fixed(msg, buf),
+            RxMessage::AntVersion(msg) => msg.pack_to_slice(buf),
+            RxMessage::Capabilities(msg) => msg.pack_to_slice(buf),
+            RxMessage::SerialNumber(msg) => pack_fixed(msg, buf),
+            RxMessage::EventBufferConfiguration(msg) => pack_fixed(msg, buf),
+            RxMessage::AdvancedBurstCapabilities(msg) => pack_fixed(msg, buf),
+            RxMessage::AdvancedBurstCurrentConfiguration(msg) => msg.serialize_message(buf),
+            RxMessage::EventFilter(msg) => pack_fixed(msg, buf),
+            RxMessage::SelectiveDataUpdateMaskSetting(msg) => pack_fixed(msg, buf),
+            RxMessage::UserNvm(msg) => msg.pack_to_slice(buf),
+            RxMessage::EncryptionModeParameters(msg) => msg.pack_to_slice(buf),
+        }
+    }
+
+    pub fn get_rx_msg_id(&self) -> RxMessageId {
+        match self {
+            RxMessage::StartUpMessage(_) => RxMessageId::StartUpMessage,
+            RxMessage::BroadcastData(_) => RxMessageId::BroadcastData,
+            RxMessage::AcknowledgedData(_) => RxMessageId::AcknowledgedData,
+            RxMessage::BurstTransferData(_) => RxMessageId::BurstTransferData,
+            RxMessage::AdvancedBurstData(_) => RxMessageId::AdvancedBurstData,
+            RxMessage::ChannelEvent(_) => RxMessageId::ChannelEvent,
+            RxMessage::ChannelResponse(_) => RxMessageId::ChannelEvent,
+            RxMessage::SerialErrorMessage(_) => RxMessageId::SerialErrorMessage,
+            RxMessage::ChannelStatus(_) => RxMessageId::ChannelStatus,
+            RxMessage::ChannelId(_) => RxMessageId::ChannelId,
+            RxMessage::AntVersion(_) => RxMessageId::AntVersion,
+            RxMessage::Capabilities(_) => RxMessageId::Capabilities,
+            RxMessage::SerialNumber(_) => RxMessageId::SerialNumber,
+            RxMessage::EventBufferConfiguration(_) => RxMessageId::EventBufferConfiguration,
+            RxMessage::AdvancedBurstCapabilities(_) => RxMessageId::AdvancedBurstCapabilities,
+            RxMessage::AdvancedBurstCurrentConfiguration(_) => {
+                RxMessageId::AdvancedBurstCapabilities
+            }
+            RxMessage::EventFilter(_) => RxMessageId::EventFilter,
+            RxMessage::SelectiveDataUpdateMaskSetting(_) => {
+                RxMessageId::SelectiveDataUpdateMaskSetting
+            }
+            RxMessage::UserNvm(_) => RxMessageId::UserNvm,
+            RxMessage::EncryptionModeParameters(_) => RxMessageId::EncryptionModeParameters,
+        }
+    }
+}
+
+/// Pack a fixed size message into the front of `buf`
+fn pack_fixed<P: PackedStructSlice>(msg: &P, buf: &mut [u8]) -> Result<usize, PackingError> {
+    let len = P::packed_bytes_size(Some(msg))?;
+    msg.pack_to_slice(buf.get_mut(..len).ok_or(PackingError::BufferTooSmall)?)?;
+    Ok(len)
+}
+
+/// All supported TX messages
+///
+/// `N` is the capacity of variable length fields, see [DEFAULT_PAYLOAD_CAPACITY].
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxMessage<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     UnAssignChannel(UnAssignChannel),
     AssignChannel(AssignChannel),
     ChannelId(ChannelId),
@@ -124,24 +201,24 @@ pub enum TxMessage {
     OpenChannel(OpenChannel),
     CloseChannel(CloseChannel),
     RequestMessage(RequestMessage),
-    // OpenRxScanMode(OpenRxScanMode),
+    OpenRxScanMode(OpenRxScanMode),
     SleepMessage(SleepMessage),
     BroadcastData(BroadcastData),
     AcknowledgedData(AcknowledgedData),
     BurstTransferData(BurstTransferData),
-    AdvancedBurstData(AdvancedBurstData),
+    AdvancedBurstData(AdvancedBurstData<N>),
     CwInit(CwInit),
     CwTest(CwTest),
 }
 
 // Hack to allow channels to recycle memory, not for actual use
-impl Default for TxMessage {
-    fn default() -> TxMessage {
+impl<const N: usize> Default for TxMessage<N> {
+    fn default() -> TxMessage<N> {
         TxMessage::UnAssignChannel(UnAssignChannel { channel_number: 0 })
     }
 }
 
-impl TransmitableMessage for TxMessage {
+impl<const N: usize> TransmitableMessage for TxMessage<N> {
     fn serialize_message(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
         match self {
             TxMessage::UnAssignChannel(uc) => uc.serialize_message(buf),
@@ -188,7 +265,7 @@ impl TransmitableMessage for TxMessage {
             TxMessage::OpenChannel(oc) => oc.serialize_message(buf),
             TxMessage::CloseChannel(cc) => cc.serialize_message(buf),
             TxMessage::RequestMessage(rm) => rm.serialize_message(buf),
-            // TxMessage::OpenRxScanMode(or) => or.serialize_message(buf),
+            TxMessage::OpenRxScanMode(or) => or.serialize_message(buf),
             TxMessage::SleepMessage(sm) => sm.serialize_message(buf),
             TxMessage::BroadcastData(bd) => bd.serialize_message(buf),
             TxMessage::AcknowledgedData(ad) => ad.serialize_message(buf),
@@ -245,7 +322,7 @@ impl TransmitableMessage for TxMessage {
             TxMessage::OpenChannel(oc) => oc.get_tx_msg_id(),
             TxMessage::CloseChannel(cc) => cc.get_tx_msg_id(),
             TxMessage::RequestMessage(rm) => rm.get_tx_msg_id(),
-            // TODO TxMessage::OpenRxScanMode(or) => or.serialize_message(buf),
+            TxMessage::OpenRxScanMode(or) => or.get_tx_msg_id(),
             TxMessage::SleepMessage(sm) => sm.get_tx_msg_id(),
             TxMessage::BroadcastData(bd) => bd.get_tx_msg_id(),
             TxMessage::AcknowledgedData(ad) => ad.get_tx_msg_id(),
@@ -257,14 +334,14 @@ impl TransmitableMessage for TxMessage {
     }
 }
 
-pub enum TxMessageData {
+pub enum TxMessageData<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     BroadcastData(BroadcastData),
     AcknowledgedData(AcknowledgedData),
     BurstTransferData(BurstTransferData),
-    AdvancedBurstData(AdvancedBurstData),
+    AdvancedBurstData(AdvancedBurstData<N>),
 }
 
-impl TxMessageData {
+impl<const N: usize> TxMessageData<N> {
     /// Helper for profiles to set channel if relevant on external Tx requests
     pub(crate) fn set_channel(&mut self, channel: u8) {
         match self {
@@ -280,8 +357,8 @@ impl TxMessageData {
     }
 }
 
-impl From<TxMessageData> for TxMessage {
-    fn from(msg: TxMessageData) -> TxMessage {
+impl<const N: usize> From<TxMessageData<N>> for TxMessage<N> {
+    fn from(msg: TxMessageData<N>) -> TxMessage<N> {
         match msg {
             TxMessageData::BroadcastData(bd) => bd.into(),
             TxMessageData::AcknowledgedData(ad) => ad.into(),
@@ -349,8 +426,8 @@ impl TxMessageChannelConfig {
     }
 }
 
-impl From<TxMessageChannelConfig> for TxMessage {
-    fn from(msg: TxMessageChannelConfig) -> TxMessage {
+impl<const N: usize> From<TxMessageChannelConfig> for TxMessage<N> {
+    fn from(msg: TxMessageChannelConfig) -> TxMessage<N> {
         match msg {
             TxMessageChannelConfig::UnAssignChannel(uc) => uc.into(),
             TxMessageChannelConfig::AssignChannel(ac) => ac.into(),
@@ -379,16 +456,68 @@ impl From<TxMessageChannelConfig> for TxMessage {
 
 #[derive(Clone, Debug, PartialEq)]
 /// Represents a generic ANT radio message
-pub struct AntMessage {
+pub struct AntMessage<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     pub header: RxMessageHeader,
-    pub message: RxMessage,
+    pub message: RxMessage<N>,
     /// XOR of all prior bytes should match this
     pub checksum: u8,
 }
 
+impl<const N: usize> AntMessage<N> {
+    /// Wrap a message with a matching header and checksum
+    pub fn new(message: RxMessage<N>) -> Result<AntMessage<N>, PackingError> {
+        let mut buf = [0; MAX_FRAME_SIZE];
+        let msg = AntMessage {
+            header: RxMessageHeader {
+                sync: RxSyncByte::Write,
+                msg_length: 0,
+                msg_id: message.get_rx_msg_id(),
+            },
+            message,
+            checksum: 0,
+        };
+        let len = msg.serialize(&mut buf)?;
+        Ok(AntMessage {
+            header: RxMessageHeader::unpack_from_slice(&buf[..HEADER_SIZE])?,
+            checksum: buf[len - CHECKSUM_SIZE],
+            ..msg
+        })
+    }
+
+    /// Serialize into wire bytes, returns the number of bytes written
+    ///
+    /// The length, message ID and checksum are computed from the message rather than copied from
+    /// the header, only the sync byte is kept.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        let body = buf
+            .get_mut(HEADER_SIZE..)
+            .ok_or(PackingError::BufferTooSmall)?;
+        let msg_len = self.message.serialize_message(body)?;
+        let header = RxMessageHeader {
+            sync: self.header.sync,
+            msg_length: u8::try_from(msg_len).map_err(|_| PackingError::InvalidValue)?,
+            msg_id: self.message.get_rx_msg_id(),
+        };
+        header.pack_to_slice(&mut buf[..HEADER_SIZE])?;
+
+        let padded_len = HEADER_SIZE + msg_len;
+        *buf.get_mut(padded_len)
+            .ok_or(PackingError::BufferTooSmall)? = calculate_checksum(&buf[..padded_len]);
+        Ok(padded_len + CHECKSUM_SIZE)
+    }
+
+    /// Serialize into wire bytes
+    pub fn to_bytes(&self) -> Result<ArrayVec<u8, MAX_FRAME_SIZE>, PackingError> {
+        let mut buf = ArrayVec::from([0; MAX_FRAME_SIZE]);
+        let len = self.serialize(&mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }
+}
+
 // Hack to allow memory channels to recycle, not intended for actual use
-impl Default for AntMessage {
-    fn default() -> AntMessage {
+impl<const N: usize> Default for AntMessage<N> {
+    fn default() -> AntMessage<N> {
         AntMessage {
             header: RxMessageHeader {
                 sync: RxSyncByte::Read,
@@ -418,11 +547,17 @@ macro_rules! AntAutoPackWithExtention {
         impl TransmitableMessage for $msg_type {
             fn serialize_message(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
                 let data_len = PackedStructSlice::packed_bytes_size(Some(&self.$main_field))?;
-                self.$main_field.pack_to_slice(&mut buf[..data_len])?;
+                self.$main_field.pack_to_slice(
+                    buf.get_mut(..data_len)
+                        .ok_or(PackingError::BufferTooSmall)?,
+                )?;
 
                 if let Some(ext) = self.$ext_field {
                     let ext_len = PackedStructSlice::packed_bytes_size(Some(&ext))?;
-                    ext.pack_to_slice(&mut buf[data_len..data_len + ext_len])?;
+                    ext.pack_to_slice(
+                        buf.get_mut(data_len..data_len + ext_len)
+                            .ok_or(PackingError::BufferTooSmall)?,
+                    )?;
                     return Ok(data_len + ext_len);
                 }
                 Ok(data_len)
@@ -431,12 +566,36 @@ macro_rules! AntAutoPackWithExtention {
                 $id
             }
         }
-        impl From<$msg_type> for TxMessage {
-            fn from(msg: $msg_type) -> TxMessage {
+        impl<const N: usize> From<$msg_type> for TxMessage<N> {
+            fn from(msg: $msg_type) -> TxMessage<N> {
                 TxMessage::$msg_type(msg)
             }
         }
+        impl $msg_type {
+            pub(crate) fn unpack_from_slice(buf: &[u8]) -> Result<$msg_type, PackingError> {
+                let ($main_field, $ext_field) = $crate::messages::unpack_with_extension(buf)?;
+                Ok($msg_type {
+                    $main_field,
+                    $ext_field,
+                })
+            }
+        }
+    };
+}
+
+/// Unpack a message made of mandatory fields followed by optional extension fields
+pub(crate) fn unpack_with_extension<M, X>(buf: &[u8]) -> Result<(M, Option<X>), PackingError>
+where
+    M: PackedStructSlice,
+    X: PackedStructSlice,
+{
+    let data_len = M::packed_bytes_size(None)?;
+    let data = M::unpack_from_slice(buf.get(..data_len).ok_or(PackingError::BufferTooSmall)?)?;
+    let ext = match &buf[data_len..] {
+        [] => None,
+        ext_buf => Some(X::unpack_from_slice(ext_buf)?),
     };
+    Ok((data, ext))
 }
 
 pub(crate) use AntAutoPackWithExtention;
@@ -6,8 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::messages::MAX_MESSAGE_DATA_SIZE;
+use crate::messages::config::ConfigError;
+use crate::messages::{TransmitableMessage, TxMessageId, DEFAULT_PAYLOAD_CAPACITY};
 use arrayvec::ArrayVec;
+use derive_new::new;
 use packed_struct::prelude::*;
 
 // Rexport reused types so they exist in all expected namespaces based on the datasheet
@@ -37,12 +39,13 @@ pub struct ChannelStatus {
     pub channel_state: ChannelState,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct AntVersion {
-    version: ArrayVec<u8, MAX_MESSAGE_DATA_SIZE>,
+/// Version string of up to `N` bytes
+#[derive(new, Clone, Debug, PartialEq)]
+pub struct AntVersion<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    version: ArrayVec<u8, N>,
 }
 
-impl AntVersion {
+impl<const N: usize> AntVersion<N> {
     pub(crate) fn unpack_from_slice(data: &[u8]) -> Result<Self, PackingError> {
         let data_bytes = match data.try_into() {
             Ok(x) => x,
@@ -56,6 +59,27 @@ impl AntVersion {
             version: data_bytes,
         })
     }
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        pack_bytes(&self.version, buf)
+    }
+
+    /// Version string without its null terminator
+    pub fn version(&self) -> &[u8] {
+        let end = self
+            .version
+            .iter()
+            .position(|x| *x == 0)
+            .unwrap_or(self.version.len());
+        &self.version[..end]
+    }
+}
+
+/// Copy a variable length field into `buf`
+fn pack_bytes(data: &[u8], buf: &mut [u8]) -> Result<usize, PackingError> {
+    buf.get_mut(..data.len())
+        .ok_or(PackingError::BufferTooSmall)?
+        .copy_from_slice(data);
+    Ok(data.len())
 }
 
 #[derive(PackedStruct, Copy, Clone, Debug, PartialEq)]
@@ -315,6 +339,309 @@ impl Capabilities {
             actual: expected_size + data.len(),
         })
     }
+
+    /// Serialize the capabilities, optional fields are written in order until the first `None`
+    ///
+    /// Setting an optional field after an unset one is not representable and is an error.
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        self.base_capabilities.pack_to_slice(
+            buf.get_mut(..BaseCapabilities::PACKING_SIZE)
+                .ok_or(PackingError::BufferTooSmall)?,
+        )?;
+        let mut len = BaseCapabilities::PACKING_SIZE;
+
+        let optional_fields = [
+            self.advanced_options2.map(|x| x.pack()).transpose()?,
+            self.max_sensrcore_channels.map(|x| [x]),
+            self.advanced_options3.map(|x| x.pack()).transpose()?,
+            self.advanced_options4.map(|x| x.pack()).transpose()?,
+        ];
+        let mut fields = optional_fields.iter();
+        for [byte] in fields.by_ref().map_while(|x| *x) {
+            *buf.get_mut(len).ok_or(PackingError::BufferTooSmall)? = byte;
+            len += 1;
+        }
+        if fields.any(Option::is_some) {
+            return Err(PackingError::InvalidValue);
+        }
+
+        Ok(len)
+    }
+}
+
+/// Optional radio features a message may depend on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RadioFeature {
+    AcknowledgedMessages,
+    BurstMessages,
+    Networks,
+    SerialNumber,
+    PerChannelTxPower,
+    LowPrioritySearch,
+    SearchList,
+    Led,
+    ExtendedMessages,
+    ScanMode,
+    ProximitySearch,
+    AdvancedBurst,
+    EventBuffering,
+    EventFiltering,
+    HighDutySearch,
+    SearchSharing,
+    SelectiveDataUpdates,
+    Encryption,
+}
+
+impl RadioFeature {
+    /// Feature the radio must advertise before accepting a message, if any
+    ///
+    /// Note the encryption ID list messages share their IDs with the channel ID list messages so
+    /// they are reported as [RadioFeature::SearchList].
+    pub fn required_by(msg_id: TxMessageId) -> Option<RadioFeature> {
+        Some(match msg_id {
+            TxMessageId::AcknowledgedData => RadioFeature::AcknowledgedMessages,
+            TxMessageId::BurstTransferData => RadioFeature::BurstMessages,
+            TxMessageId::SetNetworkKey | TxMessageId::Set128BitNetworkKey => RadioFeature::Networks,
+            TxMessageId::SerialNumberSetChannelId => RadioFeature::SerialNumber,
+            TxMessageId::SetChannelTransmitPower => RadioFeature::PerChannelTxPower,
+            TxMessageId::LowPrioritySearchTimeout => RadioFeature::LowPrioritySearch,
+            TxMessageId::AddChannelIdToList | TxMessageId::ConfigIdList => RadioFeature::SearchList,
+            TxMessageId::EnableLed => RadioFeature::Led,
+            TxMessageId::EnableExtRxMessages => RadioFeature::ExtendedMessages,
+            TxMessageId::OpenRxScanMode => RadioFeature::ScanMode,
+            TxMessageId::ProximitySearch => RadioFeature::ProximitySearch,
+            TxMessageId::ConfigureAdvancedBurst | TxMessageId::AdvancedBurstData => {
+                RadioFeature::AdvancedBurst
+            }
+            TxMessageId::ConfigureEventBuffer => RadioFeature::EventBuffering,
+            TxMessageId::ConfigureEventFilter => RadioFeature::EventFiltering,
+            TxMessageId::HighDutySearch => RadioFeature::HighDutySearch,
+            TxMessageId::ChannelSearchSharing => RadioFeature::SearchSharing,
+            TxMessageId::ConfigureSelectiveDataUpdates
+            | TxMessageId::SetSelectiveDataUpdateMask => RadioFeature::SelectiveDataUpdates,
+            TxMessageId::EnableSingleChannelEncryption
+            | TxMessageId::SetEncryptionKey
+            | TxMessageId::SetEncryptionInfo
+            | TxMessageId::LoadStoreEncryptionKeyFromNvm => RadioFeature::Encryption,
+            _ => return None,
+        })
+    }
+}
+
+/// High level summary of a [Capabilities] message
+///
+/// Optional fields missing from the message are treated as unsupported features, older radios
+/// that do not report them do not support the features either.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RadioCapabilities {
+    capabilities: Capabilities,
+}
+
+impl From<Capabilities> for RadioCapabilities {
+    fn from(capabilities: Capabilities) -> Self {
+        Self { capabilities }
+    }
+}
+
+impl From<RadioCapabilities> for Capabilities {
+    fn from(capabilities: RadioCapabilities) -> Self {
+        capabilities.capabilities
+    }
+}
+
+impl RadioCapabilities {
+    /// Raw capabilities message this summary was built from
+    pub fn raw(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn max_channels(&self) -> u8 {
+        self.capabilities.base_capabilities.max_ant_channels
+    }
+
+    pub fn max_networks(&self) -> u8 {
+        self.capabilities.base_capabilities.max_networks
+    }
+
+    pub fn max_sensrcore_channels(&self) -> u8 {
+        self.capabilities.max_sensrcore_channels.unwrap_or(0)
+    }
+
+    pub fn supports_receive(&self) -> bool {
+        let options = &self.capabilities.base_capabilities.standard_options;
+        !(options.no_receive_channels || options.no_receive_messages)
+    }
+
+    pub fn supports_transmit(&self) -> bool {
+        let options = &self.capabilities.base_capabilities.standard_options;
+        !(options.no_transmit_channels || options.no_transmit_messages)
+    }
+
+    pub fn supports_acknowledged_messages(&self) -> bool {
+        !self
+            .capabilities
+            .base_capabilities
+            .standard_options
+            .no_acked_messages
+    }
+
+    pub fn supports_burst_messages(&self) -> bool {
+        !self
+            .capabilities
+            .base_capabilities
+            .standard_options
+            .no_burst_messages
+    }
+
+    pub fn supports_networks(&self) -> bool {
+        self.capabilities
+            .base_capabilities
+            .advanced_options
+            .network_enabled
+    }
+
+    pub fn supports_serial_number(&self) -> bool {
+        self.capabilities
+            .base_capabilities
+            .advanced_options
+            .serial_number_enabled
+    }
+
+    pub fn supports_per_channel_tx_power(&self) -> bool {
+        self.capabilities
+            .base_capabilities
+            .advanced_options
+            .per_channel_tx_power_enabled
+    }
+
+    pub fn supports_low_priority_search(&self) -> bool {
+        self.capabilities
+            .base_capabilities
+            .advanced_options
+            .low_priority_search_enabled
+    }
+
+    pub fn supports_search_list(&self) -> bool {
+        self.capabilities
+            .base_capabilities
+            .advanced_options
+            .search_list_enabled
+    }
+
+    pub fn supports_led(&self) -> bool {
+        self.options2(|o| o.led_enabled)
+    }
+
+    pub fn supports_extended_messages(&self) -> bool {
+        self.options2(|o| o.ext_message_enabled)
+    }
+
+    pub fn supports_scan_mode(&self) -> bool {
+        self.options2(|o| o.scan_mode_enabled)
+    }
+
+    pub fn supports_proximity_search(&self) -> bool {
+        self.options2(|o| o.prox_search_enabled)
+    }
+
+    pub fn supports_extended_assign(&self) -> bool {
+        self.options2(|o| o.ext_assign_enabled)
+    }
+
+    pub fn supports_advanced_burst(&self) -> bool {
+        self.options3(|o| o.advanced_burst_enabled)
+    }
+
+    pub fn supports_event_buffering(&self) -> bool {
+        self.options3(|o| o.event_buffering_enabled)
+    }
+
+    pub fn supports_event_filtering(&self) -> bool {
+        self.options3(|o| o.event_filtering_enabled)
+    }
+
+    pub fn supports_high_duty_search(&self) -> bool {
+        self.options3(|o| o.high_duty_search_enabled)
+    }
+
+    pub fn supports_search_sharing(&self) -> bool {
+        self.options3(|o| o.search_sharing_enabled)
+    }
+
+    pub fn supports_selective_data_updates(&self) -> bool {
+        self.options3(|o| o.selective_data_updates_enabled)
+    }
+
+    pub fn supports_encryption(&self) -> bool {
+        self.options3(|o| o.encrypted_channel_enabled)
+    }
+
+    pub fn supports_rf_active_notification(&self) -> bool {
+        self.capabilities
+            .advanced_options4
+            .is_some_and(|o| o.rfactive_notification_enabled)
+    }
+
+    pub fn supports(&self, feature: RadioFeature) -> bool {
+        match feature {
+            RadioFeature::AcknowledgedMessages => self.supports_acknowledged_messages(),
+            RadioFeature::BurstMessages => self.supports_burst_messages(),
+            RadioFeature::Networks => self.supports_networks(),
+            RadioFeature::SerialNumber => self.supports_serial_number(),
+            RadioFeature::PerChannelTxPower => self.supports_per_channel_tx_power(),
+            RadioFeature::LowPrioritySearch => self.supports_low_priority_search(),
+            RadioFeature::SearchList => self.supports_search_list(),
+            RadioFeature::Led => self.supports_led(),
+            RadioFeature::ExtendedMessages => self.supports_extended_messages(),
+            RadioFeature::ScanMode => self.supports_scan_mode(),
+            RadioFeature::ProximitySearch => self.supports_proximity_search(),
+            RadioFeature::AdvancedBurst => self.supports_advanced_burst(),
+            RadioFeature::EventBuffering => self.supports_event_buffering(),
+            RadioFeature::EventFiltering => self.supports_event_filtering(),
+            RadioFeature::HighDutySearch => self.supports_high_duty_search(),
+            RadioFeature::SearchSharing => self.supports_search_sharing(),
+            RadioFeature::SelectiveDataUpdates => self.supports_selective_data_updates(),
+            RadioFeature::Encryption => self.supports_encryption(),
+        }
+    }
+
+    /// Verify the radio can handle `msg`, returns the missing feature otherwise
+    pub fn check_message(&self, msg: &dyn TransmitableMessage) -> Result<(), RadioFeature> {
+        match RadioFeature::required_by(msg.get_tx_msg_id()) {
+            Some(feature) if !self.supports(feature) => Err(feature),
+            _ => Ok(()),
+        }
+    }
+
+    /// Verify `channel` exists on the radio
+    pub fn check_channel(&self, channel: u8) -> Result<(), ConfigError> {
+        if channel < self.max_channels() {
+            return Ok(());
+        }
+        Err(ConfigError::InvalidChannel {
+            channel,
+            max_channels: self.max_channels(),
+        })
+    }
+
+    /// Verify `network` exists on the radio
+    pub fn check_network(&self, network: u8) -> Result<(), ConfigError> {
+        if network < self.max_networks() {
+            return Ok(());
+        }
+        Err(ConfigError::InvalidNetwork {
+            network,
+            max_networks: self.max_networks(),
+        })
+    }
+
+    fn options2(&self, f: impl Fn(&AdvancedOptions2) -> bool) -> bool {
+        self.capabilities.advanced_options2.as_ref().is_some_and(f)
+    }
+
+    fn options3(&self, f: impl Fn(&AdvancedOptions3) -> bool) -> bool {
+        self.capabilities.advanced_options3.as_ref().is_some_and(f)
+    }
 }
 
 #[derive(PackedStruct, Copy, Clone, Debug, PartialEq)]
@@ -328,13 +655,19 @@ pub struct AdvancedBurstCapabilities {
     pub supported_features: SupportedFeatures,
 }
 
-#[derive(PackedStruct, Debug, Clone, PartialEq)]
+#[derive(PackedStruct, new, Debug, Clone, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "4")]
 pub struct SerialNumber {
     #[packed_field(bytes = "0:3")]
     serial_number: [u8; 4],
 }
 
+impl SerialNumber {
+    pub fn serial_number(&self) -> u32 {
+        u32::from_le_bytes(self.serial_number)
+    }
+}
+
 // Reexport under new name even though its the same type to match the docs
 // Reserved fields are ignored so any mismatch in fixed fields is ignored on parsing
 pub use crate::messages::config::ConfigureAdvancedBurst as AdvancedBurstCurrentConfiguration;
@@ -343,22 +676,26 @@ pub use crate::messages::config::ConfigureEventFilter as EventFilter;
 pub use crate::messages::config::EventBufferConfig;
 pub use crate::messages::config::SetSelectiveDataUpdateMask as SelectiveDataUpdateMaskSetting;
 
-#[derive(PackedStruct, Clone, Copy, Debug, PartialEq)]
+#[derive(PackedStruct, Clone, Copy, Debug, Default, PartialEq)]
 #[packed_struct(bit_numbering = "msb0", endian = "lsb", size_bytes = "1")]
 pub struct UserNvmHeader {
     #[packed_field(bytes = "0")]
     resered: ReservedZeroes<packed_bits::Bits<8>>,
 }
 
-// TODO conditionally compile this, also magic num
-#[derive(Clone, Debug, PartialEq)]
-pub struct UserNvm {
+/// NVM contents of up to `N` bytes
+///
+/// USB sticks can return up to 254 bytes, larger reads fail to parse unless `N` is raised to
+/// match.
+#[derive(new, Clone, Debug, PartialEq)]
+pub struct UserNvm<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    #[new(default)]
     header: UserNvmHeader,
-    data: ArrayVec<u8, 255>,
+    data: ArrayVec<u8, N>,
 }
 
-impl UserNvm {
-    pub(crate) fn unpack_from_slice(data: &[u8]) -> Result<UserNvm, PackingError> {
+impl<const N: usize> UserNvm<N> {
+    pub(crate) fn unpack_from_slice(data: &[u8]) -> Result<Self, PackingError> {
         let data_bytes = match data
             .get(1..)
             .ok_or(PackingError::BufferTooSmall)?
@@ -371,13 +708,24 @@ impl UserNvm {
                 })
             }
         };
-        Ok(UserNvm {
+        Ok(Self {
             header: UserNvmHeader::unpack_from_slice(
                 data.get(..1).ok_or(PackingError::BufferTooSmall)?,
             )?,
             data: data_bytes,
         })
     }
+
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        let (header_buf, buf) = buf.split_first_mut().ok_or(PackingError::BufferTooSmall)?;
+        self.header
+            .pack_to_slice(std::slice::from_mut(header_buf))?;
+        Ok(1 + pack_bytes(&self.data, buf)?)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 #[derive(PrimitiveEnum_u8, Clone, Copy, PartialEq, Debug)]
@@ -456,6 +804,29 @@ impl EncryptionModeParameters {
             requested_encryption_parameter_data: data,
         })
     }
+    pub(crate) fn pack_to_slice(&self, buf: &mut [u8]) -> Result<usize, PackingError> {
+        let (parameter_buf, buf) = buf.split_first_mut().ok_or(PackingError::BufferTooSmall)?;
+        *parameter_buf = self.requested_encryption_parameter.to_primitive();
+        let len = match (
+            self.requested_encryption_parameter,
+            self.requested_encryption_parameter_data,
+        ) {
+            (
+                RequestedEncryptionParameter::MaxSupportedEncryptionMode,
+                RequestedEncryptionParameterData::MaxSupportedEncryptionMode(mode),
+            ) => pack_bytes(&[mode.to_primitive()], buf)?,
+            (
+                RequestedEncryptionParameter::EncryptionId,
+                RequestedEncryptionParameterData::EncryptionId(id),
+            ) => pack_bytes(&id, buf)?,
+            (
+                RequestedEncryptionParameter::UserInformationString,
+                RequestedEncryptionParameterData::UserInformationString(uis),
+            ) => pack_bytes(&uis, buf)?,
+            _ => return Err(PackingError::InvalidValue),
+        };
+        Ok(1 + len)
+    }
 }
 
 #[cfg(test)]
@@ -691,6 +1062,7 @@ mod tests {
     fn serial_number() {
         let unpacked = SerialNumber::unpack(&[0xAA, 0xBB, 0xCC, 0xDD]).unwrap();
         assert_eq!(unpacked.serial_number, [0xAA, 0xBB, 0xCC, 0xDD]);
+        assert_eq!(unpacked.serial_number(), 0xDDCCBBAA);
     }
 
     #[test]
@@ -701,6 +1073,49 @@ mod tests {
         assert_eq!(unpacked.time, 0xDDCC);
     }
 
+    #[test]
+    fn radio_capabilities() {
+        use crate::messages::config::ConfigureEventFilter;
+        use crate::messages::control::OpenRxScanMode;
+        use crate::messages::data::AcknowledgedData;
+
+        let caps: RadioCapabilities =
+            Capabilities::unpack_from_slice(&[16, 4, 0x15, 0x82, 4, 8, 0x40, 1])
+                .unwrap()
+                .into();
+        assert_eq!(caps.max_channels(), 16);
+        assert_eq!(caps.max_networks(), 4);
+        assert_eq!(caps.max_sensrcore_channels(), 8);
+        assert!(!caps.supports_receive());
+        assert!(caps.supports_transmit());
+        assert!(!caps.supports_acknowledged_messages());
+        assert!(caps.supports_networks());
+        assert!(caps.supports_scan_mode());
+        assert!(caps.supports_selective_data_updates());
+        assert!(!caps.supports_advanced_burst());
+        assert!(!caps.supports_encryption());
+        assert!(!caps.supports_event_filtering());
+        assert!(caps.supports_rf_active_notification());
+
+        assert_eq!(caps.check_message(&OpenRxScanMode::new(None)), Ok(()));
+        assert_eq!(
+            caps.check_message(&ConfigureEventFilter::default()),
+            Err(RadioFeature::EventFiltering)
+        );
+        assert_eq!(
+            caps.check_message(&AcknowledgedData::new(0, [0; 8])),
+            Err(RadioFeature::AcknowledgedMessages)
+        );
+
+        // Missing optional fields are reported as unsupported
+        let caps: RadioCapabilities = Capabilities::unpack_from_slice(&[16, 4, 0x15, 0x82])
+            .unwrap()
+            .into();
+        assert!(!caps.supports_scan_mode());
+        assert!(!caps.supports_selective_data_updates());
+        assert_eq!(caps.max_sensrcore_channels(), 0);
+    }
+
     #[test]
     fn advanced_burst_capabilities() {
         let unpacked = AdvancedBurstCapabilities::unpack(&[0, 2, 1, 0, 0]).unwrap();
@@ -749,10 +1164,10 @@ mod tests {
 
     #[test]
     fn user_nvm() {
-        let unpacked = UserNvm::unpack_from_slice(&[0, 1, 2, 3, 4]).unwrap();
+        let unpacked = <UserNvm>::unpack_from_slice(&[0, 1, 2, 3, 4]).unwrap();
         assert_eq!(unpacked.data.len(), 4);
         assert_eq!(unpacked.data.as_slice(), &[1, 2, 3, 4]);
-        let unpacked = UserNvm::unpack_from_slice(&[0, 1, 2, 3, 4, 5, 6]).unwrap();
+        let unpacked = <UserNvm>::unpack_from_slice(&[0, 1, 2, 3, 4, 5, 6]).unwrap();
         assert_eq!(unpacked.data.len(), 6);
         assert_eq!(unpacked.data.as_slice(), &[1, 2, 3, 4, 5, 6]);
     }
@@ -760,7 +1175,10 @@ mod tests {
     #[test]
     fn ant_version() {
         let input = [0x64, 0x65, 0x61, 0x64, 0x62, 0x65, 0x65, 0x66];
-        let unpacked = AntVersion::unpack_from_slice(&input).unwrap();
+        let unpacked = <AntVersion>::unpack_from_slice(&input).unwrap();
         assert_eq!(unpacked.version.as_slice(), input);
+        assert_eq!(unpacked.version(), input);
+        let unpacked = <AntVersion>::unpack_from_slice(b"AP2-1.05\0").unwrap();
+        assert_eq!(unpacked.version(), b"AP2-1.05");
     }
 }
@@ -1,3 +1,4 @@
+use crate::messages::config::{check_range, ConfigError, MAX_RF_FREQUENCY, MAX_TRANSMIT_POWER};
 use crate::messages::{TransmitableMessage, TxMessage, TxMessageId};
 use ant_derive::AntTx;
 use derive_new::new;
@@ -23,6 +24,15 @@ pub struct CwTest {
     pub channel_rf_frequency: u8,
 }
 
+impl CwTest {
+    /// Checked constructor, rejects a power or frequency the radio cannot produce
+    pub fn try_new(transmit_power: u8, channel_rf_frequency: u8) -> Result<Self, ConfigError> {
+        check_range(transmit_power, 0, MAX_TRANSMIT_POWER)?;
+        check_range(channel_rf_frequency, 0, MAX_RF_FREQUENCY)?;
+        Ok(Self::new(transmit_power, channel_rf_frequency))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,4 +49,18 @@ mod tests {
         let packed = CwTest::new(1, 2);
         assert_eq!(packed.pack().unwrap(), [0, 1, 2]);
     }
+
+    #[test]
+    fn cw_test_checked() {
+        assert_eq!(CwTest::try_new(4, 124), Ok(CwTest::new(4, 124)));
+        assert_eq!(
+            CwTest::try_new(5, 57),
+            Err(ConfigError::OutOfRange {
+                value: 5,
+                min: 0,
+                max: 4
+            })
+        );
+        assert!(CwTest::try_new(0, 125).is_err());
+    }
 }
@@ -0,0 +1,230 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Network key registry
+//!
+//! Channels join a network by index, the key loaded at that index decides which devices they can
+//! talk to. [NetworkKeys] remembers what was loaded where, whether the radio accepted it and which
+//! index holds a well known network so profiles can be pointed at e.g. the ANT+ network without
+//! hardcoding an index.
+
+use crate::messages::channel::{ChannelResponse, CommandError};
+use crate::messages::config::{Set128BitNetworkKey, SetNetworkKey};
+use crate::messages::{TxMessage, TxMessageId};
+
+/// Highest known supported network count on a ANT device
+pub const MAX_NETWORKS: usize = 8;
+
+/// Key securing a network
+///
+/// Keys for ANT+ and ANT-FS are available from
+/// [thisisant](http://www.thisisant.com/developer/ant-plus/ant-plus-basics/network-keys/).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetworkKey {
+    Key64([u8; 8]),
+    Key128([u8; 16]),
+}
+
+impl NetworkKey {
+    pub(crate) fn message<const N: usize>(&self, network: u8) -> TxMessage<N> {
+        match self {
+            NetworkKey::Key64(key) => SetNetworkKey::new(network, *key).into(),
+            NetworkKey::Key128(key) => Set128BitNetworkKey::new(network, *key).into(),
+        }
+    }
+}
+
+/// What a network is used for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetworkRole {
+    AntPlus,
+    AntFs,
+    /// Application specific network
+    Private,
+}
+
+/// Whether the radio accepted a key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyStatus {
+    /// Sent, waiting on the radio's response
+    Pending,
+    Accepted,
+    Rejected(CommandError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetworkEntry {
+    pub key: NetworkKey,
+    pub role: NetworkRole,
+    pub status: KeyStatus,
+}
+
+/// Keys loaded in each network slot of the radio
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetworkKeys {
+    entries: [Option<NetworkEntry>; MAX_NETWORKS],
+}
+
+impl NetworkKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `key` as sent to `network`, returns the message to load it
+    ///
+    /// Any other network holding `role` gives it up, except for [NetworkRole::Private] which
+    /// can be held by any number of networks.
+    pub fn insert<const N: usize>(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+        role: NetworkRole,
+    ) -> Option<TxMessage<N>> {
+        let entry = self.entries.get_mut(network as usize)?;
+        *entry = Some(NetworkEntry {
+            key,
+            role,
+            status: KeyStatus::Pending,
+        });
+        if role != NetworkRole::Private {
+            self.entries
+                .iter_mut()
+                .enumerate()
+                .filter(|(index, _)| *index != network as usize)
+                .filter_map(|(_, entry)| entry.as_mut())
+                .filter(|entry| entry.role == role)
+                .for_each(|entry| entry.role = NetworkRole::Private);
+        }
+        Some(key.message(network))
+    }
+
+    /// Forget the key at `network`
+    pub fn remove(&mut self, network: u8) -> Option<NetworkEntry> {
+        self.entries.get_mut(network as usize)?.take()
+    }
+
+    pub fn get(&self, network: u8) -> Option<NetworkEntry> {
+        self.entries.get(network as usize).copied().flatten()
+    }
+
+    /// Network index holding `role`, only accepted keys are reported
+    pub fn network(&self, role: NetworkRole) -> Option<u8> {
+        self.entries
+            .iter()
+            .position(|entry| {
+                entry.is_some_and(|entry| entry.role == role && entry.status == KeyStatus::Accepted)
+            })
+            .map(|index| index as u8)
+    }
+
+    /// Update the key status from the radio's response, returns false if `response` is not for a
+    /// network key
+    ///
+    /// Network key responses carry the network number in place of the channel number.
+    pub fn handle_response(&mut self, response: &ChannelResponse) -> bool {
+        let result = match response
+            .result_for(TxMessageId::SetNetworkKey)
+            .or_else(|| response.result_for(TxMessageId::Set128BitNetworkKey))
+        {
+            Some(result) => result,
+            None => return false,
+        };
+        if let Some(Some(entry)) = self.entries.get_mut(response.channel_number as usize) {
+            entry.status = match result {
+                Ok(()) => KeyStatus::Accepted,
+                Err(e) => KeyStatus::Rejected(e),
+            };
+        }
+        true
+    }
+
+    /// Messages to reload every key, e.g. after a radio reset
+    ///
+    /// Keys are marked pending until the radio accepts them again.
+    pub fn reload<const N: usize>(&mut self) -> impl Iterator<Item = TxMessage<N>> + '_ {
+        self.entries
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, entry)| entry.as_mut().map(|entry| (index, entry)))
+            .map(|(index, entry)| {
+                entry.status = KeyStatus::Pending;
+                entry.key.message(index as u8)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::channel::MessageCode;
+
+    fn response(
+        network: u8,
+        message_id: TxMessageId,
+        message_code: MessageCode,
+    ) -> ChannelResponse {
+        ChannelResponse {
+            channel_number: network,
+            message_id,
+            message_code,
+        }
+    }
+
+    #[test]
+    fn roles() {
+        let mut keys = NetworkKeys::new();
+        let msg = keys
+            .insert::<64>(1, NetworkKey::Key64([1; 8]), NetworkRole::AntPlus)
+            .unwrap();
+        assert_eq!(msg, TxMessage::SetNetworkKey(SetNetworkKey::new(1, [1; 8])));
+        // Not reported until accepted
+        assert_eq!(keys.network(NetworkRole::AntPlus), None);
+        assert!(keys.handle_response(&response(
+            1,
+            TxMessageId::SetNetworkKey,
+            MessageCode::ResponseNoError
+        )));
+        assert_eq!(keys.network(NetworkRole::AntPlus), Some(1));
+
+        // Moving the role releases the old network
+        keys.insert::<64>(2, NetworkKey::Key128([2; 16]), NetworkRole::AntPlus);
+        keys.handle_response(&response(
+            2,
+            TxMessageId::Set128BitNetworkKey,
+            MessageCode::ResponseNoError,
+        ));
+        assert_eq!(keys.network(NetworkRole::AntPlus), Some(2));
+        assert_eq!(keys.get(1).unwrap().role, NetworkRole::Private);
+        assert!(keys
+            .insert::<64>(8, NetworkKey::Key64([0; 8]), NetworkRole::AntFs)
+            .is_none());
+    }
+
+    #[test]
+    fn status() {
+        let mut keys = NetworkKeys::new();
+        keys.insert::<64>(0, NetworkKey::Key64([1; 8]), NetworkRole::Private);
+        assert!(!keys.handle_response(&response(
+            0,
+            TxMessageId::AssignChannel,
+            MessageCode::ResponseNoError
+        )));
+        assert_eq!(keys.get(0).unwrap().status, KeyStatus::Pending);
+        keys.handle_response(&response(
+            0,
+            TxMessageId::SetNetworkKey,
+            MessageCode::InvalidNetworkNumber,
+        ));
+        assert_eq!(
+            keys.get(0).unwrap().status,
+            KeyStatus::Rejected(CommandError::InvalidNetworkNumber)
+        );
+        assert_eq!(keys.reload::<64>().count(), 1);
+        assert_eq!(keys.get(0).unwrap().status, KeyStatus::Pending);
+    }
+}
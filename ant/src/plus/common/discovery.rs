@@ -0,0 +1,620 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Discovery of nearby ANT+ devices
+//!
+//! [Discovery] listens on the ANT+ network with either scan mode or a wildcard search and keeps a
+//! table of every device heard, filled in from the common manufacturer, product and battery pages
+//! as devices send them.
+//!
+//! Scan mode hears every device in range at once but takes over the radio, it must run on channel
+//! 0 with no other channels open. A wildcard search shares the radio but locks onto one device at
+//! a time, after [DiscoveryConfig::dwell] messages it searches again which often finds the same
+//! device. Prefer scan mode on radios that support it.
+//!
+//! Devices are keyed by device number, type and transmission type, the pairing bit is ignored.
+//! Found, updated, dropped and expired devices are reported through [Discovery::take_event].
+//! Nothing is sent directly, poll [Discovery::send_message] and pass every message for the
+//! discovery channel to [Discovery::receive_message] with the time it arrived.
+
+use crate::messages::channel::{CommandError, MessageCode};
+use crate::messages::config::{
+    AssignChannel, ChannelId, ChannelPeriod, ChannelRfFrequency, ChannelType, DeviceType,
+    LibConfig, SearchTimeout, TransmissionType,
+};
+use crate::messages::control::{
+    CloseChannel, OpenChannel, OpenRxScanMode, RequestMessage, RequestableMessageId,
+};
+use crate::messages::data::{ChannelIdOutput, ExtendedInfo};
+use crate::messages::{AntMessage, RxMessage, TransmitableMessage, TxMessage, TxMessageId};
+use crate::plus::common::datapages::{
+    BatteryStatus, CommonManufacturersInformation, CommonProductInformation, DataPageNumbers,
+    ManufacturersInformation, ProductInformation,
+};
+use crate::plus::common::event_queue::EventQueue;
+use crate::plus::NETWORK_RF_FREQUENCY;
+use crate::router::message_channel;
+use core::time::Duration;
+use packed_struct::{PackedStruct, PrimitiveEnum};
+
+/// Events held until taken with [Discovery::take_event], oldest are dropped first
+pub const MAX_DISCOVERY_EVENTS: usize = 16;
+
+/// Search timeout that never gives up
+const INFINITE_SEARCH: u8 = 0xFF;
+
+/// Some profiles flip the top bit of the page number every few pages
+const DATA_PAGE_NUMBER_MASK: u8 = 0x7F;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiscoveryMode {
+    /// Continuous scan, uses channel 0 and the whole radio
+    Scan,
+    /// Wildcard search on the given channel
+    Wildcard(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiscoveryConfig {
+    pub mode: DiscoveryMode,
+    /// Network holding the ANT+ key
+    pub network_key_index: u8,
+    /// Only discover devices of this type, 0 for any
+    pub device_type: u8,
+    /// Channel period for wildcard searches
+    pub channel_period: u16,
+    /// Messages received from a device before a wildcard search moves on
+    pub dwell: u8,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            mode: DiscoveryMode::Scan,
+            network_key_index: 0,
+            device_type: 0,
+            channel_period: 8192,
+            dwell: 16,
+        }
+    }
+}
+
+/// Everything known about a device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiscoveredDevice {
+    pub id: ChannelIdOutput,
+    /// Signal strength of the latest message in dBm, needs RSSI extended output
+    pub rssi: Option<i8>,
+    /// Time passed to [Discovery::receive_message] with the latest message
+    pub last_seen: Duration,
+    /// From common page 0x50
+    pub manufacturer: Option<CommonManufacturersInformation>,
+    /// From common page 0x51
+    pub product: Option<CommonProductInformation>,
+    /// From common page 0x52
+    pub battery: Option<BatteryStatus>,
+}
+
+impl DiscoveredDevice {
+    fn new(id: ChannelIdOutput, now: Duration) -> Self {
+        Self {
+            id,
+            rssi: None,
+            last_seen: now,
+            manufacturer: None,
+            product: None,
+            battery: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiscoveryEvent {
+    /// First message from a device
+    Found(ChannelIdOutput),
+    /// Manufacturer, product or battery information changed
+    Updated(ChannelIdOutput),
+    /// Device not heard from within the age given to [Discovery::expire]
+    Expired(ChannelIdOutput),
+    /// Device table is full, the device was not recorded
+    Dropped(ChannelIdOutput),
+    /// Radio rejected the channel configuration, discovery stopped
+    ConfigFailed(CommandError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    LibConfig,
+    Assign,
+    Id,
+    Period,
+    RfFrequency,
+    SearchTimeout,
+    Open,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Configuring(Step),
+    Running,
+    /// Channel needs closing
+    Close,
+    /// Close accepted, waiting for the channel to report closed
+    Closing,
+    Stopped,
+}
+
+/// Collects up to `D` devices seen on the ANT+ network
+pub struct Discovery<const D: usize> {
+    config: DiscoveryConfig,
+    state: State,
+    /// Message sent and waiting on its response
+    pending: Option<TxMessageId>,
+    stopping: bool,
+    /// Device the wildcard search locked onto, learned from extended data or a channel id request
+    tracked: Option<ChannelIdOutput>,
+    id_requested: bool,
+    messages: u8,
+    devices: [Option<DiscoveredDevice>; D],
+    events: EventQueue<DiscoveryEvent, MAX_DISCOVERY_EVENTS>,
+}
+
+impl<const D: usize> Discovery<D> {
+    /// Start discovery, the channel must already be routed here and not be used by anything else
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            state: State::Configuring(Self::first_step(&config)),
+            pending: None,
+            stopping: false,
+            tracked: None,
+            id_requested: false,
+            messages: 0,
+            devices: [None; D],
+            events: EventQueue::new(),
+        }
+    }
+
+    fn first_step(config: &DiscoveryConfig) -> Step {
+        match config.mode {
+            // Scan mode has no channel to ask, the id must come with every message
+            DiscoveryMode::Scan => Step::LibConfig,
+            DiscoveryMode::Wildcard(_) => Step::Assign,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        match self.config.mode {
+            DiscoveryMode::Scan => 0,
+            DiscoveryMode::Wildcard(channel) => channel,
+        }
+    }
+
+    /// Close the channel, devices already found are kept
+    pub fn stop(&mut self) {
+        self.stopping = true;
+        self.state = match self.state {
+            State::Configuring(Step::LibConfig | Step::Assign) if self.pending.is_none() => {
+                State::Stopped
+            }
+            State::Configuring(_) | State::Running => State::Close,
+            state => state,
+        };
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state == State::Running
+    }
+
+    pub fn devices(&self) -> impl Iterator<Item = &DiscoveredDevice> {
+        self.devices.iter().flatten()
+    }
+
+    pub fn device(&self, id: &ChannelIdOutput) -> Option<&DiscoveredDevice> {
+        self.devices().find(|device| same_device(&device.id, id))
+    }
+
+    /// Forget every device
+    pub fn clear(&mut self) {
+        self.devices = [None; D];
+    }
+
+    /// Forget devices not heard from in `max_age`, each raises [DiscoveryEvent::Expired]
+    pub fn expire(&mut self, now: Duration, max_age: Duration) {
+        for index in 0..D {
+            if let Some(device) = self.devices[index] {
+                if now.saturating_sub(device.last_seen) > max_age {
+                    self.devices[index] = None;
+                    self.events.push(DiscoveryEvent::Expired(device.id));
+                }
+            }
+        }
+    }
+
+    /// Oldest event not yet taken
+    pub fn take_event(&mut self) -> Option<DiscoveryEvent> {
+        self.events.take()
+    }
+
+    /// Next message to send to the radio
+    pub fn send_message<const N: usize>(&mut self) -> Option<TxMessage<N>> {
+        if self.pending.is_some() {
+            return None;
+        }
+        let channel = self.channel();
+        let msg: TxMessage<N> = match self.state {
+            State::Running if self.tracked.is_none() && self.messages > 0 && !self.id_requested => {
+                // No response is sent, the channel id message is the answer
+                self.id_requested = true;
+                return Some(
+                    RequestMessage::new(channel, RequestableMessageId::ChannelId, None).into(),
+                );
+            }
+            State::Running | State::Closing | State::Stopped => return None,
+            State::Close => CloseChannel::new(channel).into(),
+            State::Configuring(step) => self.step_message(step, channel),
+        };
+        self.pending = Some(msg.get_tx_msg_id());
+        Some(msg)
+    }
+
+    fn step_message<const N: usize>(&self, step: Step, channel: u8) -> TxMessage<N> {
+        match step {
+            Step::LibConfig => LibConfig::new(true, true, false).into(),
+            Step::Assign => AssignChannel::new(
+                channel,
+                ChannelType::BidirectionalSlave,
+                self.config.network_key_index,
+                None,
+            )
+            .into(),
+            Step::Id => ChannelId::new(
+                channel,
+                0,
+                DeviceType::new(self.config.device_type.into(), false),
+                TransmissionType::new_wildcard(),
+            )
+            .into(),
+            Step::Period => ChannelPeriod::new(channel, self.config.channel_period).into(),
+            Step::RfFrequency => ChannelRfFrequency::new(channel, NETWORK_RF_FREQUENCY).into(),
+            Step::SearchTimeout => SearchTimeout::new(channel, INFINITE_SEARCH).into(),
+            Step::Open => match self.config.mode {
+                DiscoveryMode::Scan => OpenRxScanMode::new(None).into(),
+                DiscoveryMode::Wildcard(_) => OpenChannel::new(channel).into(),
+            },
+        }
+    }
+
+    fn next_step(&self, step: Step) -> Step {
+        let scan = self.config.mode == DiscoveryMode::Scan;
+        match step {
+            Step::LibConfig => Step::Assign,
+            Step::Assign => Step::Id,
+            // Scan mode receives continuously, period and timeout do not apply
+            Step::Id if scan => Step::RfFrequency,
+            Step::Id => Step::Period,
+            Step::Period => Step::RfFrequency,
+            Step::RfFrequency if scan => Step::Open,
+            Step::RfFrequency => Step::SearchTimeout,
+            Step::SearchTimeout | Step::Open => Step::Open,
+        }
+    }
+
+    /// Update the device table from a message received from the radio
+    ///
+    /// `now` is the time since any fixed point, it is only used for
+    /// [DiscoveredDevice::last_seen] and [Discovery::expire].
+    pub fn receive_message<const N: usize>(&mut self, msg: &AntMessage<N>, now: Duration) {
+        if let RxMessage::StartUpMessage(_) = msg.message {
+            // Radio reset, everything has to be set up again
+            self.pending = None;
+            self.tracked = None;
+            self.state = match self.stopping {
+                true => State::Stopped,
+                false => State::Configuring(Self::first_step(&self.config)),
+            };
+            return;
+        }
+        if message_channel(&msg.message) != Some(self.channel()) {
+            return;
+        }
+        match &msg.message {
+            RxMessage::ChannelResponse(response) if self.pending == Some(response.message_id) => {
+                self.pending = None;
+                self.handle_response(response.result());
+            }
+            RxMessage::ChannelEvent(event) => self.handle_event(event.payload.message_code),
+            RxMessage::ChannelId(id) => {
+                // Still searching if the device number is wildcarded
+                if id.device_number != 0 {
+                    self.tracked = Some(ChannelIdOutput {
+                        device_number: id.device_number,
+                        device_type: id.device_type,
+                        transmission_type: id.transmission_type,
+                    });
+                }
+                self.id_requested = false;
+            }
+            RxMessage::BroadcastData(data) => {
+                self.handle_data(&data.payload.data, &data.extended_info, now)
+            }
+            RxMessage::AcknowledgedData(data) => {
+                self.handle_data(&data.payload.data, &data.extended_info, now)
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_response(&mut self, result: Result<(), CommandError>) {
+        match (self.state, result) {
+            (State::Configuring(Step::Open), Ok(())) => self.state = State::Running,
+            (State::Configuring(step), Ok(())) => {
+                self.state = State::Configuring(self.next_step(step))
+            }
+            (State::Configuring(_), Err(e)) => {
+                self.state = State::Stopped;
+                self.events.push(DiscoveryEvent::ConfigFailed(e));
+            }
+            // Failing means the channel was not open to begin with
+            (State::Close, Ok(())) => self.state = State::Closing,
+            (State::Close, Err(_)) => self.reopen(),
+            _ => (),
+        }
+    }
+
+    fn handle_event(&mut self, code: MessageCode) {
+        match code {
+            // Wildcard search lost its device and is searching again
+            MessageCode::EventRxFailGoToSearch => {
+                self.tracked = None;
+                self.messages = 0;
+            }
+            MessageCode::EventChannelClosed => self.reopen(),
+            _ => (),
+        }
+    }
+
+    // Channel closed, search again unless stopping
+    fn reopen(&mut self) {
+        self.tracked = None;
+        self.id_requested = false;
+        self.messages = 0;
+        self.state = match self.stopping {
+            true => State::Stopped,
+            // Clear the id the search locked onto
+            false => State::Configuring(Step::Id),
+        };
+    }
+
+    fn handle_data(&mut self, data: &[u8; 8], extended_info: &Option<ExtendedInfo>, now: Duration) {
+        let id = extended_info
+            .and_then(|info| info.channel_id_output)
+            .or(self.tracked);
+        self.messages = self.messages.saturating_add(1);
+        if let DiscoveryMode::Wildcard(_) = self.config.mode {
+            if self.state == State::Running && self.messages >= self.config.dwell {
+                self.state = State::Close;
+            }
+        }
+        let Some(id) = id else {
+            return;
+        };
+        let rssi = extended_info.as_ref().and_then(ExtendedInfo::rssi_dbm);
+
+        let device = match self
+            .devices
+            .iter()
+            .position(|device| device.is_some_and(|device| same_device(&device.id, &id)))
+        {
+            Some(index) => index,
+            None => match self.devices.iter().position(Option::is_none) {
+                Some(index) => {
+                    self.devices[index] = Some(DiscoveredDevice::new(id, now));
+                    self.events.push(DiscoveryEvent::Found(id));
+                    index
+                }
+                None => {
+                    self.events.push(DiscoveryEvent::Dropped(id));
+                    return;
+                }
+            },
+        };
+        let Some(device) = self.devices[device].as_mut() else {
+            return;
+        };
+        device.last_seen = now;
+        if rssi.is_some() {
+            device.rssi = rssi;
+        }
+        let updated = match DataPageNumbers::from_primitive(data[0] & DATA_PAGE_NUMBER_MASK) {
+            Some(DataPageNumbers::ManufacturersInformation) => {
+                ManufacturersInformation::unpack(data).is_ok_and(|page| {
+                    let info = Some(page.commmon_manufacturers_information);
+                    core::mem::replace(&mut device.manufacturer, info) != info
+                })
+            }
+            Some(DataPageNumbers::ProductInformation) => ProductInformation::unpack(data)
+                .is_ok_and(|page| {
+                    let info = Some(page.common_product_information);
+                    core::mem::replace(&mut device.product, info) != info
+                }),
+            Some(DataPageNumbers::BatteryStatus) => BatteryStatus::unpack(data).is_ok_and(|page| {
+                // Operating time counts up with every page, only report changes to the battery
+                device.battery.replace(page).is_none_or(|battery| {
+                    battery.descriptive_bit_field != page.descriptive_bit_field
+                        || battery.fractional_battery_voltage != page.fractional_battery_voltage
+                })
+            }),
+            _ => false,
+        };
+        if updated {
+            self.events.push(DiscoveryEvent::Updated(id));
+        }
+    }
+}
+
+// The pairing bit is only meaningful when searching, ignore it when comparing ids
+fn same_device(a: &ChannelIdOutput, b: &ChannelIdOutput) -> bool {
+    a.device_number == b.device_number
+        && a.device_type.device_type_id == b.device_type.device_type_id
+        && a.transmission_type == b.transmission_type
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::data::BroadcastData;
+    use crate::plus::common::test_util::{accept_all, event, rx, Responder};
+
+    impl<const D: usize> Responder for Discovery<D> {
+        fn next_message(&mut self) -> Option<TxMessageId> {
+            self.send_message::<64>().map(|msg| msg.get_tx_msg_id())
+        }
+
+        fn receive(&mut self, msg: &AntMessage) {
+            self.receive_message(msg, Duration::ZERO);
+        }
+    }
+
+    // Broadcast with channel id and RSSI extended output
+    fn broadcast(device_number: u16, rssi: i8, page: [u8; 8]) -> AntMessage {
+        let [low, high] = device_number.to_le_bytes();
+        let mut buf = [0; 17];
+        buf[1..9].copy_from_slice(&page);
+        buf[9..].copy_from_slice(&[0xC0, low, high, 120, 1, 0x20, rssi as u8, 0xB0]);
+        rx(RxMessage::BroadcastData(
+            BroadcastData::unpack_from_slice(&buf).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn scan_config() {
+        let mut discovery = Discovery::<4>::new(DiscoveryConfig::default());
+        assert_eq!(
+            accept_all(&mut discovery, 0).as_slice(),
+            [
+                TxMessageId::LibConfig,
+                TxMessageId::AssignChannel,
+                TxMessageId::ChannelId,
+                TxMessageId::ChannelRfFrequency,
+                TxMessageId::OpenRxScanMode,
+            ]
+        );
+        assert!(discovery.is_running());
+        discovery.stop();
+        assert_eq!(
+            accept_all(&mut discovery, 0).as_slice(),
+            [TxMessageId::CloseChannel]
+        );
+        discovery.receive_message(&event(0, MessageCode::EventChannelClosed), Duration::ZERO);
+        assert!(accept_all(&mut discovery, 0).is_empty());
+    }
+
+    #[test]
+    fn devices() {
+        let mut discovery = Discovery::<1>::new(DiscoveryConfig::default());
+        accept_all(&mut discovery, 0);
+        let manufacturer = [0x50, 0xFF, 0xFF, 3, 0x0F, 0x00, 0x34, 0x12];
+        // Page toggle bit set
+        let product = [0xD1, 0xFF, 0xFF, 2, 0x78, 0x56, 0x34, 0x12];
+        let battery = [0x52, 0xFF, 0xFF, 0, 0, 0, 0x80, 0x23];
+
+        let now = Duration::from_secs(1);
+        discovery.receive_message(&broadcast(7, -60, manufacturer), now);
+        discovery.receive_message(&broadcast(7, -65, manufacturer), now * 2);
+        discovery.receive_message(&broadcast(7, -70, product), now * 3);
+        discovery.receive_message(&broadcast(7, -70, battery), now * 3);
+        discovery.receive_message(&broadcast(8, -70, battery), now * 3);
+
+        let device = *discovery.devices().next().unwrap();
+        assert_eq!(device.id.device_number, 7);
+        assert_eq!(device.rssi, Some(-70));
+        assert_eq!(device.last_seen, now * 3);
+        let manufacturer = device.manufacturer.unwrap();
+        assert_eq!(manufacturer.manufacturer_id, 15);
+        assert_eq!(manufacturer.model_number, 0x1234);
+        assert_eq!(device.product.unwrap().serial_number, 0x12345678);
+        assert_eq!(
+            device.battery.unwrap().descriptive_bit_field.battery_status,
+            crate::plus::common::datapages::BatteryStatusField::Good
+        );
+
+        assert_eq!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Found(device.id))
+        );
+        assert_eq!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Updated(device.id))
+        );
+        assert_eq!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Updated(device.id))
+        );
+        assert_eq!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Updated(device.id))
+        );
+        assert!(matches!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Dropped(_))
+        ));
+        assert_eq!(discovery.take_event(), None);
+
+        discovery.expire(now * 5, now * 3);
+        assert_eq!(discovery.devices().count(), 1);
+        discovery.expire(now * 5, now);
+        assert_eq!(
+            discovery.take_event(),
+            Some(DiscoveryEvent::Expired(device.id))
+        );
+        assert_eq!(discovery.devices().count(), 0);
+    }
+
+    #[test]
+    fn wildcard() {
+        let config = DiscoveryConfig {
+            mode: DiscoveryMode::Wildcard(2),
+            dwell: 2,
+            ..DiscoveryConfig::default()
+        };
+        let mut discovery = Discovery::<4>::new(config);
+        let sent = accept_all(&mut discovery, 2);
+        assert_eq!(sent.first(), Some(&TxMessageId::AssignChannel));
+        assert!(sent.contains(&TxMessageId::SearchTimeout));
+        assert_eq!(sent.last(), Some(&TxMessageId::OpenChannel));
+
+        // Without extended messages the channel is asked who it found
+        let data = rx(RxMessage::BroadcastData(BroadcastData::new(2, [0; 8])));
+        discovery.receive_message(&data, Duration::ZERO);
+        assert_eq!(discovery.devices().count(), 0);
+        assert_eq!(
+            discovery
+                .send_message::<64>()
+                .map(|msg| msg.get_tx_msg_id()),
+            Some(TxMessageId::RequestMessage)
+        );
+        discovery.receive_message(
+            &rx(RxMessage::ChannelId(ChannelId::new(
+                2,
+                9,
+                DeviceType::new(120.into(), false),
+                TransmissionType::new_wildcard(),
+            ))),
+            Duration::ZERO,
+        );
+        discovery.receive_message(&data, Duration::ZERO);
+        assert_eq!(discovery.devices().next().unwrap().id.device_number, 9);
+
+        // Dwell reached, search again
+        assert_eq!(
+            accept_all(&mut discovery, 2).as_slice(),
+            [TxMessageId::CloseChannel]
+        );
+    }
+}
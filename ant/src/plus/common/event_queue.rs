@@ -0,0 +1,34 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use arrayvec::ArrayVec;
+
+/// Events waiting to be taken by the user, the oldest are dropped once `CAP` are held
+pub(crate) struct EventQueue<T, const CAP: usize> {
+    events: ArrayVec<T, CAP>,
+}
+
+impl<T, const CAP: usize> EventQueue<T, CAP> {
+    pub(crate) fn new() -> Self {
+        Self {
+            events: ArrayVec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, event: T) {
+        if self.events.is_full() {
+            self.events.remove(0);
+        }
+        self.events.push(event);
+    }
+
+    /// Oldest event not yet taken
+    pub(crate) fn take(&mut self) -> Option<T> {
+        self.events.pop_at(0)
+    }
+}
@@ -7,4 +7,10 @@
 // except according to those terms.
 
 pub mod datapages;
+pub mod discovery;
+pub(crate) mod event_queue;
 pub mod msg_handler;
+pub mod pairing;
+pub mod scheduler;
+#[cfg(test)]
+pub(crate) mod test_util;
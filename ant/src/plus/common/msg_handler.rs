@@ -6,14 +6,30 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::messages::channel::{ChannelEvent, ChannelResponse, MessageCode};
+use crate::channel::LifecycleEvent;
+use crate::encryption::{ChannelEncryption, MAX_ENCRYPTION_ID_LIST_SIZE};
+use crate::id_list::{ChannelIdList, MAX_CHANNEL_ID_LIST_SIZE};
+use crate::messages::channel::{
+    ChannelEvent, ChannelEventExtension, ChannelResponse, CommandError, MessageCode,
+};
 use crate::messages::config::{
-    AssignChannel, ChannelId, ChannelPeriod, ChannelRfFrequency, ChannelType, DeviceType,
-    SearchTimeout, TransmissionType, UnAssignChannel,
+    AssignChannel, ChannelId, ChannelPeriod, ChannelRfFrequency, ChannelType, ConfigError,
+    ConfigureEventFilter, DeviceType, EncryptionId, SearchTimeout, TransmissionType,
+    UnAssignChannel,
 };
 use crate::messages::control::{CloseChannel, OpenChannel, RequestMessage, RequestableMessageId};
-use crate::messages::requested_response::{ChannelState, ChannelStatus};
-use crate::messages::{AntMessage, RxMessage, TxMessage, TxMessageId};
+use crate::messages::data::AcknowledgedData;
+use crate::messages::requested_response::{
+    ChannelState, ChannelStatus, RadioCapabilities, RadioFeature,
+};
+use crate::messages::{
+    AntMessage, RxMessage, TransmitableMessage, TxMessage, TxMessageId, DEFAULT_PAYLOAD_CAPACITY,
+};
+use crate::plus::common::event_queue::EventQueue;
+use crate::plus::common::pairing::PairedDevice;
+
+/// Lifecycle events held until taken, the oldest are dropped past this
+const MAX_LIFECYCLE_EVENTS: usize = 8;
 
 // TODO add a send and get response
 //
@@ -31,29 +47,40 @@ pub enum ConfigureStateId {
     Id,
     Rf,
     Timeout,
+    AddChannelId(u8),
+    ChannelIdList,
+    AddEncryptionId(u8),
+    EncryptionIdList,
+    EnableEncryption,
     Error,
     Done,
     Identify,
 }
 
-trait ConfigureState {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState;
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage>;
+trait ConfigureState<const N: usize> {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N>;
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>>;
     fn get_state(&self) -> ConfigureStateId;
 }
 
 struct Assign {}
-impl ConfigureState for Assign {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
-        if response.message_id != TxMessageId::AssignChannel {
-            return self;
-        }
-        if response.message_code == MessageCode::ResponseNoError {
-            return &ID_STATE;
+impl<const N: usize> ConfigureState<N> for Assign {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::AssignChannel) {
+            None => self,
+            Some(Ok(())) => &ID_STATE,
+            Some(Err(_)) => &ERROR_STATE,
         }
-        &ERROR_STATE
     }
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(
             AssignChannel::new(
                 channel,
@@ -70,17 +97,19 @@ impl ConfigureState for Assign {
 }
 const ASSIGN_STATE: Assign = Assign {};
 struct Period {}
-impl ConfigureState for Period {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
-        if response.message_id != TxMessageId::ChannelPeriod {
-            return self;
-        }
-        if response.message_code == MessageCode::ResponseNoError {
-            return &TIMEOUT_STATE;
+impl<const N: usize> ConfigureState<N> for Period {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::ChannelPeriod) {
+            None => self,
+            Some(Ok(())) => &TIMEOUT_STATE,
+            Some(Err(_)) => &ERROR_STATE,
         }
-        &ERROR_STATE
     }
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(ChannelPeriod::new(channel, handler.state_config.channel_config.channel_period).into())
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -89,17 +118,19 @@ impl ConfigureState for Period {
 }
 const PERIOD_STATE: Period = Period {};
 struct Id {}
-impl ConfigureState for Id {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
-        if response.message_id != TxMessageId::ChannelId {
-            return self;
+impl<const N: usize> ConfigureState<N> for Id {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::ChannelId) {
+            None => self,
+            Some(Ok(())) => &RF_STATE,
+            Some(Err(_)) => &ERROR_STATE,
         }
-        if response.message_code == MessageCode::ResponseNoError {
-            return &RF_STATE;
-        }
-        &ERROR_STATE
     }
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(
             ChannelId::new(
                 channel,
@@ -122,17 +153,19 @@ impl ConfigureState for Id {
 }
 const ID_STATE: Id = Id {};
 struct Rf {}
-impl ConfigureState for Rf {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
-        if response.message_id != TxMessageId::ChannelRfFrequency {
-            return self;
+impl<const N: usize> ConfigureState<N> for Rf {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::ChannelRfFrequency) {
+            None => self,
+            Some(Ok(())) => &PERIOD_STATE,
+            Some(Err(_)) => &ERROR_STATE,
         }
-        if response.message_code == MessageCode::ResponseNoError {
-            return &PERIOD_STATE;
-        }
-        &ERROR_STATE
     }
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(
             ChannelRfFrequency::new(channel, handler.state_config.channel_config.radio_frequency)
                 .into(),
@@ -144,36 +177,200 @@ impl ConfigureState for Rf {
 }
 const RF_STATE: Rf = Rf {};
 struct Timeout {}
-impl ConfigureState for Timeout {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
-        if response.message_id != TxMessageId::SearchTimeout {
-            return self;
+impl<const N: usize> ConfigureState<N> for Timeout {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::SearchTimeout) {
+            None => self,
+            Some(Ok(())) => id_list_state(handler, 0),
+            Some(Err(_)) => &ERROR_STATE,
         }
-        if response.message_code == MessageCode::ResponseNoError {
-            return &IDENTIFY_STATE;
+    }
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
+        Some(SearchTimeout::new(channel, handler.search_timeout).into())
+    }
+    fn get_state(&self) -> ConfigureStateId {
+        ConfigureStateId::Timeout
+    }
+}
+const TIMEOUT_STATE: Timeout = Timeout {};
+
+// Channel ID list runs after the base channel config, `added` is how many list entries the radio
+// has accepted so far
+fn id_list_state<const N: usize>(
+    handler: &MessageHandler<N>,
+    added: usize,
+) -> &'static dyn ConfigureState<N> {
+    match &handler.id_list {
+        Some(list) if added < list.len() => &ADD_CHANNEL_ID_STATES[added],
+        Some(_) => &CHANNEL_ID_LIST_STATE,
+        None => encryption_state(handler, 0),
+    }
+}
+struct AddChannelId {
+    index: u8,
+}
+impl<const N: usize> ConfigureState<N> for AddChannelId {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::AddChannelIdToList) {
+            None => self,
+            Some(Ok(())) => id_list_state(handler, self.index as usize + 1),
+            Some(Err(_)) => &ERROR_STATE,
+        }
+    }
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
+        handler
+            .id_list
+            .as_ref()?
+            .add_message(channel, self.index as usize)
+    }
+    fn get_state(&self) -> ConfigureStateId {
+        ConfigureStateId::AddChannelId(self.index)
+    }
+}
+static ADD_CHANNEL_ID_STATES: [AddChannelId; MAX_CHANNEL_ID_LIST_SIZE] = [
+    AddChannelId { index: 0 },
+    AddChannelId { index: 1 },
+    AddChannelId { index: 2 },
+    AddChannelId { index: 3 },
+];
+struct ChannelIdListConfig {}
+impl<const N: usize> ConfigureState<N> for ChannelIdListConfig {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::ConfigIdList) {
+            None => self,
+            Some(Ok(())) => encryption_state(handler, 0),
+            Some(Err(_)) => &ERROR_STATE,
+        }
+    }
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
+        Some(handler.id_list.as_ref()?.config_message(channel))
+    }
+    fn get_state(&self) -> ConfigureStateId {
+        ConfigureStateId::ChannelIdList
+    }
+}
+const CHANNEL_ID_LIST_STATE: ChannelIdListConfig = ChannelIdListConfig {};
+
+// Encryption setup runs after the base channel config, `added` is how many list entries the radio
+// has accepted so far
+fn encryption_state<const N: usize>(
+    handler: &MessageHandler<N>,
+    added: usize,
+) -> &'static dyn ConfigureState<N> {
+    let Some(encryption) = &handler.encryption else {
+        return &IDENTIFY_STATE;
+    };
+    match &encryption.id_list {
+        Some(list) if added < list.ids.len() => &ADD_ENCRYPTION_ID_STATES[added],
+        Some(_) => &ENCRYPTION_ID_LIST_STATE,
+        None => &ENABLE_ENCRYPTION_STATE,
+    }
+}
+struct AddEncryptionId {
+    index: u8,
+}
+impl<const N: usize> ConfigureState<N> for AddEncryptionId {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        // Encryption ID list messages share their IDs with the channel ID list messages
+        match response.result_for(TxMessageId::AddChannelIdToList) {
+            None => self,
+            Some(Ok(())) => encryption_state(handler, self.index as usize + 1),
+            Some(Err(_)) => &ERROR_STATE,
         }
-        &ERROR_STATE
     }
-    fn transmit_config(&self, channel: u8, handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
+        handler
+            .encryption
+            .as_ref()?
+            .id_list
+            .as_ref()?
+            .add_message(channel, self.index as usize)
+    }
+    fn get_state(&self) -> ConfigureStateId {
+        ConfigureStateId::AddEncryptionId(self.index)
+    }
+}
+static ADD_ENCRYPTION_ID_STATES: [AddEncryptionId; MAX_ENCRYPTION_ID_LIST_SIZE] = [
+    AddEncryptionId { index: 0 },
+    AddEncryptionId { index: 1 },
+    AddEncryptionId { index: 2 },
+    AddEncryptionId { index: 3 },
+];
+struct EncryptionIdList {}
+impl<const N: usize> ConfigureState<N> for EncryptionIdList {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::ConfigIdList) {
+            None => self,
+            Some(Ok(())) => &ENABLE_ENCRYPTION_STATE,
+            Some(Err(_)) => &ERROR_STATE,
+        }
+    }
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(
-            SearchTimeout::new(
-                channel,
-                handler.state_config.channel_config.timeout_duration,
-            )
-            .into(),
+            handler
+                .encryption
+                .as_ref()?
+                .id_list
+                .as_ref()?
+                .config_message(channel),
         )
     }
     fn get_state(&self) -> ConfigureStateId {
-        ConfigureStateId::Timeout
+        ConfigureStateId::EncryptionIdList
     }
 }
-const TIMEOUT_STATE: Timeout = Timeout {};
+const ENCRYPTION_ID_LIST_STATE: EncryptionIdList = EncryptionIdList {};
+struct EnableEncryption {}
+impl<const N: usize> ConfigureState<N> for EnableEncryption {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
+        match response.result_for(TxMessageId::EnableSingleChannelEncryption) {
+            None => self,
+            Some(Ok(())) => &IDENTIFY_STATE,
+            Some(Err(_)) => &ERROR_STATE,
+        }
+    }
+    fn transmit_config(&self, channel: u8, handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
+        Some(handler.encryption.as_ref()?.enable_message(channel))
+    }
+    fn get_state(&self) -> ConfigureStateId {
+        ConfigureStateId::EnableEncryption
+    }
+}
+const ENABLE_ENCRYPTION_STATE: EnableEncryption = EnableEncryption {};
 struct Error {}
-impl ConfigureState for Error {
-    fn handle_response(&self, _response: &ChannelResponse) -> &dyn ConfigureState {
+impl<const N: usize> ConfigureState<N> for Error {
+    fn handle_response(
+        &self,
+        _response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
         self
     }
-    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         None
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -182,11 +379,15 @@ impl ConfigureState for Error {
 }
 const ERROR_STATE: Error = Error {};
 struct Done {}
-impl ConfigureState for Done {
-    fn handle_response(&self, _response: &ChannelResponse) -> &dyn ConfigureState {
+impl<const N: usize> ConfigureState<N> for Done {
+    fn handle_response(
+        &self,
+        _response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
         self
     }
-    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         None
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -195,14 +396,18 @@ impl ConfigureState for Done {
 }
 const DONE_STATE: Done = Done {};
 struct UnknownClose {}
-impl ConfigureState for UnknownClose {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
+impl<const N: usize> ConfigureState<N> for UnknownClose {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
         if response.message_id != TxMessageId::CloseChannel {
             return self;
         }
         &UNKNOWN_UNASSIGN_STATE
     }
-    fn transmit_config(&self, channel: u8, _handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, _handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(CloseChannel::new(channel).into())
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -211,14 +416,18 @@ impl ConfigureState for UnknownClose {
 }
 const UNKNOWN_CLOSE_STATE: UnknownClose = UnknownClose {};
 struct UnknownUnAssign {}
-impl ConfigureState for UnknownUnAssign {
-    fn handle_response(&self, response: &ChannelResponse) -> &dyn ConfigureState {
+impl<const N: usize> ConfigureState<N> for UnknownUnAssign {
+    fn handle_response(
+        &self,
+        response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
         if response.message_id != TxMessageId::UnAssignChannel {
             return self;
         }
         &ASSIGN_STATE
     }
-    fn transmit_config(&self, channel: u8, _handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, channel: u8, _handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         Some(UnAssignChannel::new(channel).into())
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -227,11 +436,15 @@ impl ConfigureState for UnknownUnAssign {
 }
 const UNKNOWN_UNASSIGN_STATE: UnknownUnAssign = UnknownUnAssign {};
 struct Identify {}
-impl ConfigureState for Identify {
-    fn handle_response(&self, _response: &ChannelResponse) -> &dyn ConfigureState {
+impl<const N: usize> ConfigureState<N> for Identify {
+    fn handle_response(
+        &self,
+        _response: &ChannelResponse,
+        _handler: &MessageHandler<N>,
+    ) -> &dyn ConfigureState<N> {
         self
     }
-    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler) -> Option<TxMessage> {
+    fn transmit_config(&self, _channel: u8, _handler: &MessageHandler<N>) -> Option<TxMessage<N>> {
         None
     }
     fn get_state(&self) -> ConfigureStateId {
@@ -243,11 +456,15 @@ const IDENTIFY_STATE: Identify = Identify {};
 #[derive(Clone, Copy, Debug)]
 pub enum ConfigureError {
     MessageTimeout(), // TODO add duration
-    MessageError(MessageCode),
+    MessageError(CommandError),
     ChannelInWrongState {
         current: ChannelState,
         expected: ChannelState,
     },
+    /// Radio does not support a feature the configuration relies on
+    Unsupported(RadioFeature),
+    /// Remote device rejected the encryption negotiation, carries the remote's encryption ID
+    EncryptionNegotiationFailed(EncryptionId),
 }
 
 pub type StateError = (ConfigureStateId, ConfigureError);
@@ -265,6 +482,27 @@ enum ChannelStateCommand {
     Close,
 }
 
+/// Identifies an acknowledged send, see [MessageHandler::send_acknowledged]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AckHandle(u16);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AckStatus {
+    /// Waiting to be sent or for the remote to acknowledge
+    Pending,
+    Completed,
+    /// Not acknowledged after all retries
+    Failed,
+}
+
+struct PendingAck {
+    handle: AckHandle,
+    data: [u8; 8],
+    retries_left: u8,
+    /// Sent and waiting on the transfer result
+    in_flight: bool,
+}
+
 // TODO doc
 #[derive(Copy, Clone, Debug)]
 pub struct ChannelConfig {
@@ -279,6 +517,15 @@ pub struct ChannelConfig {
     pub network_key_index: u8,
 }
 
+impl ChannelConfig {
+    /// Verify the period and frequency are values the radio accepts
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        ChannelPeriod::try_new(self.channel, self.channel_period)?;
+        ChannelRfFrequency::try_new(self.channel, self.radio_frequency)?;
+        Ok(())
+    }
+}
+
 /// This struct constains everything constant from the point we passed in from the initialization,
 /// nothing in it should change even if we reset
 struct StateConfig {
@@ -294,37 +541,71 @@ struct StateConfig {
     channel_config: ChannelConfig,
 }
 
-pub struct MessageHandler {
+pub struct MessageHandler<const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
     channel: u8,
     /// Are we setting the pairing bit?
     pairing_request: DevicePairingState,
     /// Configuration state machine pointer
-    configure_state: &'static dyn ConfigureState,
+    configure_state: &'static dyn ConfigureState<N>,
     /// State machine confgi message pending response
     configure_pending_response: bool,
     /// Previous TX transmission sent, ready for new message
     tx_ready: bool,
     /// Pending command to open/close the channel
     set_channel_state: Option<ChannelStateCommand>,
+    /// Channel was opened and should be reopened if the radio resets
+    reopen: bool,
     /// Original passed in arguements. This is used to differentiate in slaves from wildcarded
     /// fields versus discovered data
     state_config: StateConfig,
     /// Last state of the channel we were aware of
     channel_state: ChannelState,
+    /// Signal was lost while tracking, the next message received reacquires it
+    signal_lost: bool,
+    lifecycle_events: EventQueue<LifecycleEvent, MAX_LIFECYCLE_EVENTS>,
     /// Transmit a request for channel id on next TX window
     tx_channel_id_request: bool,
+    /// Resend the channel id, e.g. after the device to search for changed
+    tx_channel_id_update: bool,
+    /// Search timeout of the wildcard search run if the paired device is not found
+    wildcard_fallback: Option<u8>,
+    /// Search timeout in use, differs from the configured one after a wildcard fallback
+    search_timeout: u8,
+    /// Resend the search timeout, e.g. after falling back to a wildcard search
+    tx_search_timeout_update: bool,
+    /// Last search gave up, the channel is about to close
+    search_timed_out: bool,
+    /// Last capabilities reported by the radio
+    capabilities: Option<RadioCapabilities>,
+    /// Inclusion or exclusion list applied to the channel during configuration
+    id_list: Option<ChannelIdList>,
+    /// Encryption applied to the channel during configuration
+    encryption: Option<ChannelEncryption>,
+    /// Encryption ID of the remote device from the last successful negotiation
+    encryption_peer: Option<EncryptionId>,
+    /// Events the radio has been configured to suppress
+    event_filter: ConfigureEventFilter,
+    /// Times a failed acknowledged send is retried
+    ack_retries: u8,
+    ack_pending: Option<PendingAck>,
+    /// Result of the last finished acknowledged send
+    ack_result: Option<(AckHandle, AckStatus)>,
+    next_ack_handle: u16,
 }
 
-impl MessageHandler {
+impl<const N: usize> MessageHandler<N> {
     pub fn new(channel_config: &ChannelConfig) -> Self {
         Self {
             channel: channel_config.channel,
             configure_state: &UNKNOWN_CLOSE_STATE,
             set_channel_state: None,
+            reopen: false,
             tx_ready: false,
             pairing_request: DevicePairingState::BitCleared,
             configure_pending_response: false,
             channel_state: ChannelState::UnAssigned,
+            signal_lost: false,
+            lifecycle_events: EventQueue::new(),
             state_config: StateConfig {
                 device_number: channel_config.device_number,
                 device_type: DeviceType::new(channel_config.device_type.into(), false),
@@ -332,11 +613,31 @@ impl MessageHandler {
                 channel_config: *channel_config,
             },
             tx_channel_id_request: false,
+            tx_channel_id_update: false,
+            wildcard_fallback: None,
+            search_timeout: channel_config.timeout_duration,
+            tx_search_timeout_update: false,
+            search_timed_out: false,
+            capabilities: None,
+            id_list: None,
+            encryption: None,
+            encryption_peer: None,
+            event_filter: ConfigureEventFilter::default(),
+            ack_retries: 0,
+            ack_pending: None,
+            ack_result: None,
+            next_ack_handle: 0,
         }
         // TODO decide if we want to do check on the radio behalf for invalid config (e.g. wildcard
         // master)
     }
 
+    /// Checked constructor for user supplied configuration, see [ChannelConfig::validate]
+    pub fn try_new(channel_config: &ChannelConfig) -> Result<Self, ConfigError> {
+        channel_config.validate()?;
+        Ok(Self::new(channel_config))
+    }
+
     pub fn get_channel(&self) -> u8 {
         self.channel
     }
@@ -352,11 +653,229 @@ impl MessageHandler {
         self.state_config.device_number
     }
 
+    /// Channel ID of the device the channel is bound to, None while the device number is
+    /// wildcarded
+    ///
+    /// For slaves this is the device found by a wildcard search once it has been identified, save
+    /// it to search for the same device next time.
+    pub fn get_paired_device(&self) -> Option<PairedDevice> {
+        if self.state_config.device_number == 0 {
+            return None;
+        }
+        Some(PairedDevice {
+            device_number: self.state_config.device_number,
+            device_type: self.state_config.device_type.device_type_id.into(),
+            transmission_type: self.state_config.transmission_type,
+        })
+    }
+
+    /// Search for `device` instead of the configured channel ID, the channel must not be open
+    ///
+    /// Undoes a previous wildcard fallback, the configured search timeout applies again.
+    pub fn set_paired_device(&mut self, device: &PairedDevice) -> Result<(), ConfigureError> {
+        if matches!(
+            self.channel_state,
+            ChannelState::Searching | ChannelState::Tracking
+        ) {
+            return Err(ConfigureError::ChannelInWrongState {
+                current: self.channel_state,
+                expected: ChannelState::Assigned,
+            });
+        }
+        let timeout = self.state_config.channel_config.timeout_duration;
+        if self.search_timeout != timeout {
+            self.search_timeout = timeout;
+            self.tx_search_timeout_update = self.channel_state != ChannelState::UnAssigned;
+        }
+        self.state_config.device_number = device.device_number;
+        self.state_config.device_type = DeviceType::new(device.device_type.into(), false);
+        self.state_config.transmission_type = device.transmission_type;
+        if self.channel_state != ChannelState::UnAssigned {
+            self.tx_channel_id_update = true;
+        }
+        Ok(())
+    }
+
+    /// Reopen as a wildcard search with `search_timeout` if the search for a paired device times
+    /// out, `None` disables the fallback
+    ///
+    /// The paired device is given the channel's search timeout to show up. Timeouts are in radio
+    /// units, see [crate::channel::duration_to_search_timeout].
+    pub fn set_wildcard_fallback(&mut self, search_timeout: Option<u8>) {
+        self.wildcard_fallback = search_timeout;
+    }
+
     pub fn is_tracking(&self) -> bool {
         self.channel_state == ChannelState::Tracking
     }
 
+    /// Capabilities of the radio, if they have been observed yet
+    pub fn get_capabilities(&self) -> Option<RadioCapabilities> {
+        self.capabilities
+    }
+
+    /// Verify the radio supports `msg` before it is sent
+    ///
+    /// Passes if the capabilities have not been observed yet.
+    pub fn check_message(&self, msg: &dyn TransmitableMessage) -> Result<(), RadioFeature> {
+        match &self.capabilities {
+            Some(caps) => caps.check_message(msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the inclusion or exclusion list to apply to the channel
+    ///
+    /// The list is kept and sent again every time the channel is configured, e.g. after a radio
+    /// reset. If the channel has already been configured the list steps are rerun, followed by the
+    /// encryption steps, so the channel must not be open. `None` skips list config on the next
+    /// configuration, to turn off a list on a configured channel pass an empty [ChannelIdList].
+    pub fn set_id_list(&mut self, id_list: Option<ChannelIdList>) -> Result<(), ConfigureError> {
+        if matches!(
+            self.channel_state,
+            ChannelState::Searching | ChannelState::Tracking
+        ) {
+            return Err(ConfigureError::ChannelInWrongState {
+                current: self.channel_state,
+                expected: ChannelState::Assigned,
+            });
+        }
+        self.id_list = id_list;
+        if matches!(
+            self.configure_state.get_state(),
+            ConfigureStateId::AddChannelId(_)
+                | ConfigureStateId::ChannelIdList
+                | ConfigureStateId::AddEncryptionId(_)
+                | ConfigureStateId::EncryptionIdList
+                | ConfigureStateId::EnableEncryption
+                | ConfigureStateId::Identify
+                | ConfigureStateId::Done
+        ) {
+            self.configure_state = id_list_state(self, 0);
+            self.configure_pending_response = false;
+        }
+        Ok(())
+    }
+
+    /// Inclusion or exclusion list applied to the channel
+    pub fn get_id_list(&self) -> Option<&ChannelIdList> {
+        self.id_list.as_ref()
+    }
+
+    /// Set the encryption to apply to the channel
+    ///
+    /// The radio wide key must be loaded separately, see
+    /// [crate::encryption::EncryptionKeyConfig]. If the channel has already been configured the
+    /// encryption steps are rerun, the channel must not be open. `None` skips encryption config on
+    /// the next configuration, to turn off encryption on a configured channel pass a
+    /// [ChannelEncryption] with [crate::messages::config::EncryptionMode::Disable].
+    pub fn set_encryption(
+        &mut self,
+        encryption: Option<ChannelEncryption>,
+    ) -> Result<(), ConfigureError> {
+        if encryption.is_some()
+            && self
+                .capabilities
+                .is_some_and(|caps| !caps.supports_encryption())
+        {
+            return Err(ConfigureError::Unsupported(RadioFeature::Encryption));
+        }
+        if matches!(
+            self.channel_state,
+            ChannelState::Searching | ChannelState::Tracking
+        ) {
+            return Err(ConfigureError::ChannelInWrongState {
+                current: self.channel_state,
+                expected: ChannelState::Assigned,
+            });
+        }
+        self.encryption = encryption;
+        if matches!(
+            self.configure_state.get_state(),
+            ConfigureStateId::AddEncryptionId(_)
+                | ConfigureStateId::EncryptionIdList
+                | ConfigureStateId::EnableEncryption
+                | ConfigureStateId::Identify
+                | ConfigureStateId::Done
+        ) {
+            self.configure_state = encryption_state(self, 0);
+            self.configure_pending_response = false;
+        }
+        Ok(())
+    }
+
+    /// Encryption ID of the remote device if the channel has negotiated encryption
+    pub fn get_encryption_peer(&self) -> Option<EncryptionId> {
+        self.encryption_peer
+    }
+
+    /// Set how many times a failed acknowledged send is retried before it is reported failed
+    pub fn set_ack_retries(&mut self, retries: u8) {
+        self.ack_retries = retries;
+    }
+
+    /// Queue `data` to be sent as acknowledged data on the next TX window
+    ///
+    /// Only one acknowledged send can be pending at a time, returns `None` if one already is.
+    /// Track the result with [MessageHandler::ack_status].
+    pub fn send_acknowledged(&mut self, data: [u8; 8]) -> Option<AckHandle> {
+        if self.ack_pending.is_some() {
+            return None;
+        }
+        let handle = AckHandle(self.next_ack_handle);
+        self.next_ack_handle = self.next_ack_handle.wrapping_add(1);
+        self.ack_pending = Some(PendingAck {
+            handle,
+            data,
+            retries_left: self.ack_retries,
+            in_flight: false,
+        });
+        Some(handle)
+    }
+
+    /// Status of an acknowledged send
+    ///
+    /// Only the pending and last finished sends are tracked, older handles return `None`.
+    pub fn ack_status(&self, handle: AckHandle) -> Option<AckStatus> {
+        if self
+            .ack_pending
+            .as_ref()
+            .is_some_and(|ack| ack.handle == handle)
+        {
+            return Some(AckStatus::Pending);
+        }
+        match self.ack_result {
+            Some((last, status)) if last == handle => Some(status),
+            _ => None,
+        }
+    }
+
+    fn resolve_ack(&mut self, completed: bool) {
+        let Some(ack) = self.ack_pending.as_mut().filter(|ack| ack.in_flight) else {
+            return;
+        };
+        let status = if completed {
+            AckStatus::Completed
+        } else if ack.retries_left > 0 {
+            ack.retries_left -= 1;
+            ack.in_flight = false;
+            return;
+        } else {
+            AckStatus::Failed
+        };
+        self.ack_result = Some((ack.handle, status));
+        self.ack_pending = None;
+    }
+
+    /// Returns true if the radio suppresses events with `code` so they will never be received
+    pub fn is_event_filtered(&self, code: MessageCode) -> bool {
+        self.event_filter.filters(code)
+    }
+
     /// Returns true if a TX_EVENT has been recieved since last call.
+    ///
+    /// Only real events make the channel ready, if TX_EVENTs are filtered nothing more is sent
+    /// after the first message. Routers refuse to filter them for master channels.
     pub fn is_tx_ready(&self) -> bool {
         self.tx_ready
     }
@@ -366,7 +885,7 @@ impl MessageHandler {
         self.tx_ready = false;
     }
 
-    pub fn send_message(&mut self) -> Option<TxMessage> {
+    pub fn send_message(&mut self) -> Option<TxMessage<N>> {
         // Walk through configure state machine
         if !self.configure_pending_response {
             let msg = self.configure_state.transmit_config(self.channel, self);
@@ -408,6 +927,27 @@ impl MessageHandler {
             );
         }
 
+        if self.tx_search_timeout_update {
+            self.tx_search_timeout_update = false;
+            return Some(SearchTimeout::new(self.channel, self.search_timeout).into());
+        }
+
+        if self.tx_channel_id_update {
+            self.tx_channel_id_update = false;
+            return Some(
+                ChannelId::new(
+                    self.channel,
+                    self.state_config.device_number,
+                    DeviceType::new(
+                        self.state_config.device_type.device_type_id,
+                        self.pairing_request == DevicePairingState::BitSet,
+                    ),
+                    self.state_config.transmission_type,
+                )
+                .into(),
+            );
+        }
+
         // Handle channel open close command
         if let Some(command) = &self.set_channel_state {
             let msg = match command {
@@ -431,10 +971,18 @@ impl MessageHandler {
             );
         }
 
+        if self.is_tx_ready() {
+            if let Some(ack) = self.ack_pending.as_mut().filter(|ack| !ack.in_flight) {
+                ack.in_flight = true;
+                self.tx_ready = false;
+                return Some(AcknowledgedData::new(self.channel, ack.data).into());
+            }
+        }
+
         None
     }
 
-    pub fn receive_message(&mut self, msg: &AntMessage) -> Result<(), StateError> {
+    pub fn receive_message(&mut self, msg: &AntMessage<N>) -> Result<(), StateError> {
         match &msg.message {
             RxMessage::ChannelResponse(msg) => self.handle_response(msg),
             RxMessage::ChannelEvent(msg) => self.handle_event(msg),
@@ -447,6 +995,26 @@ impl MessageHandler {
                 if self.configure_state.get_state() == ConfigureStateId::Identify {
                     self.tx_channel_id_request = true;
                 }
+                self.set_tracking();
+                Ok(())
+            }
+            RxMessage::EventFilter(filter) => {
+                self.event_filter = *filter;
+                Ok(())
+            }
+            RxMessage::StartUpMessage(_) => {
+                self.event_filter = ConfigureEventFilter::default();
+                self.restart();
+                Ok(())
+            }
+            RxMessage::Capabilities(caps) => {
+                let caps: RadioCapabilities = (*caps).into();
+                self.capabilities = Some(caps);
+                if self.encryption.is_some() && !caps.supports_encryption() {
+                    let state = self.configure_state.get_state();
+                    self.configure_state = &ERROR_STATE;
+                    return Err((state, ConfigureError::Unsupported(RadioFeature::Encryption)));
+                }
                 Ok(())
             }
             _ => Ok(()),
@@ -459,12 +1027,27 @@ impl MessageHandler {
     }
 
     fn handle_response(&mut self, msg: &ChannelResponse) -> Result<(), StateError> {
-        let new_state = self.configure_state.handle_response(msg);
+        if msg.result().is_ok() {
+            match msg.message_id {
+                TxMessageId::AssignChannel => self.channel_state = ChannelState::Assigned,
+                TxMessageId::UnAssignChannel => self.channel_state = ChannelState::UnAssigned,
+                TxMessageId::OpenChannel => {
+                    self.channel_state = ChannelState::Searching;
+                    self.lifecycle_events.push(LifecycleEvent::Searching);
+                }
+                _ => (),
+            }
+        }
+        let new_state = self.configure_state.handle_response(msg, self);
         // TODO add timeout logic here
         if new_state.get_state() == ConfigureStateId::Error {
             let err = Err((
                 self.configure_state.get_state(),
-                ConfigureError::MessageError(msg.message_code),
+                ConfigureError::MessageError(
+                    msg.result()
+                        .err()
+                        .unwrap_or(CommandError::UnexpectedCode(msg.message_code)),
+                ),
             ));
             self.configure_state = new_state;
             return err;
@@ -477,14 +1060,111 @@ impl MessageHandler {
     }
 
     fn handle_event(&mut self, msg: &ChannelEvent) -> Result<(), StateError> {
-        // TODO check how collisions and TransfersFailed should be handled here
+        // TODO check how collisions should be handled here
         match msg.payload.message_code {
-            MessageCode::EventTx | MessageCode::EventTransferTxCompleted => self.tx_ready = true,
+            // Masters get this every period whether or not a slave is listening, it says nothing
+            // about the peer
+            MessageCode::EventTx => self.tx_ready = true,
+            MessageCode::EventTransferTxCompleted => {
+                self.tx_ready = true;
+                // Peer acknowledged the transfer
+                self.set_tracking();
+                self.resolve_ack(true);
+            }
+            MessageCode::EventTransferTxFailed => {
+                self.tx_ready = true;
+                self.resolve_ack(false);
+                self.lifecycle_events.push(LifecycleEvent::TxFailed);
+            }
+            MessageCode::EventRxFailGoToSearch if self.channel_state == ChannelState::Tracking => {
+                self.channel_state = ChannelState::Searching;
+                self.signal_lost = true;
+                self.lifecycle_events.push(LifecycleEvent::SignalLost);
+            }
+            MessageCode::EventRxSearchTimeout => {
+                self.search_timed_out = true;
+                self.lifecycle_events.push(LifecycleEvent::SearchTimeout)
+            }
+            MessageCode::EventChannelClosed => {
+                self.channel_state = ChannelState::Assigned;
+                self.tx_ready = false;
+                self.signal_lost = false;
+                // Closed by the radio or the user, either way it should stay closed
+                self.reopen = false;
+                self.lifecycle_events.push(LifecycleEvent::Closed);
+                if core::mem::take(&mut self.search_timed_out) {
+                    self.fallback_to_wildcard();
+                }
+            }
+            MessageCode::EventChannelCollision => {
+                self.lifecycle_events.push(LifecycleEvent::Collision)
+            }
             _ => (),
         }
+        match msg.extended_info {
+            Some(ChannelEventExtension::EncryptNegotiationSuccess(id, _)) => {
+                self.encryption_peer = Some(id)
+            }
+            Some(ChannelEventExtension::EncryptNegotiationFail(id)) => {
+                self.encryption_peer = None;
+                return Err((
+                    self.configure_state.get_state(),
+                    ConfigureError::EncryptionNegotiationFailed(id),
+                ));
+            }
+            None => (),
+        }
         Ok(())
     }
 
+    // Paired device was not found, search for any device of the same type and identify whatever
+    // turns up
+    fn fallback_to_wildcard(&mut self) {
+        let Some(timeout) = self.wildcard_fallback else {
+            return;
+        };
+        if self.state_config.device_number == 0 {
+            return;
+        }
+        if self.search_timeout != timeout {
+            self.search_timeout = timeout;
+            self.tx_search_timeout_update = true;
+        }
+        self.state_config.device_number = 0;
+        self.state_config.transmission_type = TransmissionType::new_wildcard();
+        self.tx_channel_id_update = true;
+        if self.configure_state.get_state() == ConfigureStateId::Done {
+            self.configure_state = &IDENTIFY_STATE;
+        }
+        self.open();
+        self.lifecycle_events.push(LifecycleEvent::WildcardFallback);
+    }
+
+    // Traffic with the peer, the channel is tracking if it was searching
+    fn set_tracking(&mut self) {
+        if self.channel_state != ChannelState::Searching {
+            return;
+        }
+        self.channel_state = ChannelState::Tracking;
+        let event = if self.signal_lost {
+            LifecycleEvent::Reacquired
+        } else {
+            LifecycleEvent::Tracking
+        };
+        self.signal_lost = false;
+        self.lifecycle_events.push(event);
+    }
+
+    /// Oldest lifecycle event not yet taken
+    pub fn take_lifecycle_event(&mut self) -> Option<LifecycleEvent> {
+        self.lifecycle_events.take()
+    }
+
+    /// Last known state of the channel
+    pub fn channel_state(&self) -> ChannelState {
+        self.channel_state
+    }
+
     fn handle_id(&mut self, msg: &ChannelId) -> Result<(), StateError> {
         if self.configure_state.get_state() == ConfigureStateId::Identify {
             self.configure_state = &DONE_STATE;
@@ -525,10 +1205,35 @@ impl MessageHandler {
 
     pub fn open(&mut self) {
         self.set_channel_state = Some(ChannelStateCommand::Open);
+        self.reopen = true;
     }
 
     pub fn close(&mut self) {
         self.set_channel_state = Some(ChannelStateCommand::Close);
+        self.reopen = false;
+    }
+
+    // The radio reset and lost all channel configuration, rerun it from the start and reopen the
+    // channel if it was open. Bonding information is kept so slaves reacquire the same master.
+    fn restart(&mut self) {
+        self.configure_state = &ASSIGN_STATE;
+        self.configure_pending_response = false;
+        self.tx_ready = false;
+        self.tx_channel_id_request = false;
+        // Configuration sends the current id and search timeout
+        self.tx_channel_id_update = false;
+        self.tx_search_timeout_update = false;
+        self.search_timed_out = false;
+        self.channel_state = ChannelState::UnAssigned;
+        self.signal_lost = false;
+        self.encryption_peer = None;
+        if let Some(ack) = self.ack_pending.as_mut() {
+            // Lost with the reset, send it again once the channel is back
+            ack.in_flight = false;
+        }
+        if self.reopen {
+            self.set_channel_state = Some(ChannelStateCommand::Open);
+        }
     }
 
     /// Resets assumed channel state. Maintains bonding information if `reset_id_data` is `false`.
@@ -537,6 +1242,7 @@ impl MessageHandler {
         self.configure_pending_response = false;
         self.tx_ready = true;
         self.channel_state = ChannelState::UnAssigned;
+        self.encryption_peer = None;
         if reset_id_data {
             self.state_config.device_number = self.state_config.channel_config.device_number;
             self.state_config.transmission_type =
@@ -553,6 +1259,7 @@ mod tests {
     use super::*;
     use crate::channel::duration_to_search_timeout;
     use crate::messages::config::{TransmissionChannelType, TransmissionGlobalDataPages};
+    use crate::messages::data::BroadcastData;
     use crate::messages::{RxMessageHeader, RxSyncByte, TransmitableMessage};
     use core::time::Duration;
     fn get_config() -> ChannelConfig {
@@ -707,12 +1414,100 @@ mod tests {
 
     #[test]
     fn state_transition_on_failure() {
-        // TODO
+        let mut msg_handler = MessageHandler::new(&get_config());
+        get_config_message(&mut msg_handler, TxMessageId::AssignChannel);
+        let mut response = get_response_ok(TxMessageId::AssignChannel);
+        if let RxMessage::ChannelResponse(data) = &mut response.message {
+            data.message_code = MessageCode::InvalidNetworkNumber;
+        }
+        let err = msg_handler.receive_message(&response).unwrap_err();
+        assert_eq!(err.0, ConfigureStateId::Assign);
+        assert!(matches!(
+            err.1,
+            ConfigureError::MessageError(CommandError::InvalidNetworkNumber)
+        ));
+        assert_eq!(msg_handler.send_message(), None);
     }
 
     #[test]
     fn signal_loss() {
-        // TODO
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        configure(&mut msg_handler);
+        assert_eq!(msg_handler.channel_state(), ChannelState::Assigned);
+        msg_handler.open();
+        msg_handler.send_message();
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::OpenChannel))
+            .unwrap();
+        assert_eq!(msg_handler.channel_state(), ChannelState::Searching);
+        msg_handler.receive_message(&get_data()).unwrap();
+        assert_eq!(msg_handler.channel_state(), ChannelState::Tracking);
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventRxFailGoToSearch))
+            .unwrap();
+        assert_eq!(msg_handler.channel_state(), ChannelState::Searching);
+        msg_handler.receive_message(&get_data()).unwrap();
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventChannelCollision))
+            .unwrap();
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventRxSearchTimeout))
+            .unwrap();
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventChannelClosed))
+            .unwrap();
+        assert_eq!(msg_handler.channel_state(), ChannelState::Assigned);
+
+        let expected = [
+            LifecycleEvent::Searching,
+            LifecycleEvent::Tracking,
+            LifecycleEvent::SignalLost,
+            LifecycleEvent::Reacquired,
+            LifecycleEvent::Collision,
+            LifecycleEvent::SearchTimeout,
+            LifecycleEvent::Closed,
+        ];
+        for event in expected {
+            assert_eq!(msg_handler.take_lifecycle_event(), Some(event));
+        }
+        assert_eq!(msg_handler.take_lifecycle_event(), None);
+    }
+
+    #[test]
+    fn master_tracking() {
+        let mut msg_handler = <MessageHandler>::new(&ChannelConfig {
+            channel_type: ChannelType::BidirectionalMaster,
+            ..get_config()
+        });
+        configure(&mut msg_handler);
+        msg_handler.open();
+        msg_handler.send_message();
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::OpenChannel))
+            .unwrap();
+        assert_eq!(
+            msg_handler.take_lifecycle_event(),
+            Some(LifecycleEvent::Searching)
+        );
+
+        // Transmitting every period is not a sign of a peer
+        for _ in 0..3 {
+            msg_handler
+                .receive_message(&get_event(MessageCode::EventTx))
+                .unwrap();
+        }
+        assert_eq!(msg_handler.channel_state(), ChannelState::Searching);
+        assert_eq!(msg_handler.take_lifecycle_event(), None);
+
+        // An acknowledged transfer is
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventTransferTxCompleted))
+            .unwrap();
+        assert_eq!(msg_handler.channel_state(), ChannelState::Tracking);
+        assert_eq!(
+            msg_handler.take_lifecycle_event(),
+            Some(LifecycleEvent::Tracking)
+        );
     }
 
     #[test]
@@ -734,4 +1529,393 @@ mod tests {
     fn reset() {
         // TODO
     }
+
+    #[test]
+    fn restart_after_radio_reset() {
+        use crate::messages::notifications::StartUpMessage;
+        use packed_struct::PackedStruct;
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        configure(&mut msg_handler);
+        msg_handler.open();
+        assert_eq!(
+            msg_handler.send_message().unwrap().get_tx_msg_id(),
+            TxMessageId::OpenChannel
+        );
+
+        let startup = AntMessage::new(RxMessage::StartUpMessage(
+            StartUpMessage::unpack(&[0x20]).unwrap(),
+        ))
+        .unwrap();
+        msg_handler.receive_message(&startup).unwrap();
+        assert!(!msg_handler.is_tx_ready());
+        // Radio is known to be unassigned, configuration starts at assign
+        assert_eq!(
+            msg_handler.send_message().unwrap().get_tx_msg_id(),
+            TxMessageId::AssignChannel
+        );
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::AssignChannel))
+            .unwrap();
+        let reopened = |msg_handler: &mut MessageHandler| {
+            let mut reopened = false;
+            while let Some(data) = msg_handler.send_message() {
+                reopened |= data.get_tx_msg_id() == TxMessageId::OpenChannel;
+                msg_handler
+                    .receive_message(&get_response_ok(data.get_tx_msg_id()))
+                    .unwrap();
+            }
+            reopened
+        };
+        assert!(reopened(&mut msg_handler));
+
+        // Closed channels stay closed
+        msg_handler.close();
+        msg_handler.send_message();
+        msg_handler.receive_message(&startup).unwrap();
+        assert!(!reopened(&mut msg_handler));
+    }
+
+    #[test]
+    fn capabilities() {
+        use crate::messages::control::OpenRxScanMode;
+        use crate::messages::requested_response::Capabilities;
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        let scan = OpenRxScanMode::new(None);
+        assert_eq!(msg_handler.get_capabilities(), None);
+        assert_eq!(msg_handler.check_message(&scan), Ok(()));
+
+        let caps = Capabilities::unpack_from_slice(&[16, 4, 0x15, 0x82]).unwrap();
+        msg_handler
+            .receive_message(&AntMessage::new(RxMessage::Capabilities(caps)).unwrap())
+            .unwrap();
+        assert_eq!(msg_handler.get_capabilities(), Some(caps.into()));
+        assert_eq!(
+            msg_handler.check_message(&scan),
+            Err(RadioFeature::ScanMode)
+        );
+    }
+
+    #[test]
+    fn encryption_config() {
+        use crate::encryption::EncryptionIdList;
+        use crate::messages::config::{EncryptionMode, ListType};
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        let mut encryption = ChannelEncryption::new(EncryptionMode::Enable);
+        let mut list = EncryptionIdList::new(ListType::Whitelist);
+        list.push([1, 2, 3, 4]).unwrap();
+        list.push([5, 6, 7, 8]).unwrap();
+        encryption.id_list = Some(list);
+        msg_handler.set_encryption(Some(encryption)).unwrap();
+
+        get_config_message(&mut msg_handler, TxMessageId::SearchTimeout);
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::SearchTimeout))
+            .unwrap();
+        for i in 0..2 {
+            match msg_handler.send_message() {
+                Some(TxMessage::AddEncryptionIdToList(data)) => {
+                    assert_eq!(data.channel_number, 4);
+                    assert_eq!(data.list_index, i);
+                }
+                msg => panic!("Unexpected message {:?}", msg),
+            }
+            msg_handler
+                .receive_message(&get_response_ok(TxMessageId::AddChannelIdToList))
+                .unwrap();
+        }
+        match msg_handler.send_message() {
+            Some(TxMessage::ConfigEncryptionIdList(data)) => {
+                assert_eq!(data.list_size, 2);
+                assert_eq!(data.list_type, ListType::Whitelist);
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::ConfigIdList))
+            .unwrap();
+        match msg_handler.send_message() {
+            Some(TxMessage::EnableSingleChannelEncryption(data)) => {
+                assert_eq!(data.encryption_mode, EncryptionMode::Enable);
+                assert_eq!(data.decimation_rate, 1);
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        let mut response = get_response_ok(TxMessageId::EnableSingleChannelEncryption);
+        if let RxMessage::ChannelResponse(data) = &mut response.message {
+            data.message_code = MessageCode::InvalidParameterProvided;
+        }
+        let err = msg_handler.receive_message(&response).unwrap_err();
+        assert_eq!(err.0, ConfigureStateId::EnableEncryption);
+        assert!(matches!(
+            err.1,
+            ConfigureError::MessageError(CommandError::InvalidParameterProvided)
+        ));
+    }
+
+    #[test]
+    fn encryption_unsupported() {
+        use crate::messages::config::EncryptionMode;
+        use crate::messages::requested_response::Capabilities;
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        msg_handler
+            .set_encryption(Some(ChannelEncryption::new(EncryptionMode::Enable)))
+            .unwrap();
+        let caps = Capabilities::unpack_from_slice(&[16, 4, 0x15, 0x82]).unwrap();
+        let err = msg_handler
+            .receive_message(&AntMessage::new(RxMessage::Capabilities(caps)).unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            err.1,
+            ConfigureError::Unsupported(RadioFeature::Encryption)
+        ));
+        assert!(matches!(
+            msg_handler.set_encryption(Some(ChannelEncryption::new(EncryptionMode::Enable))),
+            Err(ConfigureError::Unsupported(RadioFeature::Encryption))
+        ));
+    }
+
+    #[test]
+    fn event_filter() {
+        use crate::messages::notifications::StartUpMessage;
+        use packed_struct::PackedStruct;
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        msg_handler.tx_sent();
+        assert!(!msg_handler.is_tx_ready());
+        assert!(!msg_handler.is_event_filtered(MessageCode::EventTx));
+
+        let filter = ConfigureEventFilter::new(
+            false, false, true, false, false, false, false, false, false, false,
+        );
+        msg_handler
+            .receive_message(&AntMessage::new(RxMessage::EventFilter(filter)).unwrap())
+            .unwrap();
+        assert!(msg_handler.is_event_filtered(MessageCode::EventTx));
+        // Filtering never stands in for the event
+        assert!(!msg_handler.is_tx_ready());
+
+        let startup = StartUpMessage::unpack(&[0x20]).unwrap();
+        msg_handler
+            .receive_message(&AntMessage::new(RxMessage::StartUpMessage(startup)).unwrap())
+            .unwrap();
+        assert!(!msg_handler.is_event_filtered(MessageCode::EventTx));
+        assert!(!msg_handler.is_tx_ready());
+    }
+
+    fn get_event(code: MessageCode) -> AntMessage {
+        use crate::messages::channel::ChannelEventPayload;
+        use packed_struct::PackedStruct;
+
+        let payload = ChannelEventPayload::unpack(&[4, 1, code as u8]).unwrap();
+        AntMessage::new(RxMessage::ChannelEvent(ChannelEvent {
+            payload,
+            extended_info: None,
+        }))
+        .unwrap()
+    }
+
+    fn get_data() -> AntMessage {
+        AntMessage::new(RxMessage::BroadcastData(BroadcastData::new(4, [0; 8]))).unwrap()
+    }
+
+    fn configure(msg_handler: &mut MessageHandler) {
+        while let Some(data) = msg_handler.send_message() {
+            msg_handler
+                .receive_message(&get_response_ok(data.get_tx_msg_id()))
+                .unwrap();
+        }
+        msg_handler.handle_id(&ChannelId::default()).unwrap();
+    }
+
+    #[test]
+    fn acknowledged_retry() {
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        configure(&mut msg_handler);
+        msg_handler.set_ack_retries(1);
+        let handle = msg_handler.send_acknowledged([1; 8]).unwrap();
+        assert_eq!(msg_handler.send_acknowledged([2; 8]), None);
+        // Nothing goes out until the channel is ready to transmit
+        assert_eq!(msg_handler.send_message(), None);
+
+        for _ in 0..2 {
+            msg_handler
+                .receive_message(&get_event(MessageCode::EventTx))
+                .unwrap();
+            match msg_handler.send_message() {
+                Some(TxMessage::AcknowledgedData(data)) => {
+                    assert_eq!(data.payload.channel_number, 4);
+                    assert_eq!(data.payload.data, [1; 8]);
+                }
+                msg => panic!("Unexpected message {:?}", msg),
+            }
+            assert!(!msg_handler.is_tx_ready());
+            assert_eq!(msg_handler.ack_status(handle), Some(AckStatus::Pending));
+            msg_handler
+                .receive_message(&get_event(MessageCode::EventTransferTxFailed))
+                .unwrap();
+        }
+        assert_eq!(msg_handler.ack_status(handle), Some(AckStatus::Failed));
+        assert!(msg_handler.is_tx_ready());
+
+        let handle = msg_handler.send_acknowledged([3; 8]).unwrap();
+        assert!(matches!(
+            msg_handler.send_message(),
+            Some(TxMessage::AcknowledgedData(_))
+        ));
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventTransferTxCompleted))
+            .unwrap();
+        assert_eq!(msg_handler.ack_status(handle), Some(AckStatus::Completed));
+    }
+
+    #[test]
+    fn wildcard_fallback() {
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        configure(&mut msg_handler);
+        assert_eq!(msg_handler.get_paired_device(), None);
+        let paired = PairedDevice {
+            device_number: 4321,
+            device_type: 5,
+            transmission_type: TransmissionType::new_wildcard(),
+        };
+        msg_handler.set_paired_device(&paired).unwrap();
+        let fallback_timeout = duration_to_search_timeout(Duration::from_secs(10));
+        msg_handler.set_wildcard_fallback(Some(fallback_timeout));
+        msg_handler.open();
+        match msg_handler.send_message() {
+            Some(TxMessage::ChannelId(id)) => assert_eq!(id.device_number, 4321),
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        assert!(matches!(
+            msg_handler.send_message(),
+            Some(TxMessage::OpenChannel(_))
+        ));
+        assert_eq!(msg_handler.get_paired_device(), Some(paired));
+
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventRxSearchTimeout))
+            .unwrap();
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventChannelClosed))
+            .unwrap();
+        match msg_handler.send_message() {
+            Some(TxMessage::SearchTimeout(timeout)) => {
+                assert_eq!(timeout.search_timeout, fallback_timeout)
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        match msg_handler.send_message() {
+            Some(TxMessage::ChannelId(id)) => assert_eq!(id.device_number, 0),
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        assert!(matches!(
+            msg_handler.send_message(),
+            Some(TxMessage::OpenChannel(_))
+        ));
+        assert_eq!(msg_handler.get_paired_device(), None);
+        let mut events = core::iter::from_fn(|| msg_handler.take_lifecycle_event());
+        assert!(events.any(|event| event == LifecycleEvent::WildcardFallback));
+
+        // Whatever is found next is identified
+        msg_handler
+            .receive_message(&AntMessage {
+                message: RxMessage::BroadcastData(BroadcastData::new(4, [0; 8])),
+                ..get_event(MessageCode::EventTx)
+            })
+            .unwrap();
+        assert!(matches!(
+            msg_handler.send_message(),
+            Some(TxMessage::RequestMessage(_))
+        ));
+
+        // Back to the paired device, only once the channel is closed
+        msg_handler
+            .receive_message(&get_response_ok(TxMessageId::OpenChannel))
+            .unwrap();
+        assert!(matches!(
+            msg_handler.set_paired_device(&paired),
+            Err(ConfigureError::ChannelInWrongState { .. })
+        ));
+        msg_handler
+            .receive_message(&get_event(MessageCode::EventChannelClosed))
+            .unwrap();
+        msg_handler.set_paired_device(&paired).unwrap();
+        match get_config_message(&mut msg_handler, TxMessageId::SearchTimeout) {
+            TxMessage::SearchTimeout(timeout) => assert_eq!(
+                timeout.search_timeout,
+                duration_to_search_timeout(Duration::from_secs(30))
+            ),
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn id_list_config() {
+        use crate::id_list::ListedDevice;
+        use crate::messages::config::ListExclusion;
+        use crate::messages::notifications::StartUpMessage;
+        use packed_struct::PackedStruct;
+
+        let mut msg_handler = <MessageHandler>::new(&get_config());
+        let mut list = ChannelIdList::new(ListExclusion::Exclude);
+        list.push(ListedDevice::new(11, 120)).unwrap();
+        list.push(ListedDevice::new(12, 120)).unwrap();
+        msg_handler.set_id_list(Some(list)).unwrap();
+
+        for _ in 0..2 {
+            get_config_message(&mut msg_handler, TxMessageId::SearchTimeout);
+            msg_handler
+                .receive_message(&get_response_ok(TxMessageId::SearchTimeout))
+                .unwrap();
+            for i in 0..2 {
+                match msg_handler.send_message() {
+                    Some(TxMessage::AddChannelIdToList(data)) => {
+                        assert_eq!(data.channel_number, 4);
+                        assert_eq!(data.device_number, 11 + i as u16);
+                        assert_eq!(data.list_index, i);
+                    }
+                    msg => panic!("Unexpected message {:?}", msg),
+                }
+                msg_handler
+                    .receive_message(&get_response_ok(TxMessageId::AddChannelIdToList))
+                    .unwrap();
+            }
+            match msg_handler.send_message() {
+                Some(TxMessage::ConfigIdList(data)) => {
+                    assert_eq!(data.list_size, 2);
+                    assert_eq!(data.exclude, ListExclusion::Exclude);
+                }
+                msg => panic!("Unexpected message {:?}", msg),
+            }
+            msg_handler
+                .receive_message(&get_response_ok(TxMessageId::ConfigIdList))
+                .unwrap();
+            assert_eq!(
+                msg_handler.configure_state.get_state(),
+                ConfigureStateId::Identify
+            );
+            // List is applied again after a radio reset
+            msg_handler
+                .receive_message(&AntMessage {
+                    message: RxMessage::StartUpMessage(StartUpMessage::unpack(&[0]).unwrap()),
+                    ..get_response_ok(TxMessageId::ConfigIdList)
+                })
+                .unwrap();
+        }
+
+        // Changing the list on a configured channel reruns the list steps
+        configure(&mut msg_handler);
+        msg_handler
+            .set_id_list(Some(ChannelIdList::new(ListExclusion::Include)))
+            .unwrap();
+        match msg_handler.send_message() {
+            Some(TxMessage::ConfigIdList(data)) => assert_eq!(data.list_size, 0),
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        assert_eq!(msg_handler.get_id_list().unwrap().len(), 0);
+    }
 }
@@ -0,0 +1,353 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pairings that outlive the process
+//!
+//! A slave channel opened with a wildcarded device number binds to whatever it finds first. Once
+//! [crate::plus::common::msg_handler::MessageHandler] has identified the device its channel ID can
+//! be saved in a [PairingStore] slot, e.g. one per user, and handed back on the next start so the
+//! channel searches for that device only.
+//!
+//! Stores are written to a [PairingStorage] backend, [FileStorage] under std or any flash or
+//! EEPROM driver on targets without a filesystem.
+
+use crate::messages::config::TransmissionType;
+use crate::plus::common::msg_handler::ChannelConfig;
+use core::convert::Infallible;
+use packed_struct::PackedStruct;
+
+/// Marks the start of a saved store
+const MAGIC: [u8; 4] = *b"ANTP";
+const VERSION: u8 = 1;
+/// Magic, version and slot count
+const HEADER_SIZE: usize = MAGIC.len() + 2;
+/// Present flag, device number, device type and transmission type
+const RECORD_SIZE: usize = 5;
+
+/// Channel ID of a paired device
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PairedDevice {
+    pub device_number: u16,
+    pub device_type: u8,
+    pub transmission_type: TransmissionType,
+}
+
+impl PairedDevice {
+    /// Point `config` at this device
+    pub fn apply(&self, config: &mut ChannelConfig) {
+        config.device_number = self.device_number;
+        config.device_type = self.device_type;
+        config.transmission_type = self.transmission_type;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PairingError<E = Infallible> {
+    /// Backend failed to read or write
+    Storage(E),
+    /// Saved data is not a pairing store or was written by an incompatible version
+    Corrupt,
+    /// Slot is past the end of the store
+    SlotOutOfBounds,
+    /// Buffer too small for the store
+    BufferTooSmall,
+}
+
+impl PairingError {
+    fn storage<E>(self) -> PairingError<E> {
+        match self {
+            PairingError::Storage(e) => match e {},
+            PairingError::Corrupt => PairingError::Corrupt,
+            PairingError::SlotOutOfBounds => PairingError::SlotOutOfBounds,
+            PairingError::BufferTooSmall => PairingError::BufferTooSmall,
+        }
+    }
+}
+
+/// Where a [PairingStore] is saved
+///
+/// The store is always written and read as a whole, backends only need to keep the last block of
+/// bytes written.
+pub trait PairingStorage {
+    type Error;
+
+    /// Read the saved bytes into `buf` and return how many were read, 0 if nothing was saved
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+    /// Replace the saved bytes with `data`
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Paired devices kept in `S` slots, at most 255 slots can be saved
+#[derive(Clone, Debug, PartialEq)]
+pub struct PairingStore<const S: usize> {
+    slots: [Option<PairedDevice>; S],
+}
+
+impl<const S: usize> Default for PairingStore<S> {
+    fn default() -> Self {
+        Self { slots: [None; S] }
+    }
+}
+
+impl<const S: usize> PairingStore<S> {
+    /// Bytes needed to save the store
+    pub const ENCODED_SIZE: usize = HEADER_SIZE + S * RECORD_SIZE;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, slot: usize) -> Option<PairedDevice> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Pair `slot` with `device`, returns the device it was paired with before
+    pub fn insert(
+        &mut self,
+        slot: usize,
+        device: PairedDevice,
+    ) -> Result<Option<PairedDevice>, PairingError> {
+        Ok(self
+            .slots
+            .get_mut(slot)
+            .ok_or(PairingError::SlotOutOfBounds)?
+            .replace(device))
+    }
+
+    pub fn remove(&mut self, slot: usize) -> Option<PairedDevice> {
+        self.slots.get_mut(slot)?.take()
+    }
+
+    /// Slot holding `device`, if any
+    pub fn find(&self, device: &PairedDevice) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.as_ref() == Some(device))
+    }
+
+    /// Occupied slots and their devices
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &PairedDevice)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, device)| device.as_ref().map(|device| (slot, device)))
+    }
+
+    /// Write the store to `buf`, returns the bytes used
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, PairingError> {
+        let buf = buf
+            .get_mut(..Self::ENCODED_SIZE)
+            .ok_or(PairingError::BufferTooSmall)?;
+        let (header, records) = buf.split_at_mut(HEADER_SIZE);
+        header[..MAGIC.len()].copy_from_slice(&MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[MAGIC.len() + 1] = S as u8;
+        for (record, device) in records.chunks_exact_mut(RECORD_SIZE).zip(&self.slots) {
+            record.fill(0);
+            if let Some(device) = device {
+                let [low, high] = device.device_number.to_le_bytes();
+                record[0] = 1;
+                record[1] = low;
+                record[2] = high;
+                record[3] = device.device_type;
+                record[4] = device
+                    .transmission_type
+                    .pack()
+                    .map_err(|_| PairingError::Corrupt)?[0];
+            }
+        }
+        Ok(Self::ENCODED_SIZE)
+    }
+
+    /// Read a store written by [PairingStore::encode]
+    ///
+    /// A store saved with a different number of slots is read as far as it fits, extra slots are
+    /// left empty.
+    pub fn decode(buf: &[u8]) -> Result<Self, PairingError> {
+        if buf.len() < HEADER_SIZE || buf[..MAGIC.len()] != MAGIC || buf[MAGIC.len()] != VERSION {
+            return Err(PairingError::Corrupt);
+        }
+        let saved = buf[MAGIC.len() + 1] as usize;
+        let records = buf[HEADER_SIZE..]
+            .get(..saved * RECORD_SIZE)
+            .ok_or(PairingError::Corrupt)?;
+        let mut store = Self::new();
+        for (slot, record) in store
+            .slots
+            .iter_mut()
+            .zip(records.chunks_exact(RECORD_SIZE))
+        {
+            *slot = match record[0] {
+                0 => None,
+                1 => Some(PairedDevice {
+                    device_number: u16::from_le_bytes([record[1], record[2]]),
+                    device_type: record[3],
+                    transmission_type: TransmissionType::unpack(&[record[4]])
+                        .map_err(|_| PairingError::Corrupt)?,
+                }),
+                _ => return Err(PairingError::Corrupt),
+            };
+        }
+        Ok(store)
+    }
+
+    pub fn save<T: PairingStorage>(&self, storage: &mut T) -> Result<(), PairingError<T::Error>> {
+        // Sized for the largest store a slot count byte can describe
+        let mut buf = [0; HEADER_SIZE + u8::MAX as usize * RECORD_SIZE];
+        let len = self.encode(&mut buf).map_err(PairingError::storage)?;
+        storage.write(&buf[..len]).map_err(PairingError::Storage)
+    }
+
+    /// Load the store from `storage`, an empty store if nothing was saved yet
+    pub fn load<T: PairingStorage>(storage: &mut T) -> Result<Self, PairingError<T::Error>> {
+        let mut buf = [0; HEADER_SIZE + u8::MAX as usize * RECORD_SIZE];
+        match storage.read(&mut buf).map_err(PairingError::Storage)? {
+            0 => Ok(Self::new()),
+            len => Self::decode(&buf[..len]).map_err(PairingError::storage),
+        }
+    }
+}
+
+/// Saves a pairing store to a file, a missing file reads as nothing saved
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl FileStorage {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl PairingStorage for FileStorage {
+    type Error = std::io::Error;
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        use std::io::Read;
+        match std::fs::File::open(&self.path) {
+            Ok(mut file) => {
+                let mut len = 0;
+                while len < buf.len() {
+                    match file.read(&mut buf[len..])? {
+                        0 => break,
+                        read => len += read,
+                    }
+                }
+                Ok(len)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        std::fs::write(&self.path, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::config::{TransmissionChannelType, TransmissionGlobalDataPages};
+
+    #[derive(Default)]
+    struct MemoryStorage {
+        data: Option<([u8; 64], usize)>,
+    }
+
+    impl PairingStorage for MemoryStorage {
+        type Error = ();
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+            let Some((data, len)) = self.data else {
+                return Ok(0);
+            };
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<(), ()> {
+            let mut saved = [0; 64];
+            saved.get_mut(..data.len()).ok_or(())?.copy_from_slice(data);
+            self.data = Some((saved, data.len()));
+            Ok(())
+        }
+    }
+
+    fn device(device_number: u16) -> PairedDevice {
+        PairedDevice {
+            device_number,
+            device_type: 120,
+            transmission_type: TransmissionType::new(
+                TransmissionChannelType::IndependentChannel,
+                TransmissionGlobalDataPages::GlobalDataPagesNotUsed,
+                5.into(),
+            ),
+        }
+    }
+
+    #[test]
+    fn slots() {
+        let mut store = PairingStore::<2>::new();
+        assert_eq!(store.insert(0, device(1)), Ok(None));
+        assert_eq!(store.insert(0, device(2)), Ok(Some(device(1))));
+        assert_eq!(
+            store.insert(2, device(3)),
+            Err(PairingError::SlotOutOfBounds)
+        );
+        assert_eq!(store.find(&device(2)), Some(0));
+        assert_eq!(store.iter().count(), 1);
+        assert_eq!(store.remove(0), Some(device(2)));
+        assert_eq!(store.get(0), None);
+    }
+
+    #[test]
+    fn save_load() {
+        let mut storage = MemoryStorage::default();
+        assert_eq!(
+            PairingStore::<3>::load(&mut storage),
+            Ok(PairingStore::new())
+        );
+
+        let mut store = PairingStore::<3>::new();
+        store.insert(0, device(0x1234)).unwrap();
+        store.insert(2, device(7)).unwrap();
+        store.save(&mut storage).unwrap();
+        assert_eq!(PairingStore::<3>::load(&mut storage), Ok(store.clone()));
+
+        // Fewer slots keeps what fits
+        let smaller = PairingStore::<1>::load(&mut storage).unwrap();
+        assert_eq!(smaller.get(0), Some(device(0x1234)));
+
+        storage.write(b"nope").unwrap();
+        assert_eq!(
+            PairingStore::<3>::load(&mut storage),
+            Err(PairingError::Corrupt)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_storage() {
+        let path = std::env::temp_dir().join(format!("ant-pairing-{}", std::process::id()));
+        let mut storage = FileStorage::new(&path);
+        assert_eq!(
+            PairingStore::<2>::load(&mut storage).unwrap(),
+            PairingStore::new()
+        );
+        let mut store = PairingStore::<2>::new();
+        store.insert(1, device(42)).unwrap();
+        store.save(&mut storage).unwrap();
+        assert_eq!(PairingStore::<2>::load(&mut storage).unwrap(), store);
+        std::fs::remove_file(path).unwrap();
+    }
+}
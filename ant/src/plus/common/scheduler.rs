@@ -0,0 +1,716 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Time sliced search across more sensors than the radio has channels
+//!
+//! [SearchScheduler] owns a set of hardware channels and a larger pool of sensors. Sensors that
+//! are not being tracked take turns searching on the free hardware channels, each for one low
+//! priority search window. When the window times out the radio closes the channel and the next
+//! sensor in line gets it. Once a sensor is found it keeps its channel until the radio gives up
+//! on it, after which it rejoins the rotation.
+//!
+//! Which sensor searches next is set by [Fairness], sensor priorities break ties. Every channel
+//! the scheduler owns is driven from the same [SearchScheduler::send_message] and
+//! [SearchScheduler::receive_message] pair, sensors changing state are reported through
+//! [SearchScheduler::take_event].
+
+use crate::messages::channel::{CommandError, MessageCode};
+use crate::messages::config::{
+    AssignChannel, ChannelId, ChannelPeriod, ChannelRfFrequency, ChannelSearchPriority,
+    ChannelSearchSharing, ConfigError, DeviceType, LowPrioritySearchTimeout, SearchTimeout,
+    UnAssignChannel,
+};
+use crate::messages::control::{CloseChannel, OpenChannel};
+use crate::messages::requested_response::RadioCapabilities;
+use crate::messages::{AntMessage, RxMessage, TransmitableMessage, TxMessage, TxMessageId};
+use crate::plus::common::event_queue::EventQueue;
+use crate::plus::common::msg_handler::ChannelConfig;
+use crate::router::{message_channel, MAX_CHANNELS};
+use arrayvec::ArrayVec;
+
+/// Events held until taken with [SearchScheduler::take_event], oldest are dropped first
+pub const MAX_SCHEDULER_EVENTS: usize = 16;
+
+/// Order in which waiting sensors get a search window
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Fairness {
+    /// Sensors search in the order they started waiting
+    #[default]
+    RoundRobin,
+    /// Higher priority sensors search first, equal priorities take turns
+    Priority,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SchedulerConfig {
+    /// Length of each search window in counts of 2.5s
+    ///
+    /// Shorter windows rotate through waiting sensors faster, longer windows give each sensor
+    /// more time to be found.
+    pub search_window: u8,
+    /// High priority search before each window in counts of 2.5s
+    ///
+    /// High priority search can interrupt tracked channels, leave at 0 unless acquisition latency
+    /// matters more than dropped messages.
+    pub high_priority_timeout: u8,
+    /// Sent as [ChannelSearchPriority] for every window if set
+    pub search_priority: Option<u8>,
+    /// Sent as [ChannelSearchSharing] for every window if set
+    pub search_sharing_cycles: Option<u8>,
+    pub fairness: Fairness,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            search_window: 2,
+            high_priority_timeout: 0,
+            search_priority: None,
+            search_sharing_cycles: None,
+            fairness: Fairness::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedulerError {
+    /// Sensor pool is full
+    Full,
+    /// Sensor configuration has values the radio rejects
+    InvalidConfig(ConfigError),
+}
+
+/// Handle to a sensor in the pool
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SensorId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SensorState {
+    /// In line for a search window
+    Waiting,
+    /// Searching on the hardware channel
+    Searching(u8),
+    /// Found and tracked on the hardware channel
+    Tracking(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchedulerEvent {
+    /// Search window opened on the hardware channel
+    Searching(SensorId, u8),
+    /// Sensor found, it keeps the hardware channel
+    Acquired(SensorId, u8),
+    /// Tracked sensor dropped out, it searches out the window then rejoins the rotation
+    Lost(SensorId),
+    /// Search window ended without finding the sensor
+    SearchTimeout(SensorId),
+    /// Radio rejected part of the channel configuration, the sensor rejoins the rotation
+    ConfigFailed(SensorId, CommandError),
+}
+
+#[derive(Clone, Copy)]
+struct Sensor {
+    config: ChannelConfig,
+    priority: u8,
+    state: SensorState,
+    /// Position in the rotation, lower searches first
+    ticket: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Assign,
+    Id,
+    Period,
+    RfFrequency,
+    SearchTimeout,
+    LowPrioritySearchTimeout,
+    SearchPriority,
+    SearchSharing,
+    Open,
+}
+
+impl Step {
+    fn next(self) -> Step {
+        match self {
+            Step::Assign => Step::Id,
+            Step::Id => Step::Period,
+            Step::Period => Step::RfFrequency,
+            Step::RfFrequency => Step::SearchTimeout,
+            Step::SearchTimeout => Step::LowPrioritySearchTimeout,
+            Step::LowPrioritySearchTimeout => Step::SearchPriority,
+            Step::SearchPriority => Step::SearchSharing,
+            Step::SearchSharing | Step::Open => Step::Open,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SlotState {
+    /// Unassigned and ready for the next sensor
+    Free,
+    Configuring(Step),
+    Open,
+    /// Channel needs closing
+    Close,
+    /// Close accepted, waiting for the channel to report closed
+    Closing,
+    /// Channel needs unassigning
+    UnAssign,
+}
+
+// A hardware channel owned by the scheduler
+struct Slot {
+    channel: u8,
+    sensor: Option<usize>,
+    state: SlotState,
+    /// Message sent and waiting on its response
+    pending: Option<TxMessageId>,
+}
+
+/// Rotates a pool of up to `S` sensors through a set of hardware channels
+pub struct SearchScheduler<const S: usize> {
+    config: SchedulerConfig,
+    slots: ArrayVec<Slot, MAX_CHANNELS>,
+    sensors: [Option<Sensor>; S],
+    next_ticket: u32,
+    capabilities: Option<RadioCapabilities>,
+    events: EventQueue<SchedulerEvent, MAX_SCHEDULER_EVENTS>,
+}
+
+impl<const S: usize> SearchScheduler<S> {
+    /// Schedule searches on `channels`
+    ///
+    /// The channels must already be routed to the scheduler and not be used by anything else,
+    /// only the first [MAX_CHANNELS] are used.
+    pub fn new(config: SchedulerConfig, channels: &[u8]) -> Self {
+        Self {
+            config,
+            slots: channels
+                .iter()
+                .take(MAX_CHANNELS)
+                .map(|&channel| Slot {
+                    channel,
+                    sensor: None,
+                    state: SlotState::Free,
+                    pending: None,
+                })
+                .collect(),
+            sensors: [None; S],
+            next_ticket: 0,
+            capabilities: None,
+            events: EventQueue::new(),
+        }
+    }
+
+    /// Add a sensor to the rotation
+    ///
+    /// `config.channel` and `config.timeout_duration` are ignored, the scheduler picks the
+    /// channel and search timeouts.
+    pub fn add_sensor(
+        &mut self,
+        config: ChannelConfig,
+        priority: u8,
+    ) -> Result<SensorId, SchedulerError> {
+        config.validate().map_err(SchedulerError::InvalidConfig)?;
+        let index = self
+            .sensors
+            .iter()
+            .position(Option::is_none)
+            .ok_or(SchedulerError::Full)?;
+        let ticket = self.ticket();
+        self.sensors[index] = Some(Sensor {
+            config,
+            priority,
+            state: SensorState::Waiting,
+            ticket,
+        });
+        Ok(SensorId(index))
+    }
+
+    /// Remove a sensor, closing its channel if it has one
+    pub fn remove_sensor(&mut self, id: SensorId) {
+        if self.sensors.get_mut(id.0).and_then(Option::take).is_none() {
+            return;
+        }
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.sensor == Some(id.0)) {
+            slot.sensor = None;
+            slot.state = match slot.state {
+                SlotState::Configuring(Step::Assign) if slot.pending.is_none() => SlotState::Free,
+                SlotState::Configuring(_) | SlotState::Open => SlotState::Close,
+                state => state,
+            };
+        }
+    }
+
+    pub fn sensor_state(&self, id: SensorId) -> Option<SensorState> {
+        self.sensor(id.0).map(|sensor| sensor.state)
+    }
+
+    /// Sensor currently using the hardware `channel`
+    pub fn sensor_for_channel(&self, channel: u8) -> Option<SensorId> {
+        self.slots
+            .iter()
+            .find(|slot| slot.channel == channel)
+            .and_then(|slot| slot.sensor)
+            .map(SensorId)
+    }
+
+    /// Oldest event not yet taken
+    pub fn take_event(&mut self) -> Option<SchedulerEvent> {
+        self.events.take()
+    }
+
+    /// Next message to send to the radio
+    pub fn send_message<const N: usize>(&mut self) -> Option<TxMessage<N>> {
+        for index in 0..self.slots.len() {
+            if self.slots[index].pending.is_some() {
+                continue;
+            }
+            if self.slots[index].state == SlotState::Free {
+                let Some(sensor) = self.next_waiting() else {
+                    continue;
+                };
+                let slot = &mut self.slots[index];
+                slot.sensor = Some(sensor);
+                slot.state = SlotState::Configuring(Step::Assign);
+                let channel = slot.channel;
+                if let Some(sensor) = self.sensor_mut(sensor) {
+                    sensor.state = SensorState::Searching(channel);
+                }
+            }
+            if let Some(msg) = self.slot_message(index) {
+                self.slots[index].pending = Some(msg.get_tx_msg_id());
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    /// Update the schedule from a message received from the radio
+    ///
+    /// Returns the sensor the message is for if it arrived on one of the scheduler's channels so
+    /// the caller can pass it on, e.g. to decode datapages.
+    pub fn receive_message<const N: usize>(&mut self, msg: &AntMessage<N>) -> Option<SensorId> {
+        match &msg.message {
+            RxMessage::Capabilities(caps) => {
+                self.capabilities = Some((*caps).into());
+                return None;
+            }
+            RxMessage::StartUpMessage(_) => {
+                self.restart();
+                return None;
+            }
+            _ => (),
+        }
+        let channel = message_channel(&msg.message)?;
+        let index = self.slots.iter().position(|slot| slot.channel == channel)?;
+        match &msg.message {
+            RxMessage::ChannelResponse(response) => {
+                if self.slots[index].pending != Some(response.message_id) {
+                    return None;
+                }
+                self.slots[index].pending = None;
+                self.handle_response(index, response.message_id, response.result());
+            }
+            RxMessage::ChannelEvent(event) => {
+                self.handle_event(index, event.payload.message_code);
+            }
+            RxMessage::BroadcastData(_)
+            | RxMessage::AcknowledgedData(_)
+            | RxMessage::BurstTransferData(_)
+            | RxMessage::AdvancedBurstData(_) => {
+                if let Some(sensor) = self.slots[index].sensor {
+                    if let Some(state) = self.sensor_mut(sensor).map(|sensor| &mut sensor.state) {
+                        if let SensorState::Searching(_) = *state {
+                            *state = SensorState::Tracking(channel);
+                            self.events
+                                .push(SchedulerEvent::Acquired(SensorId(sensor), channel));
+                        }
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.slots[index].sensor.map(SensorId)
+    }
+
+    fn handle_response(
+        &mut self,
+        index: usize,
+        message_id: TxMessageId,
+        result: Result<(), CommandError>,
+    ) {
+        let slot = &mut self.slots[index];
+        let channel = slot.channel;
+        let sensor = slot.sensor;
+        match (slot.state, message_id) {
+            (SlotState::Configuring(step), _) => match result {
+                Ok(()) if step == Step::Open => {
+                    slot.state = SlotState::Open;
+                    if let Some(sensor) = sensor {
+                        self.events
+                            .push(SchedulerEvent::Searching(SensorId(sensor), channel));
+                    }
+                }
+                Ok(()) => slot.state = SlotState::Configuring(step.next()),
+                Err(e) => {
+                    slot.sensor = None;
+                    slot.state = match step {
+                        Step::Assign => SlotState::Free,
+                        _ => SlotState::UnAssign,
+                    };
+                    if let Some(sensor) = sensor {
+                        self.requeue(sensor);
+                        self.events
+                            .push(SchedulerEvent::ConfigFailed(SensorId(sensor), e));
+                    }
+                }
+            },
+            (SlotState::Close, TxMessageId::CloseChannel) => {
+                // Failing means the channel was not open to begin with
+                slot.state = match result {
+                    Ok(()) => SlotState::Closing,
+                    Err(_) => SlotState::UnAssign,
+                };
+            }
+            (SlotState::UnAssign, TxMessageId::UnAssignChannel) => slot.state = SlotState::Free,
+            _ => (),
+        }
+    }
+
+    fn handle_event(&mut self, index: usize, code: MessageCode) {
+        let sensor = self.slots[index].sensor;
+        match code {
+            MessageCode::EventRxSearchTimeout => {
+                if let Some(sensor) = sensor {
+                    self.events
+                        .push(SchedulerEvent::SearchTimeout(SensorId(sensor)));
+                }
+            }
+            MessageCode::EventRxFailGoToSearch => {
+                if let Some(sensor) = sensor {
+                    if let Some(state) = self.sensor_mut(sensor).map(|sensor| &mut sensor.state) {
+                        if let SensorState::Tracking(channel) = *state {
+                            *state = SensorState::Searching(channel);
+                            self.events.push(SchedulerEvent::Lost(SensorId(sensor)));
+                        }
+                    }
+                }
+            }
+            MessageCode::EventChannelClosed => {
+                let slot = &mut self.slots[index];
+                slot.state = SlotState::UnAssign;
+                if let Some(sensor) = slot.sensor.take() {
+                    self.requeue(sensor);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Radio reset, every channel is unassigned and every sensor needs a new window
+    fn restart(&mut self) {
+        for index in 0..self.slots.len() {
+            let slot = &mut self.slots[index];
+            slot.state = SlotState::Free;
+            slot.pending = None;
+            if let Some(sensor) = slot.sensor.take() {
+                self.requeue(sensor);
+            }
+        }
+    }
+
+    fn slot_message<const N: usize>(&mut self, index: usize) -> Option<TxMessage<N>> {
+        let slot = &self.slots[index];
+        let channel = slot.channel;
+        match slot.state {
+            SlotState::Free | SlotState::Open | SlotState::Closing => None,
+            SlotState::Close => Some(CloseChannel::new(channel).into()),
+            SlotState::UnAssign => Some(UnAssignChannel::new(channel).into()),
+            SlotState::Configuring(mut step) => {
+                let config = self.sensor(slot.sensor?)?.config;
+                loop {
+                    if let Some(msg) = self.step_message(step, channel, &config) {
+                        self.slots[index].state = SlotState::Configuring(step);
+                        return Some(msg);
+                    }
+                    step = step.next();
+                }
+            }
+        }
+    }
+
+    // Message for `step`, None if the step does not apply
+    fn step_message<const N: usize>(
+        &self,
+        step: Step,
+        channel: u8,
+        config: &ChannelConfig,
+    ) -> Option<TxMessage<N>> {
+        let msg: TxMessage<N> = match step {
+            Step::Assign => {
+                AssignChannel::new(channel, config.channel_type, config.network_key_index, None)
+                    .into()
+            }
+            Step::Id => ChannelId::new(
+                channel,
+                config.device_number,
+                DeviceType::new(config.device_type.into(), false),
+                config.transmission_type,
+            )
+            .into(),
+            Step::Period => ChannelPeriod::new(channel, config.channel_period).into(),
+            Step::RfFrequency => ChannelRfFrequency::new(channel, config.radio_frequency).into(),
+            Step::SearchTimeout => {
+                // Without low priority search the whole window is spent in high priority
+                let timeout = if self.supports_low_priority_search() {
+                    self.config.high_priority_timeout
+                } else {
+                    self.config.search_window
+                };
+                SearchTimeout::new(channel, timeout).into()
+            }
+            Step::LowPrioritySearchTimeout => {
+                LowPrioritySearchTimeout::new(channel, self.config.search_window).into()
+            }
+            Step::SearchPriority => {
+                ChannelSearchPriority::new(channel, self.config.search_priority?).into()
+            }
+            Step::SearchSharing => {
+                ChannelSearchSharing::new(channel, self.config.search_sharing_cycles?).into()
+            }
+            Step::Open => OpenChannel::new(channel).into(),
+        };
+        // Optional tuning the radio cannot do is skipped rather than failing the window
+        match &self.capabilities {
+            Some(caps) if step != Step::Open && caps.check_message(&msg).is_err() => None,
+            _ => Some(msg),
+        }
+    }
+
+    fn supports_low_priority_search(&self) -> bool {
+        self.capabilities
+            .is_none_or(|caps| caps.supports_low_priority_search())
+    }
+
+    // Waiting sensor next in line for a search window
+    fn next_waiting(&self) -> Option<usize> {
+        let waiting = self
+            .sensors
+            .iter()
+            .enumerate()
+            .filter_map(|(index, sensor)| sensor.map(|sensor| (index, sensor)))
+            .filter(|(_, sensor)| sensor.state == SensorState::Waiting);
+        match self.config.fairness {
+            Fairness::RoundRobin => waiting.min_by_key(|(_, sensor)| sensor.ticket),
+            Fairness::Priority => waiting
+                .min_by_key(|(_, sensor)| (core::cmp::Reverse(sensor.priority), sensor.ticket)),
+        }
+        .map(|(index, _)| index)
+    }
+
+    // Send a sensor to the back of the line
+    fn requeue(&mut self, sensor: usize) {
+        let ticket = self.ticket();
+        if let Some(sensor) = self.sensor_mut(sensor) {
+            sensor.state = SensorState::Waiting;
+            sensor.ticket = ticket;
+        }
+    }
+
+    fn ticket(&mut self) -> u32 {
+        let ticket = self.next_ticket;
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+        ticket
+    }
+
+    fn sensor(&self, index: usize) -> Option<&Sensor> {
+        self.sensors.get(index).and_then(Option::as_ref)
+    }
+
+    fn sensor_mut(&mut self, index: usize) -> Option<&mut Sensor> {
+        self.sensors.get_mut(index).and_then(Option::as_mut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::config::{
+        ChannelType, TransmissionChannelType, TransmissionGlobalDataPages, TransmissionType,
+    };
+    use crate::messages::data::BroadcastData;
+    use crate::plus::common::test_util::{accept_all, event, response, rx, Responder};
+
+    fn get_config(device_number: u16) -> ChannelConfig {
+        ChannelConfig {
+            channel: 0,
+            device_number,
+            device_type: 120,
+            channel_type: ChannelType::BidirectionalSlave,
+            transmission_type: TransmissionType::new(
+                TransmissionChannelType::IndependentChannel,
+                TransmissionGlobalDataPages::GlobalDataPagesNotUsed,
+                0.into(),
+            ),
+            radio_frequency: 57,
+            timeout_duration: 0,
+            channel_period: 8070,
+            network_key_index: 0,
+        }
+    }
+
+    impl<const S: usize> Responder for SearchScheduler<S> {
+        fn next_message(&mut self) -> Option<TxMessageId> {
+            self.send_message::<64>().map(|msg| msg.get_tx_msg_id())
+        }
+
+        fn receive(&mut self, msg: &AntMessage) {
+            self.receive_message(msg);
+        }
+    }
+
+    // Radio times the search out and closes the channel
+    fn time_out<const S: usize>(scheduler: &mut SearchScheduler<S>, channel: u8) {
+        scheduler.receive_message(&event(channel, MessageCode::EventRxSearchTimeout));
+        scheduler.receive_message(&event(channel, MessageCode::EventChannelClosed));
+    }
+
+    #[test]
+    fn rotation() {
+        let mut scheduler = SearchScheduler::<3>::new(SchedulerConfig::default(), &[0]);
+        let a = scheduler.add_sensor(get_config(1), 0).unwrap();
+        let b = scheduler.add_sensor(get_config(2), 0).unwrap();
+        let c = scheduler.add_sensor(get_config(3), 0).unwrap();
+        assert_eq!(
+            scheduler.add_sensor(get_config(4), 0),
+            Err(SchedulerError::Full)
+        );
+        let mut config = get_config(4);
+        config.channel_period = 0;
+        assert!(matches!(
+            scheduler.add_sensor(config, 0),
+            Err(SchedulerError::InvalidConfig(_))
+        ));
+
+        let sent = accept_all(&mut scheduler, 0);
+        assert_eq!(sent.first(), Some(&TxMessageId::AssignChannel));
+        assert_eq!(sent.last(), Some(&TxMessageId::OpenChannel));
+        assert!(!sent.contains(&TxMessageId::ChannelSearchPriority));
+        assert_eq!(scheduler.sensor_state(a), Some(SensorState::Searching(0)));
+        assert_eq!(scheduler.sensor_state(b), Some(SensorState::Waiting));
+        assert_eq!(
+            scheduler.take_event(),
+            Some(SchedulerEvent::Searching(a, 0))
+        );
+
+        time_out(&mut scheduler, 0);
+        assert_eq!(
+            scheduler.take_event(),
+            Some(SchedulerEvent::SearchTimeout(a))
+        );
+        assert_eq!(scheduler.sensor_state(a), Some(SensorState::Waiting));
+        let sent = accept_all(&mut scheduler, 0);
+        assert_eq!(sent[0], TxMessageId::UnAssignChannel);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(b));
+
+        time_out(&mut scheduler, 0);
+        accept_all(&mut scheduler, 0);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(c));
+        time_out(&mut scheduler, 0);
+        accept_all(&mut scheduler, 0);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(a));
+    }
+
+    #[test]
+    fn acquire_and_lose() {
+        let mut scheduler = SearchScheduler::<2>::new(SchedulerConfig::default(), &[3]);
+        let a = scheduler.add_sensor(get_config(1), 0).unwrap();
+        let b = scheduler.add_sensor(get_config(2), 0).unwrap();
+        accept_all(&mut scheduler, 3);
+        assert_eq!(
+            scheduler.receive_message(&rx(RxMessage::BroadcastData(BroadcastData::new(3, [0; 8])))),
+            Some(a)
+        );
+        assert_eq!(scheduler.sensor_state(a), Some(SensorState::Tracking(3)));
+        scheduler.take_event();
+        assert_eq!(scheduler.take_event(), Some(SchedulerEvent::Acquired(a, 3)));
+
+        // Tracked sensors keep the channel
+        assert!(accept_all(&mut scheduler, 3).is_empty());
+        assert_eq!(scheduler.sensor_state(b), Some(SensorState::Waiting));
+
+        scheduler.receive_message(&event(3, MessageCode::EventRxFailGoToSearch));
+        assert_eq!(scheduler.take_event(), Some(SchedulerEvent::Lost(a)));
+        assert_eq!(scheduler.sensor_state(a), Some(SensorState::Searching(3)));
+        time_out(&mut scheduler, 3);
+        accept_all(&mut scheduler, 3);
+        assert_eq!(scheduler.sensor_for_channel(3), Some(b));
+    }
+
+    #[test]
+    fn priority() {
+        let config = SchedulerConfig {
+            fairness: Fairness::Priority,
+            search_priority: Some(1),
+            ..SchedulerConfig::default()
+        };
+        let mut scheduler = SearchScheduler::<3>::new(config, &[0]);
+        scheduler.add_sensor(get_config(1), 0).unwrap();
+        let high = scheduler.add_sensor(get_config(2), 5).unwrap();
+        let sent = accept_all(&mut scheduler, 0);
+        assert!(sent.contains(&TxMessageId::ChannelSearchPriority));
+        assert_eq!(scheduler.sensor_for_channel(0), Some(high));
+        time_out(&mut scheduler, 0);
+        accept_all(&mut scheduler, 0);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(high));
+    }
+
+    #[test]
+    fn config_failure_and_removal() {
+        let mut scheduler = SearchScheduler::<2>::new(SchedulerConfig::default(), &[0]);
+        let a = scheduler.add_sensor(get_config(1), 0).unwrap();
+        let b = scheduler.add_sensor(get_config(2), 0).unwrap();
+        let msg = scheduler.send_message::<64>().unwrap();
+        assert_eq!(msg.get_tx_msg_id(), TxMessageId::AssignChannel);
+        scheduler.receive_message(&response(
+            0,
+            TxMessageId::AssignChannel,
+            MessageCode::ChannelInWrongState,
+        ));
+        assert_eq!(
+            scheduler.take_event(),
+            Some(SchedulerEvent::ConfigFailed(
+                a,
+                CommandError::ChannelInWrongState
+            ))
+        );
+        accept_all(&mut scheduler, 0);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(b));
+
+        scheduler.remove_sensor(b);
+        assert_eq!(scheduler.sensor_state(b), None);
+        assert_eq!(scheduler.sensor_for_channel(0), None);
+        assert_eq!(
+            scheduler
+                .send_message::<64>()
+                .map(|msg| msg.get_tx_msg_id()),
+            Some(TxMessageId::CloseChannel)
+        );
+        scheduler.receive_message(&response(
+            0,
+            TxMessageId::CloseChannel,
+            MessageCode::ResponseNoError,
+        ));
+        assert!(scheduler.send_message::<64>().is_none());
+        scheduler.receive_message(&event(0, MessageCode::EventChannelClosed));
+        accept_all(&mut scheduler, 0);
+        assert_eq!(scheduler.sensor_for_channel(0), Some(a));
+    }
+}
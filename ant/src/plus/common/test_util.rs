@@ -0,0 +1,60 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use crate::messages::channel::{ChannelEvent, ChannelResponse, MessageCode};
+use crate::messages::{
+    AntMessage, RxMessage, RxMessageHeader, RxMessageId, RxSyncByte, TxMessageId,
+};
+use arrayvec::ArrayVec;
+
+/// State machine driven by the tests through its messages
+pub(crate) trait Responder {
+    /// Id of the next message it sends, None while it waits
+    fn next_message(&mut self) -> Option<TxMessageId>;
+    fn receive(&mut self, msg: &AntMessage);
+}
+
+pub(crate) fn rx(message: RxMessage) -> AntMessage {
+    AntMessage {
+        header: RxMessageHeader {
+            sync: RxSyncByte::Write,
+            msg_id: RxMessageId::ChannelEvent,
+            msg_length: 3,
+        },
+        message,
+        checksum: 0, // this doesn't matter
+    }
+}
+
+pub(crate) fn event(channel: u8, code: MessageCode) -> AntMessage {
+    rx(RxMessage::ChannelEvent(
+        ChannelEvent::unpack_from_slice(&[channel, 0x01, code as u8]).unwrap(),
+    ))
+}
+
+pub(crate) fn response(
+    channel: u8,
+    message_id: TxMessageId,
+    message_code: MessageCode,
+) -> AntMessage {
+    rx(RxMessage::ChannelResponse(ChannelResponse {
+        channel_number: channel,
+        message_id,
+        message_code,
+    }))
+}
+
+// Accept everything sent on `channel` until it goes quiet, returns the ids sent
+pub(crate) fn accept_all(machine: &mut impl Responder, channel: u8) -> ArrayVec<TxMessageId, 32> {
+    let mut sent = ArrayVec::new();
+    while let Some(id) = machine.next_message() {
+        machine.receive(&response(channel, id, MessageCode::ResponseNoError));
+        sent.push(id);
+    }
+    sent
+}
@@ -6,19 +6,25 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::callback::{callback_type, LifecycleCallback, RxMessageCallback, TxMessageCallback};
 use crate::channel::duration_to_search_timeout;
-use crate::channel::{ChanError, RxHandler, TxHandler};
+use crate::channel::{RxHandler, TxError, TxHandler};
+use crate::id_list::ChannelIdList;
 use crate::messages::config::{
     ChannelType, TransmissionChannelType, TransmissionGlobalDataPages, TransmissionType,
 };
-use crate::messages::{AntMessage, RxMessage, TxMessage, TxMessageChannelConfig, TxMessageData};
+use crate::messages::requested_response::{ChannelState, RadioFeature};
+use crate::messages::{AntMessage, RxMessage, TxMessage, TxMessageData, DEFAULT_PAYLOAD_CAPACITY};
 use crate::plus::common::datapages::MANUFACTURER_SPECIFIC_RANGE;
-use crate::plus::common::msg_handler::{ChannelConfig, MessageHandler};
+use crate::plus::common::msg_handler::{
+    AckHandle, AckStatus, ChannelConfig, ConfigureError, MessageHandler,
+};
+use crate::plus::common::pairing::PairedDevice;
 use crate::plus::profiles::heart_rate::{
     BatteryStatus, Capabilities, CumulativeOperatingTime, DataPageNumbers, DefaultDataPage,
     DeviceInformation, Error, ManufacturerInformation, ManufacturerSpecific, MonitorTxDataPage,
-    Period, PreviousHeartBeat, ProductInformation, SwimIntervalSummary, DATA_PAGE_NUMBER_MASK,
-    DEVICE_TYPE,
+    Period, PreviousHeartBeat, ProcessError, ProductInformation, SwimIntervalSummary,
+    DATA_PAGE_NUMBER_MASK, DEVICE_TYPE,
 };
 use crate::plus::NETWORK_RF_FREQUENCY;
 
@@ -27,12 +33,27 @@ use packed_struct::{PackedStruct, PrimitiveEnum};
 
 use std::time::Duration;
 
-pub struct Display<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> {
-    msg_handler: MessageHandler,
-    rx_message_callback: Option<fn(&AntMessage)>,
-    rx_datapage_callback: Option<fn(Result<MonitorTxDataPage, Error>)>,
-    tx_message_callback: Option<fn() -> Option<TxMessageChannelConfig>>,
-    tx_datapage_callback: Option<fn() -> Option<TxMessageData>>,
+callback_type!(
+    /// Receives parsed datapages and datapage errors from the monitor
+    pub DisplayRxDataPageCallback<'a> = FnMut(Result<MonitorTxDataPage, Error>)
+);
+callback_type!(
+    /// Polled when the channel is ready to transmit for data to send to the monitor
+    pub DisplayTxDataPageCallback<'a, const N: usize> = FnMut() -> Option<TxMessageData<N>>
+);
+
+pub struct Display<
+    'a,
+    T: TxHandler<TxMessage<N>>,
+    R: RxHandler<AntMessage<N>>,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+> {
+    msg_handler: MessageHandler<N>,
+    rx_message_callback: Option<RxMessageCallback<'a, N>>,
+    rx_datapage_callback: Option<DisplayRxDataPageCallback<'a>>,
+    tx_message_callback: Option<TxMessageCallback<'a>>,
+    lifecycle_callback: Option<LifecycleCallback<'a>>,
+    tx_datapage_callback: Option<DisplayTxDataPageCallback<'a, N>>,
     tx: T,
     rx: R,
 }
@@ -45,7 +66,9 @@ pub struct DisplayConfig {
     pub period: Period,
 }
 
-impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
+impl<'a, T: TxHandler<TxMessage<N>>, R: RxHandler<AntMessage<N>>, const N: usize>
+    Display<'a, T, R, N>
+{
     pub fn new(
         conf: DisplayConfig,
         // TODO make this a type
@@ -76,6 +99,7 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
             rx_message_callback: None,
             rx_datapage_callback: None,
             tx_message_callback: None,
+            lifecycle_callback: None,
             tx_datapage_callback: None,
             msg_handler: MessageHandler::new(&channel_config),
             tx,
@@ -91,23 +115,72 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
         self.msg_handler.close();
     }
 
+    /// Send `data` as acknowledged data, see [MessageHandler::send_acknowledged]
+    pub fn send_acknowledged(&mut self, data: [u8; 8]) -> Option<AckHandle> {
+        self.msg_handler.send_acknowledged(data)
+    }
+
+    /// Status of a send from [Display::send_acknowledged]
+    pub fn ack_status(&self, handle: AckHandle) -> Option<AckStatus> {
+        self.msg_handler.ack_status(handle)
+    }
+
+    /// Set how many times failed acknowledged sends are retried
+    pub fn set_ack_retries(&mut self, retries: u8) {
+        self.msg_handler.set_ack_retries(retries);
+    }
+
     pub fn get_device_id(&self) -> u16 {
         self.msg_handler.get_device_id()
     }
 
-    pub fn set_rx_message_callback(&mut self, f: Option<fn(&AntMessage)>) {
+    /// See [MessageHandler::get_paired_device]
+    pub fn get_paired_device(&self) -> Option<PairedDevice> {
+        self.msg_handler.get_paired_device()
+    }
+
+    /// See [MessageHandler::set_paired_device]
+    pub fn set_paired_device(&mut self, device: &PairedDevice) -> Result<(), ConfigureError> {
+        self.msg_handler.set_paired_device(device)
+    }
+
+    /// See [MessageHandler::set_wildcard_fallback]
+    pub fn set_wildcard_fallback(&mut self, search_timeout: Option<u8>) {
+        self.msg_handler.set_wildcard_fallback(search_timeout);
+    }
+
+    /// See [MessageHandler::set_id_list]
+    pub fn set_id_list(&mut self, id_list: Option<ChannelIdList>) -> Result<(), ConfigureError> {
+        self.msg_handler.set_id_list(id_list)
+    }
+
+    pub fn get_id_list(&self) -> Option<&ChannelIdList> {
+        self.msg_handler.get_id_list()
+    }
+
+    pub fn set_rx_message_callback(&mut self, f: Option<RxMessageCallback<'a, N>>) {
         self.rx_message_callback = f;
     }
 
-    pub fn set_rx_datapage_callback(&mut self, f: Option<fn(Result<MonitorTxDataPage, Error>)>) {
+    pub fn set_rx_datapage_callback(&mut self, f: Option<DisplayRxDataPageCallback<'a>>) {
         self.rx_datapage_callback = f;
     }
 
-    pub fn set_tx_message_callback(&mut self, f: Option<fn() -> Option<TxMessageChannelConfig>>) {
+    pub fn set_tx_message_callback(&mut self, f: Option<TxMessageCallback<'a>>) {
         self.tx_message_callback = f;
     }
 
-    pub fn set_tx_datapage_callback(&mut self, f: Option<fn() -> Option<TxMessageData>>) {
+    /// Set callback for changes in the link to the monitor, e.g. signal loss
+    pub fn set_lifecycle_callback(&mut self, f: Option<LifecycleCallback<'a>>) {
+        self.lifecycle_callback = f;
+    }
+
+    /// Last known state of the channel
+    pub fn channel_state(&self) -> ChannelState {
+        self.msg_handler.channel_state()
+    }
+
+    pub fn set_tx_datapage_callback(&mut self, f: Option<DisplayTxDataPageCallback<'a, N>>) {
         self.tx_datapage_callback = f;
     }
 
@@ -118,7 +191,7 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
     // get result and call callback
     fn handle_dp(&mut self, data: &[u8; 8]) {
         let dp = self.parse_dp(data);
-        if let Some(f) = self.rx_datapage_callback {
+        if let Some(f) = self.rx_datapage_callback.as_mut() {
             f(dp);
         }
     }
@@ -173,10 +246,14 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
         Err(Error::UnsupportedDataPage(dp_num))
     }
 
-    pub fn process(&mut self) -> Result<(), ChanError> {
+    /// Handle messages from the router and send whatever is due
+    ///
+    /// Messages from the user callbacks that the radio does not support are dropped and reported
+    /// with [ProcessError::Unsupported] once the rest of the cycle has run.
+    pub fn process(&mut self) -> Result<(), ProcessError> {
         // TODO handle closed channel
         while let Ok(msg) = self.rx.try_recv() {
-            if let Some(f) = self.rx_message_callback {
+            if let Some(f) = self.rx_message_callback.as_mut() {
                 f(&msg);
             }
             match msg.message {
@@ -187,32 +264,106 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Display<T, R> {
             match self.msg_handler.receive_message(&msg) {
                 Ok(_) => (),
                 Err(e) => {
-                    if let Some(f) = self.rx_datapage_callback {
+                    if let Some(f) = self.rx_datapage_callback.as_mut() {
                         f(Err(e.into()));
                     }
                 }
             }
         }
+        while let Some(event) = self.msg_handler.take_lifecycle_event() {
+            if let Some(f) = self.lifecycle_callback.as_mut() {
+                f(event);
+            }
+        }
 
         // TODO handle errors
         if let Some(msg) = self.msg_handler.send_message() {
             self.tx.try_send(msg)?;
         }
-        if let Some(callback) = self.tx_message_callback {
+        let mut unsupported = None;
+        if let Some(callback) = self.tx_message_callback.as_mut() {
             if let Some(mut msg) = callback() {
                 msg.set_channel(self.msg_handler.get_channel());
-                self.tx.try_send(msg.into())?;
+                unsupported = unsupported.or(self.try_send_checked(msg.into())?);
             }
         }
         if self.msg_handler.is_tx_ready() {
-            if let Some(callback) = self.tx_datapage_callback {
+            if let Some(callback) = self.tx_datapage_callback.as_mut() {
                 if let Some(mut msg) = callback() {
                     msg.set_channel(self.msg_handler.get_channel());
                     self.msg_handler.tx_sent();
-                    self.tx.try_send(msg.into())?;
+                    unsupported = unsupported.or(self.try_send_checked(msg.into())?);
                 }
             }
         }
-        Ok(())
+        match unsupported {
+            Some(feature) => Err(feature.into()),
+            None => Ok(()),
+        }
+    }
+
+    // Drop user messages the radio cannot handle, returning the feature they needed
+    fn try_send_checked(&mut self, msg: TxMessage<N>) -> Result<Option<RadioFeature>, TxError> {
+        if let Err(feature) = self.msg_handler.check_message(&msg) {
+            return Ok(Some(feature));
+        }
+        self.tx.try_send(msg)?;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::Callback;
+    use crate::channel::mpsc::{RxChannel, TxChannel};
+    use crate::messages::data::{BroadcastData, BroadcastDataPayload};
+    use crate::messages::{RxMessageHeader, RxMessageId, RxSyncByte};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn capturing_callbacks() {
+        let (tx, _router_rx) = channel();
+        let (router_tx, rx) = channel();
+        let mut pages = 0;
+        let mut messages = 0;
+        {
+            let mut display: Display<_, _> = Display::new(
+                DisplayConfig {
+                    channel: 0,
+                    device_number: 0,
+                    device_number_extension: 0.into(),
+                    ant_plus_key_index: 0,
+                    period: Period::FourHz,
+                },
+                TxChannel { sender: tx },
+                RxChannel { receiver: rx },
+            );
+            display.set_rx_datapage_callback(Some(Callback::boxed(Box::new(|dp| {
+                assert!(matches!(dp, Ok(MonitorTxDataPage::DefaultDataPage(_))));
+                pages += 1;
+            }))));
+            display.set_rx_message_callback(Some(Callback::boxed(Box::new(|_| messages += 1))));
+            let msg = AntMessage {
+                header: RxMessageHeader {
+                    sync: RxSyncByte::Write,
+                    msg_id: RxMessageId::BroadcastData,
+                    msg_length: 9,
+                },
+                message: RxMessage::BroadcastData(BroadcastData {
+                    payload: BroadcastDataPayload {
+                        channel_number: 0,
+                        data: [0, 0xFF, 0xFF, 0xFF, 0, 0, 1, 60],
+                    },
+                    extended_info: None,
+                }),
+                checksum: 0,
+            };
+            router_tx.send(msg.clone()).unwrap();
+            router_tx.send(msg).unwrap();
+            display.process().unwrap();
+        }
+        assert_eq!(pages, 2);
+        assert_eq!(messages, 2);
     }
 }
@@ -16,6 +16,8 @@ pub use datapages::*;
 pub use display::*;
 pub use monitor::*;
 
+use crate::channel::{ChanError, TxError};
+use crate::messages::requested_response::RadioFeature;
 use crate::plus::common::datapages::{ModeSettings, RequestDataPage};
 use crate::plus::common::msg_handler::StateError;
 
@@ -81,3 +83,29 @@ impl From<StateError> for Error {
         Self::ConfigurationError(err)
     }
 }
+
+/// Failure to process a profile's messages
+#[derive(Debug, Clone)]
+pub enum ProcessError {
+    ChannelError(ChanError),
+    /// Message from a user callback was not sent as the radio does not support it
+    Unsupported(RadioFeature),
+}
+
+impl From<ChanError> for ProcessError {
+    fn from(err: ChanError) -> Self {
+        Self::ChannelError(err)
+    }
+}
+
+impl From<TxError> for ProcessError {
+    fn from(err: TxError) -> Self {
+        Self::ChannelError(err.into())
+    }
+}
+
+impl From<RadioFeature> for ProcessError {
+    fn from(feature: RadioFeature) -> Self {
+        Self::Unsupported(feature)
+    }
+}
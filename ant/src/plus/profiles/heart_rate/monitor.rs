@@ -6,20 +6,27 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::channel::{duration_to_search_timeout, ChanError, RxHandler, TxHandler};
+use crate::burst::{
+    AdvancedBurstReceiver, AdvancedBurstSender, BurstError, BurstReceiver, BurstSender,
+    DEFAULT_ADVANCED_BURST_BUFFER_SIZE,
+};
+use crate::callback::{callback_type, LifecycleCallback, RxMessageCallback, TxMessageCallback};
+use crate::channel::{duration_to_search_timeout, RxHandler, TxHandler};
 use crate::messages::config::{
-    ChannelType, TransmissionChannelType, TransmissionGlobalDataPages, TransmissionType,
+    AdvancedBurstMaxPacketLength, ChannelType, TransmissionChannelType,
+    TransmissionGlobalDataPages, TransmissionType,
 };
 use crate::messages::data::BroadcastData;
-use crate::messages::{AntMessage, RxMessage, TxMessage, TxMessageChannelConfig};
+use crate::messages::requested_response::ChannelState;
+use crate::messages::{AntMessage, RxMessage, TxMessage, DEFAULT_PAYLOAD_CAPACITY};
 use crate::plus::common::datapages::{
     DataPageNumbers as CommonDataPageNumbers, ModeSettings, RequestDataPage,
     MANUFACTURER_SPECIFIC_RANGE,
 };
-use crate::plus::common::msg_handler::{ChannelConfig, MessageHandler};
+use crate::plus::common::msg_handler::{AckHandle, AckStatus, ChannelConfig, MessageHandler};
 use crate::plus::profiles::heart_rate::{
     DataPageNumbers, DisplayTxDataPage, Error, HRFeatureCommand, ManufacturerSpecific, Period,
-    DATA_PAGE_NUMBER_MASK, DEVICE_TYPE,
+    ProcessError, DATA_PAGE_NUMBER_MASK, DEVICE_TYPE,
 };
 use crate::plus::NETWORK_RF_FREQUENCY;
 
@@ -59,8 +66,22 @@ pub struct MonitorConfig {
     pub channel: u8,
 }
 
-type RxDataPageCallback = fn(Result<DisplayTxDataPage, Error>);
-type TxDatapageCallback = fn(&TxDatapage) -> [u8; 8];
+callback_type!(
+    /// Receives parsed datapages and datapage errors from the display
+    pub MonitorRxDataPageCallback<'a> = FnMut(Result<DisplayTxDataPage, Error>)
+);
+callback_type!(
+    /// Fills in the payload for the datapage due next in the transmission pattern
+    pub MonitorTxDataPageCallback<'a> = FnMut(&TxDatapage) -> [u8; 8]
+);
+callback_type!(
+    /// Receives bursts from the display
+    pub RxBurstCallback<'a> = FnMut(Result<&[u8], BurstError>)
+);
+callback_type!(
+    /// Receives the result of bursts sent to the display
+    pub TxBurstCallback<'a> = FnMut(Result<(), BurstError>)
+);
 
 /// Collection of datapge transmission state variables
 struct PageState {
@@ -85,16 +106,28 @@ const WEAVE_PATTERN: [TxDatapage; 8] = [
 /// When using this profile, mode changes initiaded by display must be triggered by your code. E.g.
 /// if display sends [ModeSettings] your code must call [Monitor::set_swim_mode]. This is so your code
 /// can update the config once it is ready to handle the new state.
-pub struct Monitor<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> {
-    msg_handler: MessageHandler,
-    rx_message_callback: Option<fn(&AntMessage)>,
-    rx_datapage_callback: RxDataPageCallback,
-    tx_message_callback: Option<fn() -> Option<TxMessageChannelConfig>>,
-    tx_datapage_callback: TxDatapageCallback,
+pub struct Monitor<
+    'a,
+    T: TxHandler<TxMessage<N>>,
+    R: RxHandler<AntMessage<N>>,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+> {
+    msg_handler: MessageHandler<N>,
+    rx_message_callback: Option<RxMessageCallback<'a, N>>,
+    rx_datapage_callback: MonitorRxDataPageCallback<'a>,
+    tx_message_callback: Option<TxMessageCallback<'a>>,
+    lifecycle_callback: Option<LifecycleCallback<'a>>,
+    tx_datapage_callback: MonitorTxDataPageCallback<'a>,
     in_gym_mode: bool,
     in_swim_mode: bool,
     config: MonitorConfig,
     page_state: PageState,
+    burst_sender: BurstSender<N>,
+    burst_receiver: BurstReceiver<N>,
+    advanced_burst_sender: AdvancedBurstSender<DEFAULT_ADVANCED_BURST_BUFFER_SIZE, N>,
+    advanced_burst_receiver: AdvancedBurstReceiver,
+    rx_burst_callback: Option<RxBurstCallback<'a>>,
+    tx_burst_callback: Option<TxBurstCallback<'a>>,
     sender: T,
     receiver: R,
 }
@@ -112,21 +145,33 @@ pub enum TxDatapage {
     ManufacturerSpecific(u8),
 }
 
-impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Monitor<T, R> {
+impl<'a, T: TxHandler<TxMessage<N>>, R: RxHandler<AntMessage<N>>, const N: usize>
+    Monitor<'a, T, R, N>
+{
     pub fn new(
         config: MonitorConfig,
         sender: T,
         receiver: R,
-        rx_datapage_callback: RxDataPageCallback,
-        tx_datapage_callback: TxDatapageCallback,
+        rx_datapage_callback: MonitorRxDataPageCallback<'a>,
+        tx_datapage_callback: MonitorTxDataPageCallback<'a>,
     ) -> Self {
         Self {
             rx_message_callback: None,
             rx_datapage_callback,
             tx_message_callback: None,
+            lifecycle_callback: None,
             tx_datapage_callback,
             sender,
             receiver,
+            burst_sender: BurstSender::new(config.channel),
+            burst_receiver: BurstReceiver::new(),
+            advanced_burst_sender: AdvancedBurstSender::new(
+                config.channel,
+                AdvancedBurstMaxPacketLength::Max8Byte,
+            ),
+            advanced_burst_receiver: AdvancedBurstReceiver::new(),
+            rx_burst_callback: None,
+            tx_burst_callback: None,
             msg_handler: MessageHandler::new(&ChannelConfig {
                 channel: config.channel,
                 device_number: config.device_number,
@@ -162,27 +207,93 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Monitor<T, R> {
         self.msg_handler.close();
     }
 
+    /// Send `data` as acknowledged data, see [MessageHandler::send_acknowledged]
+    pub fn send_acknowledged(&mut self, data: [u8; 8]) -> Option<AckHandle> {
+        self.msg_handler.send_acknowledged(data)
+    }
+
+    /// Status of a send from [Monitor::send_acknowledged]
+    pub fn ack_status(&self, handle: AckHandle) -> Option<AckStatus> {
+        self.msg_handler.ack_status(handle)
+    }
+
+    /// Set how many times failed acknowledged sends are retried
+    pub fn set_ack_retries(&mut self, retries: u8) {
+        self.msg_handler.set_ack_retries(retries);
+    }
+
     /// Set callback for users to observe every message this channel observes
-    pub fn set_rx_message_callback(&mut self, f: Option<fn(&AntMessage)>) {
+    pub fn set_rx_message_callback(&mut self, f: Option<RxMessageCallback<'a, N>>) {
         self.rx_message_callback = f;
     }
 
     /// Set callback for users to observe every message this channel observes
-    pub fn set_rx_datapage_callback(&mut self, f: RxDataPageCallback) {
+    pub fn set_rx_datapage_callback(&mut self, f: MonitorRxDataPageCallback<'a>) {
         self.rx_datapage_callback = f;
     }
 
     /// Set callback for users to send channel specific config messages
     /// is called continously every TX cycle
-    pub fn set_tx_message_callback(&mut self, f: Option<fn() -> Option<TxMessageChannelConfig>>) {
+    pub fn set_tx_message_callback(&mut self, f: Option<TxMessageCallback<'a>>) {
         self.tx_message_callback = f;
     }
 
+    /// Set callback for changes in the link to the display, e.g. collisions
+    pub fn set_lifecycle_callback(&mut self, f: Option<LifecycleCallback<'a>>) {
+        self.lifecycle_callback = f;
+    }
+
+    /// Last known state of the channel
+    pub fn channel_state(&self) -> ChannelState {
+        self.msg_handler.channel_state()
+    }
+
     /// Set callback for users to observe every message this channel observes
-    pub fn set_tx_datapage_callback(&mut self, f: TxDatapageCallback) {
+    pub fn set_tx_datapage_callback(&mut self, f: MonitorTxDataPageCallback<'a>) {
         self.tx_datapage_callback = f;
     }
 
+    /// Set callback for bursts received from the display
+    pub fn set_rx_burst_callback(&mut self, f: Option<RxBurstCallback<'a>>) {
+        self.rx_burst_callback = f;
+    }
+
+    /// Set callback for the result of bursts sent with [Monitor::send_burst]
+    pub fn set_tx_burst_callback(&mut self, f: Option<TxBurstCallback<'a>>) {
+        self.tx_burst_callback = f;
+    }
+
+    /// Send up to `N` bytes to the display as a burst
+    ///
+    /// The burst starts on the next TX event and replaces datapages until it is finished.
+    pub fn send_burst(&mut self, data: &[u8]) -> Result<(), BurstError> {
+        if !self.advanced_burst_sender.is_idle() {
+            return Err(BurstError::Busy);
+        }
+        self.burst_sender.start(data)
+    }
+
+    /// Send up to [DEFAULT_ADVANCED_BURST_BUFFER_SIZE] bytes to the display as an advanced burst
+    ///
+    /// The last packet is zero padded to a multiple of 8 bytes, the display receives the padding
+    /// with the data. Advanced burst must have been configured on the radio first, see
+    /// [crate::router::Router::configure_advanced_burst]. The result is reported to the tx burst
+    /// callback like [Monitor::send_burst].
+    pub fn send_advanced_burst(&mut self, data: &[u8]) -> Result<(), BurstError> {
+        if !self.burst_sender.is_idle() {
+            return Err(BurstError::Busy);
+        }
+        self.advanced_burst_sender.start(data)
+    }
+
+    /// Packet length of the negotiated advanced burst configuration, defaults to 8 bytes
+    pub fn set_advanced_burst_packet_length(
+        &mut self,
+        packet_length: AdvancedBurstMaxPacketLength,
+    ) {
+        self.advanced_burst_sender.set_packet_length(packet_length);
+    }
+
     /// Used to put profile into gym mode
     /// See section 6.3 for how this modifies the transmission pattern.
     ///
@@ -210,8 +321,7 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Monitor<T, R> {
     // get result and call callback
     fn handle_dp(&mut self, data: &[u8; 8]) {
         let dp = self.parse_dp(data);
-        let f = self.rx_datapage_callback;
-        f(dp);
+        (self.rx_datapage_callback)(dp);
     }
 
     fn parse_dp(&mut self, data: &[u8; 8]) -> Result<DisplayTxDataPage, Error> {
@@ -329,42 +439,240 @@ impl<T: TxHandler<TxMessage>, R: RxHandler<AntMessage>> Monitor<T, R> {
         }
     }
 
-    pub fn process(&mut self) -> Result<(), ChanError> {
+    /// Handle messages from the router and send whatever is due
+    ///
+    /// Messages from the tx message callback that the radio does not support are dropped and
+    /// reported with [ProcessError::Unsupported] once the rest of the cycle has run.
+    pub fn process(&mut self) -> Result<(), ProcessError> {
         // TODO handle closed
         while let Ok(msg) = self.receiver.try_recv() {
-            if let Some(f) = self.rx_message_callback {
+            if let Some(f) = self.rx_message_callback.as_mut() {
                 f(&msg);
             }
             match msg.message {
                 RxMessage::BroadcastData(msg) => self.handle_dp(&msg.payload.data),
                 RxMessage::AcknowledgedData(msg) => self.handle_dp(&msg.payload.data),
+                RxMessage::BurstTransferData(msg) => {
+                    let result = self.burst_receiver.receive(&msg);
+                    if let Some(f) = self.rx_burst_callback.as_mut() {
+                        match result {
+                            Ok(Some(data)) => f(Ok(data)),
+                            Ok(None) => (),
+                            Err(e) => f(Err(e)),
+                        }
+                    }
+                }
+                RxMessage::AdvancedBurstData(ref msg) => {
+                    let result = self.advanced_burst_receiver.receive(msg);
+                    if let Some(f) = self.rx_burst_callback.as_mut() {
+                        match result {
+                            Ok(Some(data)) => f(Ok(data)),
+                            Ok(None) => (),
+                            Err(e) => f(Err(e)),
+                        }
+                    }
+                }
+                RxMessage::ChannelEvent(event) => {
+                    let code = event.payload.message_code;
+                    let rx_results = [
+                        self.burst_receiver.handle_event(code),
+                        self.advanced_burst_receiver.handle_event(code),
+                    ];
+                    if let Some(f) = self.rx_burst_callback.as_mut() {
+                        rx_results
+                            .into_iter()
+                            .filter_map(Result::err)
+                            .for_each(|e| f(Err(e)));
+                    }
+                    let tx_results = [
+                        self.burst_sender.handle_event(code),
+                        self.advanced_burst_sender.handle_event(code),
+                    ];
+                    if let Some(f) = self.tx_burst_callback.as_mut() {
+                        tx_results.into_iter().flatten().for_each(&mut **f);
+                    }
+                }
                 _ => (),
             }
             match self.msg_handler.receive_message(&msg) {
                 Ok(_) => (),
-                Err(e) => {
-                    let f = self.rx_datapage_callback;
-                    f(Err(e.into()));
-                }
+                Err(e) => (self.rx_datapage_callback)(Err(e.into())),
+            }
+        }
+        while let Some(event) = self.msg_handler.take_lifecycle_event() {
+            if let Some(f) = self.lifecycle_callback.as_mut() {
+                f(event);
             }
         }
 
         if let Some(msg) = self.msg_handler.send_message() {
             self.sender.try_send(msg)?;
         }
-        if let Some(callback) = self.tx_message_callback {
+        let mut unsupported = None;
+        if let Some(callback) = self.tx_message_callback.as_mut() {
             if let Some(mut msg) = callback() {
                 msg.set_channel(self.msg_handler.get_channel());
-                self.sender.try_send(msg.into())?;
+                let msg = msg.into();
+                // Drop messages the radio cannot handle and report them once the cycle is done
+                match self.msg_handler.check_message(&msg) {
+                    Ok(()) => self.sender.try_send(msg)?,
+                    Err(feature) => unsupported = Some(feature),
+                }
             }
         }
-        if self.msg_handler.is_tx_ready() {
-            let callback = self.tx_datapage_callback;
+        if !self.burst_sender.is_idle() {
+            if let Some(msg) = self
+                .burst_sender
+                .next_packet(self.msg_handler.is_tx_ready())
+            {
+                self.msg_handler.tx_sent();
+                self.sender.try_send(msg.into())?;
+            }
+        } else if !self.advanced_burst_sender.is_idle() {
+            if let Some(msg) = self
+                .advanced_burst_sender
+                .next_packet(self.msg_handler.is_tx_ready())
+            {
+                self.msg_handler.tx_sent();
+                self.sender.try_send(msg.into())?;
+            }
+        } else if self.msg_handler.is_tx_ready() {
             let dp = self.get_next_datapage();
-            let msg = BroadcastData::new(self.msg_handler.get_channel(), callback(&dp)); // TODO handle ack param
+            let msg = BroadcastData::new(
+                self.msg_handler.get_channel(),
+                (self.tx_datapage_callback)(&dp),
+            );
             self.msg_handler.tx_sent();
             self.sender.try_send(msg.into())?;
         }
-        Ok(())
+        match unsupported {
+            Some(feature) => Err(feature.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::callback::Callback;
+    use crate::channel::mpsc::{RxChannel, TxChannel};
+    use crate::messages::channel::MessageCode;
+    use crate::messages::config::ConfigureEventFilter;
+    use crate::messages::{RxMessageHeader, RxMessageId, RxSyncByte, TransmitableMessage};
+    use crate::plus::common::test_util::{self, event, response};
+    use arrayvec::ArrayVec;
+    use std::sync::mpsc::channel;
+
+    fn config() -> MonitorConfig {
+        MonitorConfig {
+            device_number: 1,
+            transmission_type_extension: 1.into(),
+            main_data_page: MainDataPage::DefaultDataPage,
+            cumulative_operating_time_supported: false,
+            battery_status_supported: false,
+            swim_mode_supported: false,
+            gym_mode_supported: false,
+            number_manufacturer_pages: 0,
+            background_page_interval: 65,
+            ant_plus_key_index: 0,
+            channel: 0,
+        }
+    }
+
+    #[test]
+    fn event_tx_filtered() {
+        let (tx, router_rx) = channel();
+        let (router_tx, rx) = channel();
+        let mut monitor: Monitor<_, _> = Monitor::new(
+            config(),
+            TxChannel { sender: tx },
+            RxChannel { receiver: rx },
+            Callback::boxed(Box::new(|_| ())),
+            Callback::boxed(Box::new(|_| [0; 8])),
+        );
+        monitor.open();
+
+        // Accept the configuration, the open channel sends its first page right away
+        let mut pages = 0;
+        for _ in 0..32 {
+            monitor.process().unwrap();
+            for msg in router_rx.try_iter() {
+                match msg {
+                    TxMessage::BroadcastData(_) => pages += 1,
+                    msg => router_tx
+                        .send(response(
+                            0,
+                            msg.get_tx_msg_id(),
+                            MessageCode::ResponseNoError,
+                        ))
+                        .unwrap(),
+                }
+            }
+        }
+        assert_eq!(pages, 1);
+
+        let filter = ConfigureEventFilter::new(
+            false, false, true, false, false, false, false, false, false, false,
+        );
+        router_tx
+            .send(test_util::rx(RxMessage::EventFilter(filter)))
+            .unwrap();
+        for _ in 0..8 {
+            monitor.process().unwrap();
+        }
+        // Nothing is sent without TX_EVENTs to pace the channel
+        assert_eq!(router_rx.try_iter().count(), 0);
+
+        router_tx.send(event(0, MessageCode::EventTx)).unwrap();
+        monitor.process().unwrap();
+        assert!(matches!(
+            router_rx.try_iter().collect::<Vec<_>>().as_slice(),
+            [TxMessage::BroadcastData(_)]
+        ));
+    }
+
+    #[test]
+    fn advanced_burst() {
+        let (tx, _router_rx) = channel();
+        let (router_tx, rx) = channel();
+        let mut received = ArrayVec::<u8, 64>::new();
+        {
+            let mut monitor: Monitor<_, _> = Monitor::new(
+                config(),
+                TxChannel { sender: tx },
+                RxChannel { receiver: rx },
+                Callback::boxed(Box::new(|_| ())),
+                Callback::boxed(Box::new(|_| [0; 8])),
+            );
+            monitor.set_rx_burst_callback(Some(Callback::boxed(Box::new(|data| {
+                received.try_extend_from_slice(data.unwrap()).unwrap()
+            }))));
+
+            // Only one kind of burst at a time
+            monitor.send_burst(&[1; 8]).unwrap();
+            assert_eq!(monitor.send_advanced_burst(&[1; 8]), Err(BurstError::Busy));
+
+            // Display sends 20 bytes in 16 byte packets, the padding arrives with the data
+            let mut sender =
+                AdvancedBurstSender::<64>::new(0, AdvancedBurstMaxPacketLength::Max16Byte);
+            sender.start(&[7; 20]).unwrap();
+            while let Some(packet) = sender.next_packet(true) {
+                router_tx
+                    .send(AntMessage {
+                        header: RxMessageHeader {
+                            sync: RxSyncByte::Write,
+                            msg_id: RxMessageId::AdvancedBurstData,
+                            msg_length: packet.data.len() as u8 + 1,
+                        },
+                        message: RxMessage::AdvancedBurstData(packet),
+                        checksum: 0,
+                    })
+                    .unwrap();
+            }
+            monitor.process().unwrap();
+        }
+        assert_eq!(&received[..20], &[7; 20]);
+        assert_eq!(&received[20..], &[0; 4]);
     }
 }
@@ -0,0 +1,284 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Continuous wave test mode for RF certification
+//!
+//! In CW test mode the radio stops normal operation and emits an unmodulated carrier, which is what
+//! regulatory labs measure. Test mode can only be entered right after a reset and is only left by
+//! another reset, [CwTestController] sequences both around the tone itself.
+
+use crate::drivers::{Driver, DriverError};
+use crate::messages::channel::CommandError;
+use crate::messages::config::ConfigError;
+use crate::messages::control::ResetSystem;
+use crate::messages::test_mode::{CwInit, CwTest};
+use crate::messages::{RxMessage, TxMessageId, DEFAULT_PAYLOAD_CAPACITY};
+use embedded_hal::delay::DelayNs;
+use std::marker::PhantomData;
+
+/// Time the radio needs after a reset before it accepts commands
+pub const RESET_DELAY_MS: u32 = 500;
+/// Polls for the test mode init response before giving up
+pub const CW_INIT_RETRIES: u32 = 10;
+/// Time between polls for the test mode init response
+const CW_INIT_POLL_MS: u32 = 10;
+
+#[derive(Debug, PartialEq)]
+pub enum CwTestError<E> {
+    DriverError(DriverError<E>),
+    /// Requested power or frequency is out of range
+    ConfigError(ConfigError),
+    /// Radio refused to enter test mode
+    InitRejected(CommandError),
+    /// Radio did not answer the test mode init within [CW_INIT_RETRIES] polls
+    InitNotConfirmed,
+}
+
+impl<E> From<DriverError<E>> for CwTestError<E> {
+    fn from(err: DriverError<E>) -> Self {
+        Self::DriverError(err)
+    }
+}
+
+impl<E> From<ConfigError> for CwTestError<E> {
+    fn from(err: ConfigError) -> Self {
+        Self::ConfigError(err)
+    }
+}
+
+/// A single tone in a sweep
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CwStep {
+    pub transmit_power: u8,
+    /// Offset from 2400MHz in MHz
+    pub rf_frequency: u8,
+    /// How long the tone is held before moving on
+    pub dwell_ms: u32,
+}
+
+impl CwStep {
+    pub fn new(transmit_power: u8, rf_frequency: u8, dwell_ms: u32) -> Self {
+        Self {
+            transmit_power,
+            rf_frequency,
+            dwell_ms,
+        }
+    }
+}
+
+/// Owns the radio for the duration of a CW test
+///
+/// The driver is handed back by [CwTestController::exit] once the radio is back in normal mode.
+pub struct CwTestController<E, D: Driver<E, N>, const N: usize = DEFAULT_PAYLOAD_CAPACITY> {
+    driver: D,
+    _error: PhantomData<E>,
+}
+
+impl<E, D: Driver<E, N>, const N: usize> CwTestController<E, D, N> {
+    /// Reset the radio and place it in CW test mode
+    ///
+    /// Fails if the radio does not confirm it entered test mode.
+    pub fn enter(mut driver: D, delay: &mut impl DelayNs) -> Result<Self, CwTestError<E>> {
+        reset(&mut driver, delay)?;
+        driver.send_message(&CwInit::new())?;
+        wait_for_init(&mut driver, delay)?;
+        Ok(Self {
+            driver,
+            _error: PhantomData,
+        })
+    }
+
+    /// Emit a tone, replacing any tone currently running
+    pub fn start(&mut self, transmit_power: u8, rf_frequency: u8) -> Result<(), CwTestError<E>> {
+        let msg = CwTest::try_new(transmit_power, rf_frequency)?;
+        self.driver.send_message(&msg)?;
+        Ok(())
+    }
+
+    /// Run each step in order, holding every tone for its dwell time
+    ///
+    /// All steps are validated before the first tone is emitted so a bad entry cannot abort the
+    /// sweep half way through.
+    pub fn sweep(
+        &mut self,
+        steps: &[CwStep],
+        delay: &mut impl DelayNs,
+    ) -> Result<(), CwTestError<E>> {
+        for step in steps {
+            CwTest::try_new(step.transmit_power, step.rf_frequency)?;
+        }
+        for step in steps {
+            self.start(step.transmit_power, step.rf_frequency)?;
+            delay.delay_ms(step.dwell_ms);
+        }
+        Ok(())
+    }
+
+    /// Reset the radio back to normal operation and release the driver
+    pub fn exit(mut self, delay: &mut impl DelayNs) -> Result<D, CwTestError<E>> {
+        reset(&mut self.driver, delay)?;
+        Ok(self.driver)
+    }
+}
+
+fn reset<E, D: Driver<E, N>, const N: usize>(
+    driver: &mut D,
+    delay: &mut impl DelayNs,
+) -> Result<(), DriverError<E>> {
+    driver.send_message(&ResetSystem::new())?;
+    delay.delay_ms(RESET_DELAY_MS);
+    // Drop the startup message and anything left over from before the reset, including frames
+    // that fail to parse
+    while driver.get_message().unwrap_or(None).is_some() {}
+    Ok(())
+}
+
+fn wait_for_init<E, D: Driver<E, N>, const N: usize>(
+    driver: &mut D,
+    delay: &mut impl DelayNs,
+) -> Result<(), CwTestError<E>> {
+    for _ in 0..CW_INIT_RETRIES {
+        match driver.get_message() {
+            Ok(Some(msg)) => {
+                if let RxMessage::ChannelResponse(response) = msg.message {
+                    if let Some(result) = response.result_for(TxMessageId::CwInit) {
+                        return result.map_err(CwTestError::InitRejected);
+                    }
+                }
+            }
+            Ok(None) => delay.delay_ms(CW_INIT_POLL_MS),
+            // Garbage on the line, keep looking for the response
+            Err(_) => (),
+        }
+    }
+    Err(CwTestError::InitNotConfirmed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::channel::{ChannelResponse, MessageCode};
+    use crate::messages::AntMessage;
+    use arrayvec::ArrayVec;
+
+    #[derive(Default)]
+    struct RecordingDriver {
+        sent: ArrayVec<TxMessageId, 8>,
+        /// Answer to test mode init, none to stay silent
+        init_response: Option<MessageCode>,
+        pending: Option<AntMessage>,
+    }
+
+    impl RecordingDriver {
+        fn answering(code: MessageCode) -> Self {
+            Self {
+                init_response: Some(code),
+                ..Self::default()
+            }
+        }
+    }
+
+    impl Driver<()> for RecordingDriver {
+        fn get_message(&mut self) -> Result<Option<AntMessage>, DriverError<()>> {
+            Ok(self.pending.take())
+        }
+
+        fn send_message(
+            &mut self,
+            msg: &dyn crate::messages::TransmitableMessage,
+        ) -> Result<(), DriverError<()>> {
+            let id = msg.get_tx_msg_id();
+            self.sent.push(id);
+            if let (TxMessageId::CwInit, Some(code)) = (id, self.init_response) {
+                let response = RxMessage::ChannelResponse(ChannelResponse {
+                    channel_number: 0,
+                    message_id: id,
+                    message_code: code,
+                });
+                self.pending = AntMessage::new(response).ok();
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingDelay {
+        total_ms: u32,
+    }
+
+    impl DelayNs for RecordingDelay {
+        fn delay_ns(&mut self, ns: u32) {
+            self.total_ms += ns / 1_000_000;
+        }
+
+        fn delay_ms(&mut self, ms: u32) {
+            self.total_ms += ms;
+        }
+    }
+
+    #[test]
+    fn sweep() {
+        let mut delay = RecordingDelay::default();
+        let mut cw: CwTestController<_, _> = CwTestController::enter(
+            RecordingDriver::answering(MessageCode::ResponseNoError),
+            &mut delay,
+        )
+        .unwrap();
+        cw.sweep(
+            &[CwStep::new(3, 2, 100), CwStep::new(3, 80, 250)],
+            &mut delay,
+        )
+        .unwrap();
+        let driver = cw.exit(&mut delay).unwrap();
+        assert_eq!(
+            driver.sent.as_slice(),
+            &[
+                TxMessageId::ResetSystem,
+                TxMessageId::CwInit,
+                TxMessageId::CwTest,
+                TxMessageId::CwTest,
+                TxMessageId::ResetSystem,
+            ]
+        );
+        assert_eq!(delay.total_ms, 2 * RESET_DELAY_MS + 350);
+    }
+
+    #[test]
+    fn sweep_rejects_bad_step() {
+        let mut delay = RecordingDelay::default();
+        let mut cw: CwTestController<_, _> = CwTestController::enter(
+            RecordingDriver::answering(MessageCode::ResponseNoError),
+            &mut delay,
+        )
+        .unwrap();
+        assert!(matches!(
+            cw.sweep(
+                &[CwStep::new(3, 2, 100), CwStep::new(3, 200, 100)],
+                &mut delay
+            ),
+            Err(CwTestError::ConfigError(_))
+        ));
+        let driver = cw.exit(&mut delay).unwrap();
+        assert!(!driver.sent.contains(&TxMessageId::CwTest));
+    }
+
+    #[test]
+    fn init_must_be_confirmed() {
+        let mut delay = RecordingDelay::default();
+        let result: Result<CwTestController<_, _>, _> =
+            CwTestController::enter(RecordingDriver::default(), &mut delay);
+        assert!(matches!(result, Err(CwTestError::InitNotConfirmed)));
+
+        let driver = RecordingDriver::answering(MessageCode::ChannelInWrongState);
+        let result: Result<CwTestController<_, _>, _> = CwTestController::enter(driver, &mut delay);
+        assert!(matches!(
+            result,
+            Err(CwTestError::InitRejected(CommandError::ChannelInWrongState))
+        ));
+    }
+}
@@ -6,14 +6,34 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::channel::{ChanError, RxHandler, TxError, TxHandler};
+mod state;
+
+pub(crate) use state::{Requested, Route, RouterState};
+
+use crate::burst::{AdvancedBurstConfig, BurstError};
+use crate::callback::RxMessageCallback;
+use crate::channel::{ChanError, OverflowPolicy, RxHandler, TxError, TxHandler};
 use crate::drivers::{Driver, DriverError};
-use crate::messages::config::UnAssignChannel;
-use crate::messages::control::{CloseChannel, RequestMessage, RequestableMessageId, ResetSystem};
-use crate::messages::requested_response::Capabilities;
-use crate::messages::{AntMessage, RxMessage, TransmitableMessage, TxMessage};
+use crate::encryption::EncryptionKeyConfig;
+use crate::messages::channel::CommandError;
+use crate::messages::config::{
+    ChannelId, ConfigError, ConfigureAdvancedBurst, ConfigureEventFilter, LibConfig,
+    UnAssignChannel,
+};
+use crate::messages::control::{
+    CloseChannel, NvmeRequest, RequestMessage, RequestableMessageId, ResetSystem,
+};
+use crate::messages::requested_response::{
+    AdvancedBurstCapabilities, AntVersion, ChannelStatus, EventBufferConfiguration, EventFilter,
+    RadioCapabilities, RadioFeature, SelectiveDataUpdateMaskSetting, SerialNumber, UserNvm,
+};
+use crate::messages::{
+    AntMessage, RxMessage, TransmitableMessage, TxMessage, TxMessageId, DEFAULT_PAYLOAD_CAPACITY,
+};
+use crate::network::{NetworkEntry, NetworkKey, NetworkRole};
+use crate::sdu::{SduError, SduMasks};
+use crate::stats::LinkStats;
 
-use std::cell::Cell;
 use std::marker::PhantomData;
 
 #[derive(Debug)]
@@ -25,6 +45,44 @@ pub enum RouterError {
     ChannelNotAssociated(),
     FailedToGetCapabilities(),
     ChannelBufferError(ChanError),
+    /// Radio does not support a feature the message relies on
+    Unsupported(RadioFeature),
+    SduError(SduError),
+    BurstError(BurstError),
+    ConfigError(ConfigError),
+    /// Radio refused the network key
+    NetworkKeyRejected(CommandError),
+    /// Radio did not respond to the network key within [ROUTER_CAPABILITIES_RETRIES] polls
+    NetworkKeyNotConfirmed(),
+    /// Radio did not respond to the request within [ROUTER_CAPABILITIES_RETRIES] polls
+    RequestTimeout(RequestableMessageId),
+    /// Radio answered the request with an error, e.g. the message is not supported
+    RequestRejected(RequestableMessageId, CommandError),
+    /// Radio failed a radio wide command
+    CommandRejected(TxMessageId, CommandError),
+    /// Radio did not respond to a radio wide command within [ROUTER_CAPABILITIES_RETRIES] polls
+    CommandNotConfirmed(TxMessageId),
+    /// Master channel paces its transmissions on EVENT_TX, it can not be filtered while the
+    /// channel is assigned
+    EventTxRequired(u8),
+}
+
+impl From<ConfigError> for RouterError {
+    fn from(err: ConfigError) -> RouterError {
+        RouterError::ConfigError(err)
+    }
+}
+
+impl From<BurstError> for RouterError {
+    fn from(err: BurstError) -> RouterError {
+        RouterError::BurstError(err)
+    }
+}
+
+impl From<SduError> for RouterError {
+    fn from(err: SduError) -> RouterError {
+        RouterError::SduError(err)
+    }
 }
 
 impl From<TxError> for RouterError {
@@ -36,17 +94,44 @@ impl From<TxError> for RouterError {
 // This in theory is infinite, but its what the current hardware limit is.
 /// Highest known supported channel count on a ANT device
 pub const MAX_CHANNELS: usize = 15;
+/// Messages a channel holds back before discarding the oldest, see [OverflowPolicy::DropOldest]
+pub const OVERFLOW_BACKLOG: usize = 8;
 
-pub struct Router<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> {
+/// Routes messages between a driver and the channels assigned to it
+///
+/// `N` is the capacity of variable length message fields and must match the driver.
+pub struct Router<
+    'a,
+    E,
+    D: Driver<E, N>,
+    T: TxHandler<AntMessage<N>>,
+    R: RxHandler<TxMessage<N>>,
+    const N: usize = DEFAULT_PAYLOAD_CAPACITY,
+> {
     channels: [Option<T>; MAX_CHANNELS],
-    max_channels: Cell<usize>, // what the hardware reports as some have less than max
+    state: RouterState<'a, N>,
     driver: D,
-    reset_restore: Cell<bool>,
-    rx_message_callback: Option<fn(&AntMessage)>,
     receiver: R,
     _marker: PhantomData<E>,
 }
 
+/// Channel a message is for, None if it is not channel specific
+pub(crate) fn message_channel<const N: usize>(msg: &RxMessage<N>) -> Option<u8> {
+    match msg {
+        RxMessage::BroadcastData(data) => Some(data.payload.channel_number),
+        RxMessage::AcknowledgedData(data) => Some(data.payload.channel_number),
+        RxMessage::BurstTransferData(data) => {
+            Some(data.payload.channel_sequence.channel_number.into())
+        }
+        RxMessage::AdvancedBurstData(data) => Some(data.channel_sequence.channel_number.into()),
+        RxMessage::ChannelEvent(data) => Some(data.payload.channel_number),
+        RxMessage::ChannelResponse(data) => Some(data.channel_number),
+        RxMessage::ChannelStatus(data) => Some(data.channel_number),
+        RxMessage::ChannelId(data) => Some(data.channel_number),
+        _ => None,
+    }
+}
+
 impl<E> From<DriverError<E>> for RouterError {
     fn from(_err: DriverError<E>) -> Self {
         // TODO encapsilate error
@@ -54,9 +139,21 @@ impl<E> From<DriverError<E>> for RouterError {
     }
 }
 
-const ROUTER_CAPABILITIES_RETRIES: u8 = 25;
+/// Times the router polls the driver for a response before giving up
+///
+/// This is a retry count, not a duration. A poll returns as soon as the driver has nothing queued
+/// so how long this takes depends on the driver, retry the call if the radio is slow to answer.
+pub const ROUTER_CAPABILITIES_RETRIES: u8 = 25;
 
-impl<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> Router<E, D, T, R> {
+impl<
+        'a,
+        E,
+        D: Driver<E, N>,
+        T: TxHandler<AntMessage<N>>,
+        R: RxHandler<TxMessage<N>>,
+        const N: usize,
+    > Router<'a, E, D, T, R, N>
+{
     // TODO change to generic receiver
     pub fn new(mut driver: D, receiver: R) -> Result<Self, RouterError> {
         // Reset system so we are coherent
@@ -71,16 +168,14 @@ impl<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> Router<
         ))?;
         let mut router = Self {
             channels: std::array::from_fn(|_| None),
-            max_channels: Cell::new(0),
-            reset_restore: Cell::new(false),
+            state: RouterState::new(OverflowPolicy::default()),
             driver,
-            rx_message_callback: None,
             receiver,
             _marker: PhantomData,
         };
         // If we don't get a response within 25ms give up
         let mut i = 0;
-        while router.max_channels.get() == 0 && i < ROUTER_CAPABILITIES_RETRIES {
+        while router.state.capabilities().is_none() && i < ROUTER_CAPABILITIES_RETRIES {
             router.process()?;
             i += 1;
         }
@@ -90,25 +185,41 @@ impl<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> Router<
         Ok(router)
     }
 
+    /// Capabilities reported by the radio
+    pub fn capabilities(&self) -> Option<RadioCapabilities> {
+        self.state.capabilities()
+    }
+
     /// Add a channel at next available index
+    ///
+    /// The channel is sent the radio capabilities so it can tell what configuration is supported.
     pub fn add_channel(&mut self, channel: T) -> Result<u8, RouterError> {
-        let index = self.channels.iter().position(|x| x.is_none());
+        let index = self.channels[..self.state.max_channels()]
+            .iter()
+            .position(|x| x.is_none());
         let index = match index {
             Some(x) => x,
             None => return Err(RouterError::OutOfChannels()),
         };
-        self.channels[index] = Some(channel);
+        self.insert_channel(channel, index)?;
         Ok(index as u8)
     }
 
     /// Add channel at a specific index
     pub fn add_channel_at_index(&mut self, channel: T, index: usize) -> Result<(), RouterError> {
-        if index >= self.max_channels.get() {
+        if index >= self.state.max_channels() {
             return Err(RouterError::ChannelOutOfBounds());
         }
         if self.channels[index].is_some() {
             return Err(RouterError::ChannelAlreadyAssigned());
         }
+        self.insert_channel(channel, index)
+    }
+
+    fn insert_channel(&mut self, channel: T, index: usize) -> Result<(), RouterError> {
+        for msg in self.state.welcome_messages() {
+            channel.try_send(msg)?;
+        }
         self.channels[index] = Some(channel);
         Ok(())
     }
@@ -117,119 +228,426 @@ impl<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> Router<
     /// If `restore` is false: dissociate all channels and reset the hardware, router stays associated to
     /// the driver, if true restore system state.
     ///
+    /// Restoring replays network keys, library config, the encryption key, the event filter and
+    /// SDU masks once the radio reports it started, channels then rerun their own configuration
+    /// and reopen. The same happens when the radio resets on its own, e.g. from a watchdog.
+    ///
     /// If you think the radio is not responding it is best to [Router::release] the driver and issue a
     /// reset via a hardware mechanism then rebuild.
     pub fn reset(&mut self, restore: bool) -> Result<(), DriverError<E>> {
         self.driver.send_message(&ResetSystem::new())?;
-        self.reset_restore.set(restore);
+        self.state.reset(restore);
         if !restore {
-            // TODO release profiles
+            // Dropping the handlers closes the profiles' receivers so they can tell they were
+            // released
+            self.channels = std::array::from_fn(|_| None);
+        }
+        Ok(())
+    }
+
+    // Replay radio wide state lost in a reset
+    fn restore(&mut self) -> Result<(), RouterError> {
+        // A failed message should not leave the rest unrestored, the first error is reported
+        let mut result = Ok(());
+        for msg in self.state.restore_messages() {
+            result = result.and(match msg {
+                Ok(msg) => self.driver.send_message(&msg).map_err(RouterError::from),
+                Err(e) => Err(e.into()),
+            });
+        }
+        // Channels cleared their filter on startup
+        match self.state.restored_event_filter() {
+            Some(msg) => result.and(self.broadcast_message(msg)),
+            None => result,
+        }
+    }
+
+    /// Load a key for an application specific network
+    ///
+    /// Blocks until the radio accepts the key. The key is restored if the radio resets.
+    pub fn set_network_key(&mut self, network: u8, key: NetworkKey) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::Private)
+    }
+
+    /// Load the ANT+ key, see [Router::set_network_key]
+    ///
+    /// Profiles can then be pointed at [Router::network] with [NetworkRole::AntPlus].
+    pub fn set_ant_plus_key(&mut self, network: u8, key: NetworkKey) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::AntPlus)
+    }
+
+    /// Load the ANT-FS key, see [Router::set_network_key]
+    pub fn set_ant_fs_key(&mut self, network: u8, key: NetworkKey) -> Result<(), RouterError> {
+        self.load_network_key(network, key, NetworkRole::AntFs)
+    }
+
+    fn load_network_key(
+        &mut self,
+        network: u8,
+        key: NetworkKey,
+        role: NetworkRole,
+    ) -> Result<(), RouterError> {
+        let msg = self.state.network_key_message(network, key)?;
+        self.send(&msg)?;
+        self.state.insert_network_key(network, key, role);
+        let mut i = 0;
+        while self.state.network_key_pending(network) && i < ROUTER_CAPABILITIES_RETRIES {
+            self.process()?;
+            i += 1;
         }
+        self.state.network_key_result(network)
+    }
+
+    /// Key loaded at `network`
+    pub fn network_key(&self, network: u8) -> Option<NetworkEntry> {
+        self.state.network_key(network)
+    }
+
+    /// Network index whose accepted key fills `role`
+    pub fn network(&self, role: NetworkRole) -> Option<u8> {
+        self.state.network(role)
+    }
+
+    /// Set which extended data the radio appends to received messages, it is restored if the
+    /// radio resets
+    pub fn set_lib_config(&mut self, config: LibConfig) -> Result<(), RouterError> {
+        self.send(&config)?;
+        self.state.set_lib_config(config);
         Ok(())
     }
 
     /// Transmit a message to the radio
+    ///
+    /// Messages relying on features the radio does not report are rejected with
+    /// [RouterError::Unsupported].
     pub fn send(&mut self, msg: &dyn TransmitableMessage) -> Result<(), RouterError> {
+        self.state.check_message(msg)?;
         self.driver.send_message(msg)?;
         Ok(())
     }
 
+    /// Load the encryption key and info shared by all encrypted channels
+    ///
+    /// Must be done before opening any channel configured with
+    /// [crate::encryption::ChannelEncryption]. Blocks until the radio accepts each message, fails
+    /// with [RouterError::Unsupported] if the radio does not support encryption and
+    /// [RouterError::CommandRejected] if it refuses any part of the config. The key is restored if
+    /// the radio resets.
+    pub fn set_encryption_key(&mut self, config: &EncryptionKeyConfig) -> Result<(), RouterError> {
+        config
+            .messages::<N>()
+            .try_for_each(|msg| self.send_command(&msg))?;
+        self.state.set_encryption_key(*config);
+        Ok(())
+    }
+
+    // Send a radio wide command and process messages until the radio answers it
+    fn send_command(&mut self, msg: &dyn TransmitableMessage) -> Result<(), RouterError> {
+        let id = msg.get_tx_msg_id();
+        self.send(msg)?;
+        self.state.start_command(id);
+        let mut i = 0;
+        while !self.state.command_answered() && i < ROUTER_CAPABILITIES_RETRIES {
+            if let Err(e) = self.process() {
+                self.state.finish_command(id).ok();
+                return Err(e);
+            }
+            i += 1;
+        }
+        self.state.finish_command(id)
+    }
+
+    /// Advanced burst capabilities reported by the radio, once they have been requested
+    pub fn advanced_burst_capabilities(&self) -> Option<AdvancedBurstCapabilities> {
+        self.state.advanced_burst_capabilities()
+    }
+
+    /// Negotiate `config` against the radio's advanced burst capabilities and the `peer`'s
+    /// configuration, if known, and apply it
+    ///
+    /// The capabilities are requested first if they are not known. Returns the applied
+    /// configuration, pass its packet length to the profile sending advanced bursts, e.g.
+    /// [crate::plus::profiles::heart_rate::Monitor::set_advanced_burst_packet_length].
+    pub fn configure_advanced_burst(
+        &mut self,
+        config: &AdvancedBurstConfig,
+        peer: Option<&ConfigureAdvancedBurst>,
+    ) -> Result<ConfigureAdvancedBurst, RouterError> {
+        self.state
+            .check_message(&ConfigureAdvancedBurst::default())?;
+        if self.state.advanced_burst_capabilities().is_none() {
+            self.send(&AdvancedBurstConfig::request_capabilities())?;
+            let mut i = 0;
+            while self.state.advanced_burst_capabilities().is_none()
+                && i < ROUTER_CAPABILITIES_RETRIES
+            {
+                self.process()?;
+                i += 1;
+            }
+        }
+        let capabilities = self
+            .state
+            .advanced_burst_capabilities()
+            .ok_or(RouterError::FailedToGetCapabilities())?;
+        let msg = config.negotiate(&capabilities, peer)?;
+        self.send(&msg)?;
+        Ok(msg)
+    }
+
+    /// Events currently suppressed by the radio
+    pub fn event_filter(&self) -> ConfigureEventFilter {
+        self.state.event_filter()
+    }
+
+    /// Configure which events the radio suppresses
+    ///
+    /// All channels are sent the new filter so profiles can stop waiting on events that will
+    /// never arrive. EVENT_TX can not be filtered while a profile has a master channel assigned,
+    /// see [RouterError::EventTxRequired].
+    pub fn set_event_filter(&mut self, filter: ConfigureEventFilter) -> Result<(), RouterError> {
+        self.state.check_event_filter(filter)?;
+        self.send(&filter)?;
+        match self.state.set_event_filter(filter) {
+            Some(msg) => self.broadcast_message(msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Selective data update masks loaded in the radio
+    pub fn sdu_masks(&self) -> &SduMasks {
+        self.state.sdu_masks()
+    }
+
+    /// Load a selective data update mask for data page `page`
+    ///
+    /// Channels already bound to the page pick up the new mask.
+    pub fn set_sdu_mask(&mut self, page: u8, mask: [u8; 8]) -> Result<(), RouterError> {
+        let (masks, msg) = self.state.sdu_mask_update(page, mask)?;
+        self.send(&msg)?;
+        self.state.set_sdu_masks(masks);
+        Ok(())
+    }
+
+    /// Release the mask of `page`, fails if a channel is still bound to it
+    pub fn remove_sdu_mask(&mut self, page: u8) -> Result<(), RouterError> {
+        self.state.remove_sdu_mask(page)
+    }
+
+    /// Only forward data on `channel` when bytes covered by the mask of `page` change
+    pub fn bind_sdu_mask(&mut self, channel: u8, page: u8) -> Result<(), RouterError> {
+        self.set_sdu_binding(channel, Some(page))
+    }
+
+    /// Forward all data on `channel` again
+    pub fn unbind_sdu_mask(&mut self, channel: u8) -> Result<(), RouterError> {
+        self.set_sdu_binding(channel, None)
+    }
+
+    fn set_sdu_binding(&mut self, channel: u8, page: Option<u8>) -> Result<(), RouterError> {
+        let msg = self.state.sdu_binding_message(channel, page)?;
+        self.send(&msg)?;
+        self.state.set_sdu_binding(channel, page);
+        Ok(())
+    }
+
+    /// Page whose mask is applied to `channel`
+    pub fn sdu_binding(&self, channel: u8) -> Option<u8> {
+        self.state.sdu_binding(channel)
+    }
+
     /// Given a reference channel remove it from the router
-    // TODO test
+    ///
+    /// Messages the radio still sends for the channel, e.g. the responses to closing it, only
+    /// reach the rx message callback.
     pub fn remove_channel(&mut self, channel: u8) -> Result<(), RouterError> {
-        // TODO make indux lookup safe
-        let chan = self.channels[channel as usize].take();
-        if chan.is_none() {
+        let slot = self
+            .channels
+            .get_mut(channel as usize)
+            .ok_or(RouterError::ChannelOutOfBounds())?;
+        if slot.take().is_none() {
             return Err(RouterError::ChannelNotAssociated());
         }
         self.driver.send_message(&CloseChannel::new(channel))?;
         self.driver.send_message(&UnAssignChannel::new(channel))?;
+        self.state.remove_channel(channel);
         Ok(())
     }
 
+    /// Set what happens to messages for `channel` when its queue is full
+    ///
+    /// Defaults to [OverflowPolicy::Error]. The policy applies to the index, not the channel, and
+    /// is reset when the channel is removed.
+    pub fn set_overflow_policy(
+        &mut self,
+        channel: u8,
+        policy: OverflowPolicy,
+    ) -> Result<(), RouterError> {
+        self.state.set_overflow_policy(channel, policy)
+    }
+
+    pub fn overflow_policy(&self, channel: u8) -> Option<OverflowPolicy> {
+        self.state.overflow_policy(channel)
+    }
+
+    /// Messages for `channel` discarded since it was added because its queue was full or closed
+    pub fn dropped_messages(&self, channel: u8) -> Option<u32> {
+        self.state.dropped_messages(channel)
+    }
+
+    fn flush_backlogs(&self) -> Result<(), RouterError> {
+        let mut result = Ok(());
+        for (channel, handler) in self.channels.iter().enumerate() {
+            if let Some(handler) = handler {
+                result = result.and(self.state.flush(channel as u8, handler));
+            }
+        }
+        result
+    }
+
+    /// Link statistics gathered for `channel` since it was added or last reset
+    pub fn link_stats(&self, channel: u8) -> Option<LinkStats> {
+        self.state.link_stats(channel)
+    }
+
+    /// Clear the link statistics of `channel`
+    pub fn reset_link_stats(&self, channel: u8) {
+        self.state.reset_link_stats(channel)
+    }
+
     /// Register a callback to obersve all messages, this is meant for debugging or
     /// handling some radio specifics not handled by the router or a specific channel, e.g.
     /// capabilities messages
-    pub fn set_rx_message_callback(&mut self, f: Option<fn(&AntMessage)>) {
-        self.rx_message_callback = f;
+    pub fn set_rx_message_callback(&mut self, f: Option<RxMessageCallback<'a, N>>) {
+        self.state.set_rx_message_callback(f);
     }
 
-    fn route_message(&self, channel: u8, msg: AntMessage) -> Result<(), RouterError> {
-        if channel as usize >= MAX_CHANNELS {
-            return Err(RouterError::ChannelOutOfBounds());
+    fn route_message(&self, channel: u8, msg: AntMessage<N>) -> Result<(), RouterError> {
+        match self.channels.get(channel as usize) {
+            Some(Some(handler)) => self.state.deliver(channel, handler, msg),
+            // No channel to take it, e.g. the radio closing a removed channel, the rx message
+            // callback already saw it
+            _ => Ok(()),
         }
-        match &self.channels[channel as usize] {
-            Some(handler) => handler.try_send(msg)?,
-            None => return Err(RouterError::ChannelNotAssociated()),
-        };
-        Ok(())
     }
 
-    fn broadcast_message(&self, msg: AntMessage) -> Result<(), RouterError> {
-        self.channels
-            .iter()
-            .flatten()
-            .try_for_each(|x| x.try_send(msg.clone()))?;
-        Ok(())
+    fn broadcast_message(&self, msg: AntMessage<N>) -> Result<(), RouterError> {
+        // A failing channel should not starve the rest
+        let mut result = Ok(());
+        for (channel, handler) in self.channels.iter().enumerate() {
+            if let Some(handler) = handler {
+                result = result.and(self.state.deliver(channel as u8, handler, msg.clone()));
+            }
+        }
+        result
+    }
+
+    fn handle_message(&self, msg: AntMessage<N>) -> Result<(), RouterError> {
+        match self.state.handle_message(&msg) {
+            Route::Channel(channel) => self.route_message(channel, msg),
+            Route::Broadcast => self.broadcast_message(msg),
+            Route::Consumed => Ok(()),
+        }
+    }
+
+    /// Query the radio's version string
+    pub fn request_version(&mut self) -> Result<AntVersion<N>, RouterError> {
+        self.request(0, None)
+    }
+
+    pub fn request_serial_number(&mut self) -> Result<SerialNumber, RouterError> {
+        self.request(0, None)
+    }
+
+    /// Query capabilities again, the result also replaces [Router::capabilities]
+    pub fn request_capabilities(&mut self) -> Result<RadioCapabilities, RouterError> {
+        self.request(0, None)
+    }
+
+    pub fn request_channel_status(&mut self, channel: u8) -> Result<ChannelStatus, RouterError> {
+        self.state.check_channel(channel)?;
+        self.request(channel, None)
     }
 
-    fn parse_capabilities(&self, msg: &Capabilities) {
-        self.max_channels
-            .set(msg.base_capabilities.max_ant_channels as usize);
+    /// Query the ID of `channel`, for slaves this is the ID of the master it is tracking
+    pub fn request_channel_id(&mut self, channel: u8) -> Result<ChannelId, RouterError> {
+        self.state.check_channel(channel)?;
+        self.request(channel, None)
     }
 
-    fn handle_message(&self, msg: AntMessage) -> Result<(), RouterError> {
-        if let Some(f) = self.rx_message_callback {
-            f(&msg);
+    pub fn request_event_buffer_configuration(
+        &mut self,
+    ) -> Result<EventBufferConfiguration, RouterError> {
+        self.request(0, None)
+    }
+
+    /// Query the event filter, the result also replaces [Router::event_filter]
+    pub fn request_event_filter(&mut self) -> Result<EventFilter, RouterError> {
+        self.request(0, None)
+    }
+
+    /// Query the mask loaded in SDU mask slot `mask_number`
+    pub fn request_sdu_mask(
+        &mut self,
+        mask_number: u8,
+    ) -> Result<SelectiveDataUpdateMaskSetting, RouterError> {
+        self.request(mask_number, None)
+    }
+
+    /// Read `size` bytes of user NVM starting at `addr`
+    ///
+    /// `size` can not be more than the router's capacity `N`, raise it to read larger regions.
+    pub fn request_user_nvm(&mut self, addr: u16, size: u8) -> Result<UserNvm<N>, RouterError> {
+        let region = RouterState::<N>::user_nvm_region(addr, size)?;
+        self.request(0, Some(region))
+    }
+
+    // Send a request and process messages until the matching response arrives
+    fn request<V: Requested<N>>(
+        &mut self,
+        channel: u8,
+        nvme_region: Option<NvmeRequest>,
+    ) -> Result<V, RouterError> {
+        let msg = self.state.start_request(V::ID, channel, nvme_region);
+        if let Err(e) = self.send(&msg) {
+            self.state.finish_request::<V>().ok();
+            return Err(e);
         }
-        match &msg.message {
-            // These messages all have channel information, forward it accordingly
-            RxMessage::BroadcastData(data) => self.route_message(data.payload.channel_number, msg),
-            RxMessage::AcknowledgedData(data) => {
-                self.route_message(data.payload.channel_number, msg)
-            }
-            RxMessage::BurstTransferData(data) => {
-                self.route_message(data.payload.channel_sequence.channel_number.into(), msg)
-            }
-            RxMessage::AdvancedBurstData(data) => {
-                self.route_message(data.channel_sequence.channel_number.into(), msg)
-            }
-            RxMessage::ChannelEvent(data) => self.route_message(data.payload.channel_number, msg),
-            RxMessage::ChannelResponse(data) => self.route_message(data.channel_number, msg),
-            RxMessage::ChannelStatus(data) => self.route_message(data.channel_number, msg),
-            RxMessage::ChannelId(data) => self.route_message(data.channel_number, msg),
-            // These messages can all provide actionable information to the profile but are not
-            // channel specific
-            RxMessage::StartUpMessage(_) => self.broadcast_message(msg),
-            RxMessage::Capabilities(data) => {
-                self.parse_capabilities(data);
-                self.broadcast_message(msg.clone())
+        let mut i = 0;
+        while !self.state.request_answered() && i < ROUTER_CAPABILITIES_RETRIES {
+            if let Err(e) = self.process() {
+                self.state.finish_request::<V>().ok();
+                return Err(e);
             }
-            RxMessage::AdvancedBurstCapabilities(_) => self.broadcast_message(msg),
-            RxMessage::AdvancedBurstCurrentConfiguration(_) => self.broadcast_message(msg),
-            RxMessage::EncryptionModeParameters(_) => self.broadcast_message(msg),
-            // These message are not channel specific and operate at the router scope, should be
-            // consumed directly at router callback
-            RxMessage::EventFilter(_) => Ok(()),
-            RxMessage::SerialErrorMessage(_) => Ok(()),
-            RxMessage::AntVersion(_) => Ok(()),
-            RxMessage::SerialNumber(_) => Ok(()),
-            RxMessage::EventBufferConfiguration(_) => Ok(()),
-            RxMessage::SelectiveDataUpdateMaskSetting(_) => Ok(()),
-            RxMessage::UserNvm(_) => Ok(()),
-        }?;
-        Ok(())
+            i += 1;
+        }
+        self.state.finish_request()
     }
 
     /// Parse all incoming messages and run callbacks
+    ///
+    /// Messages for a channel whose queue is full are handled according to its
+    /// [OverflowPolicy], see [Router::set_overflow_policy]. Messages from the profiles the radio
+    /// does not support are dropped and reported with [RouterError::Unsupported].
     pub fn process(&mut self) -> Result<(), RouterError> {
-        while let Some(msg) = self.driver.get_message()? {
-            self.handle_message(msg)?;
+        // Routing carries on past a failed delivery, the first error is reported at the end
+        let mut result = self.flush_backlogs();
+        while !self.state.blocked() {
+            match self.driver.get_message()? {
+                Some(msg) => result = result.and(self.handle_message(msg)),
+                None => break,
+            }
+        }
+        if self.state.take_restore_pending() {
+            result = result.and(self.restore());
         }
         while let Ok(msg) = self.receiver.try_recv() {
-            self.driver.send_message(&msg)?;
+            // Only the unsupported message is dropped, the rest of the queue is still sent
+            match self.state.check_profile_message(&msg) {
+                Ok(()) => self.driver.send_message(&msg)?,
+                Err(e) => result = result.and(Err(e)),
+            }
         }
-        Ok(())
+        result
     }
 
     /// Teardown router and return driver
@@ -237,3 +655,520 @@ impl<E, D: Driver<E>, T: TxHandler<AntMessage>, R: RxHandler<TxMessage>> Router<
         self.driver
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::RxError;
+    use crate::messages::channel::{ChannelResponse, MessageCode};
+    use crate::messages::config::{AssignChannel, ChannelType};
+    use crate::messages::data::{BroadcastData, BroadcastDataPayload};
+    use crate::messages::notifications::StartUpMessage;
+    use crate::messages::requested_response::{Capabilities, ChannelState};
+    use crate::network::KeyStatus;
+    use arrayvec::ArrayVec;
+    use packed_struct::PackedStruct;
+    use std::cell::{Cell, RefCell};
+
+    // What the radio has queued, what it answers and what it was sent
+    #[derive(Default)]
+    struct Radio {
+        incoming: ArrayVec<AntMessage, 16>,
+        // Queued once a message with the id is sent, each answer is used once
+        answers: ArrayVec<(TxMessageId, AntMessage), 8>,
+        sent: ArrayVec<TxMessageId, 32>,
+        // Sending a message with this id fails
+        fail: Option<TxMessageId>,
+        // Messages from the profiles
+        outbox: ArrayVec<TxMessage, 4>,
+    }
+
+    impl Radio {
+        fn answer(&mut self, id: TxMessageId, msg: RxMessage) {
+            self.answers.push((id, AntMessage::new(msg).unwrap()));
+        }
+
+        fn push(&mut self, msg: RxMessage) {
+            self.incoming.push(AntMessage::new(msg).unwrap());
+        }
+    }
+
+    struct MockDriver<'r>(&'r RefCell<Radio>);
+
+    impl Driver<()> for MockDriver<'_> {
+        fn get_message(&mut self) -> Result<Option<AntMessage>, DriverError<()>> {
+            Ok(self.0.borrow_mut().incoming.pop_at(0))
+        }
+
+        fn send_message(&mut self, msg: &dyn TransmitableMessage) -> Result<(), DriverError<()>> {
+            let mut radio = self.0.borrow_mut();
+            let id = msg.get_tx_msg_id();
+            if radio.fail == Some(id) {
+                return Err(DriverError::ReferenceError());
+            }
+            radio.sent.push(id);
+            if let Some(index) = radio.answers.iter().position(|(answers, _)| *answers == id) {
+                let (_, answer) = radio.answers.remove(index);
+                radio.incoming.push(answer);
+            }
+            Ok(())
+        }
+    }
+
+    // Channel queue, full once it holds `capacity` messages
+    struct Queue {
+        messages: RefCell<ArrayVec<AntMessage, 16>>,
+        capacity: Cell<usize>,
+    }
+
+    impl Queue {
+        fn new() -> Self {
+            Self {
+                messages: RefCell::new(ArrayVec::new()),
+                capacity: Cell::new(16),
+            }
+        }
+
+        fn take(&self) -> Option<RxMessage> {
+            self.messages.borrow_mut().pop_at(0).map(|msg| msg.message)
+        }
+
+        fn clear(&self) {
+            self.messages.borrow_mut().clear();
+        }
+    }
+
+    impl TxHandler<AntMessage> for &Queue {
+        fn try_send(&self, msg: AntMessage) -> Result<(), TxError> {
+            let mut messages = self.messages.borrow_mut();
+            if messages.len() >= self.capacity.get() {
+                return Err(TxError::Full);
+            }
+            messages.push(msg);
+            Ok(())
+        }
+    }
+
+    struct Profiles<'r>(&'r RefCell<Radio>);
+
+    impl RxHandler<TxMessage> for Profiles<'_> {
+        fn try_recv(&self) -> Result<TxMessage, RxError> {
+            self.0.borrow_mut().outbox.pop_at(0).ok_or(RxError::Empty)
+        }
+    }
+
+    type TestRouter<'r, 'q> = Router<'static, (), MockDriver<'r>, &'q Queue, Profiles<'r>>;
+
+    fn router<'r, 'q>(radio: &'r RefCell<Radio>) -> TestRouter<'r, 'q> {
+        // 8 channels, 3 networks, network keys and event filtering
+        router_with_capabilities(radio, [8, 3, 0, 0x02, 0, 0, 0x04, 0])
+    }
+
+    fn router_with_capabilities<'r, 'q>(
+        radio: &'r RefCell<Radio>,
+        capabilities: [u8; 8],
+    ) -> TestRouter<'r, 'q> {
+        let caps = Capabilities::unpack_from_slice(&capabilities).unwrap();
+        radio
+            .borrow_mut()
+            .answer(TxMessageId::RequestMessage, RxMessage::Capabilities(caps));
+        let router = Router::new(MockDriver(radio), Profiles(radio)).unwrap();
+        radio.borrow_mut().sent.clear();
+        router
+    }
+
+    fn response(channel: u8, message_id: TxMessageId, message_code: MessageCode) -> RxMessage {
+        RxMessage::ChannelResponse(ChannelResponse {
+            channel_number: channel,
+            message_id,
+            message_code,
+        })
+    }
+
+    fn accept_key(radio: &RefCell<Radio>, network: u8) {
+        radio.borrow_mut().answer(
+            TxMessageId::SetNetworkKey,
+            response(
+                network,
+                TxMessageId::SetNetworkKey,
+                MessageCode::ResponseNoError,
+            ),
+        );
+    }
+
+    fn start_up(reason: u8) -> RxMessage {
+        RxMessage::StartUpMessage(StartUpMessage::unpack(&[reason]).unwrap())
+    }
+
+    const KEY: NetworkKey = NetworkKey::Key64([1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // Suppresses EVENT_TX
+    fn event_filter() -> ConfigureEventFilter {
+        ConfigureEventFilter::unpack(&[0, 0x04, 0]).unwrap()
+    }
+
+    #[test]
+    fn network_key_confirmation() {
+        let radio = RefCell::default();
+        let mut router = router(&radio);
+
+        accept_key(&radio, 1);
+        router.set_ant_plus_key(1, KEY).unwrap();
+        assert_eq!(router.network(NetworkRole::AntPlus), Some(1));
+        assert_eq!(
+            router.network_key(1).map(|entry| entry.status),
+            Some(KeyStatus::Accepted)
+        );
+
+        radio.borrow_mut().answer(
+            TxMessageId::SetNetworkKey,
+            response(
+                2,
+                TxMessageId::SetNetworkKey,
+                MessageCode::InvalidNetworkNumber,
+            ),
+        );
+        assert!(matches!(
+            router.set_network_key(2, KEY),
+            Err(RouterError::NetworkKeyRejected(
+                CommandError::InvalidNetworkNumber
+            ))
+        ));
+        assert_eq!(router.network_key(2), None);
+
+        // Radio never answers
+        assert!(matches!(
+            router.set_network_key(0, KEY),
+            Err(RouterError::NetworkKeyNotConfirmed())
+        ));
+    }
+
+    #[test]
+    fn failed_network_keys_are_not_restored() {
+        let radio = RefCell::default();
+        let mut router = router(&radio);
+        radio.borrow_mut().answer(
+            TxMessageId::SetNetworkKey,
+            response(
+                1,
+                TxMessageId::SetNetworkKey,
+                MessageCode::InvalidNetworkNumber,
+            ),
+        );
+        assert!(router.set_network_key(1, KEY).is_err());
+
+        radio.borrow_mut().push(start_up(0x02));
+        radio.borrow_mut().sent.clear();
+        router.process().unwrap();
+        assert!(radio.borrow().sent.is_empty());
+
+        // Radio without network key support never sees the key, not even on a reset
+        let radio = RefCell::default();
+        let mut router = router_with_capabilities(&radio, [8, 3, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(
+            router.set_network_key(0, KEY),
+            Err(RouterError::Unsupported(RadioFeature::Networks))
+        ));
+        assert_eq!(router.network_key(0), None);
+
+        radio.borrow_mut().push(start_up(0x02));
+        router.process().unwrap();
+        assert!(radio.borrow().sent.is_empty());
+    }
+
+    #[test]
+    fn event_tx_filter_refused_for_masters() {
+        let radio = RefCell::default();
+        let mut router = router(&radio);
+        let assign = |channel, channel_type| AssignChannel::new(channel, channel_type, 0, None);
+
+        radio
+            .borrow_mut()
+            .outbox
+            .push(assign(0, ChannelType::BidirectionalMaster).into());
+        router.process().unwrap();
+        assert!(matches!(
+            router.set_event_filter(event_filter()),
+            Err(RouterError::EventTxRequired(0))
+        ));
+        assert_eq!(router.event_filter(), ConfigureEventFilter::default());
+
+        radio
+            .borrow_mut()
+            .outbox
+            .push(UnAssignChannel::new(0).into());
+        router.process().unwrap();
+        router.set_event_filter(event_filter()).unwrap();
+
+        // Slaves do not rely on EVENT_TX, masters are refused while it is filtered
+        radio
+            .borrow_mut()
+            .outbox
+            .push(assign(1, ChannelType::BidirectionalSlave).into());
+        router.process().unwrap();
+        radio.borrow_mut().sent.clear();
+        radio
+            .borrow_mut()
+            .outbox
+            .push(assign(2, ChannelType::MasterTransmitOnly).into());
+        assert!(matches!(
+            router.process(),
+            Err(RouterError::EventTxRequired(2))
+        ));
+        assert!(radio.borrow().sent.is_empty());
+    }
+
+    #[test]
+    fn watchdog_reset_restores_state() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = router(&radio);
+        router.add_channel(&queue).unwrap();
+        accept_key(&radio, 0);
+        router.set_ant_plus_key(0, KEY).unwrap();
+        router.set_lib_config(LibConfig::default()).unwrap();
+        router.set_event_filter(event_filter()).unwrap();
+        queue.clear();
+        radio.borrow_mut().sent.clear();
+
+        radio.borrow_mut().push(start_up(0x02));
+        accept_key(&radio, 0);
+        router.process().unwrap();
+        assert_eq!(
+            radio.borrow().sent.as_slice(),
+            [
+                TxMessageId::SetNetworkKey,
+                TxMessageId::LibConfig,
+                TxMessageId::ConfigureEventFilter,
+            ]
+        );
+        assert!(matches!(queue.take(), Some(RxMessage::StartUpMessage(_))));
+        // Profiles cleared their filter on startup and are sent it again
+        assert_eq!(queue.take(), Some(RxMessage::EventFilter(event_filter())));
+        assert_eq!(router.event_filter(), event_filter());
+
+        // The key is pending until the radio accepts it again
+        assert_eq!(
+            router.network_key(0).map(|entry| entry.status),
+            Some(KeyStatus::Pending)
+        );
+        router.process().unwrap();
+        assert_eq!(router.network(NetworkRole::AntPlus), Some(0));
+    }
+
+    #[test]
+    fn restore_continues_past_failed_messages() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = router(&radio);
+        router.add_channel(&queue).unwrap();
+        accept_key(&radio, 0);
+        router.set_network_key(0, KEY).unwrap();
+        router.set_lib_config(LibConfig::default()).unwrap();
+        router.set_event_filter(event_filter()).unwrap();
+        queue.clear();
+
+        radio.borrow_mut().push(start_up(0x02));
+        radio.borrow_mut().sent.clear();
+        radio.borrow_mut().fail = Some(TxMessageId::SetNetworkKey);
+        assert!(matches!(router.process(), Err(RouterError::DriverError())));
+        assert_eq!(
+            radio.borrow().sent.as_slice(),
+            [TxMessageId::LibConfig, TxMessageId::ConfigureEventFilter]
+        );
+        assert!(matches!(queue.take(), Some(RxMessage::StartUpMessage(_))));
+        assert_eq!(queue.take(), Some(RxMessage::EventFilter(event_filter())));
+    }
+
+    #[test]
+    fn reset_without_restore_releases_state() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let other = Queue::new();
+        let mut router = router(&radio);
+        router.add_channel(&queue).unwrap();
+        accept_key(&radio, 0);
+        router.set_network_key(0, KEY).unwrap();
+        router.set_lib_config(LibConfig::default()).unwrap();
+        router.set_event_filter(event_filter()).unwrap();
+
+        router.reset(false).unwrap();
+        radio.borrow_mut().push(start_up(0x20));
+        radio.borrow_mut().sent.clear();
+        router.process().unwrap();
+        assert!(radio.borrow().sent.is_empty());
+        assert_eq!(router.network_key(0), None);
+        assert_eq!(router.event_filter(), ConfigureEventFilter::default());
+        // The channel was dropped and its slot is free again
+        assert_eq!(router.add_channel(&other).unwrap(), 0);
+
+        // A later watchdog reset restores again, there is nothing left to replay
+        radio.borrow_mut().push(start_up(0x02));
+        radio.borrow_mut().sent.clear();
+        router.process().unwrap();
+        assert!(radio.borrow().sent.is_empty());
+    }
+
+    #[test]
+    fn requests_match_their_response() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = router(&radio);
+        router.add_channel(&queue).unwrap();
+        queue.clear();
+
+        let status = |channel_number| ChannelStatus {
+            channel_number,
+            channel_type: ChannelType::BidirectionalSlave,
+            network_number: 0,
+            channel_state: ChannelState::Assigned,
+        };
+        // Status of another channel arrives first and is routed as usual
+        radio.borrow_mut().push(RxMessage::ChannelStatus(status(0)));
+        radio.borrow_mut().answer(
+            TxMessageId::RequestMessage,
+            RxMessage::ChannelStatus(status(1)),
+        );
+        assert_eq!(router.request_channel_status(1).unwrap(), status(1));
+        assert_eq!(queue.take(), Some(RxMessage::ChannelStatus(status(0))));
+
+        let serial = SerialNumber::unpack(&[1, 2, 3, 4]).unwrap();
+        radio.borrow_mut().answer(
+            TxMessageId::RequestMessage,
+            RxMessage::SerialNumber(serial.clone()),
+        );
+        assert_eq!(router.request_serial_number().unwrap(), serial);
+
+        radio.borrow_mut().answer(
+            TxMessageId::RequestMessage,
+            response(0, TxMessageId::RequestMessage, MessageCode::InvalidMessage),
+        );
+        assert!(matches!(
+            router.request_version(),
+            Err(RouterError::RequestRejected(
+                RequestableMessageId::AntVersion,
+                CommandError::InvalidMessage
+            ))
+        ));
+        // Only the requester sees the error
+        assert_eq!(queue.take(), None);
+
+        assert!(matches!(
+            router.request_serial_number(),
+            Err(RouterError::RequestTimeout(
+                RequestableMessageId::SerialNumber
+            ))
+        ));
+        assert!(matches!(
+            router.request_channel_status(8),
+            Err(RouterError::ConfigError(ConfigError::InvalidChannel { .. }))
+        ));
+    }
+
+    fn broadcast(page: u8) -> RxMessage {
+        RxMessage::BroadcastData(BroadcastData {
+            payload: BroadcastDataPayload {
+                channel_number: 0,
+                data: [page, 0, 0, 0, 0, 0, 0, 0],
+            },
+            extended_info: None,
+        })
+    }
+
+    // Router whose channel 0 queue fits a single message and `pages` broadcasts queued in the radio
+    fn overflowing<'r, 'q>(
+        radio: &'r RefCell<Radio>,
+        queue: &'q Queue,
+        policy: OverflowPolicy,
+        pages: u8,
+    ) -> TestRouter<'r, 'q> {
+        let mut router = router(radio);
+        router.add_channel(queue).unwrap();
+        router.set_overflow_policy(0, policy).unwrap();
+        queue.clear();
+        queue.capacity.set(1);
+        for page in 0..pages {
+            radio.borrow_mut().push(broadcast(page));
+        }
+        router
+    }
+
+    // Read the channel's queue as it fills, processing after each message
+    fn drain(router: &mut TestRouter, queue: &Queue) -> ArrayVec<u8, 16> {
+        let mut pages = ArrayVec::new();
+        while let Some(msg) = queue.take() {
+            if let RxMessage::BroadcastData(data) = msg {
+                pages.push(data.payload.data[0]);
+            }
+            router.process().unwrap();
+        }
+        pages
+    }
+
+    #[test]
+    fn overflow_error_reports_full() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = overflowing(&radio, &queue, OverflowPolicy::Error, 2);
+        assert!(matches!(
+            router.process(),
+            Err(RouterError::ChannelBufferError(ChanError::Tx(
+                TxError::Full
+            )))
+        ));
+        assert_eq!(router.dropped_messages(0), Some(1));
+        assert_eq!(drain(&mut router, &queue).as_slice(), [0]);
+    }
+
+    #[test]
+    fn overflow_drop_newest() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = overflowing(&radio, &queue, OverflowPolicy::DropNewest, 3);
+        router.process().unwrap();
+        assert_eq!(router.dropped_messages(0), Some(2));
+        assert_eq!(drain(&mut router, &queue).as_slice(), [0]);
+    }
+
+    #[test]
+    fn overflow_drop_oldest() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        // One delivered, a full backlog and one more pushing out the oldest held
+        let pages = OVERFLOW_BACKLOG as u8 + 2;
+        let mut router = overflowing(&radio, &queue, OverflowPolicy::DropOldest, pages);
+        router.process().unwrap();
+        assert_eq!(router.dropped_messages(0), Some(1));
+        let expected: ArrayVec<u8, 16> = [0].into_iter().chain(2..pages).collect();
+        assert_eq!(drain(&mut router, &queue), expected);
+    }
+
+    #[test]
+    fn overflow_block_stops_reading() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = overflowing(&radio, &queue, OverflowPolicy::Block, 4);
+        router.process().unwrap();
+        // One delivered, one held and the rest left with the radio
+        assert_eq!(radio.borrow().incoming.len(), 2);
+        assert_eq!(drain(&mut router, &queue).as_slice(), [0, 1, 2, 3]);
+        assert_eq!(router.dropped_messages(0), Some(0));
+    }
+
+    #[test]
+    fn removed_channel_messages_are_dropped() {
+        let radio = RefCell::default();
+        let queue = Queue::new();
+        let mut router = router(&radio);
+        router.add_channel(&queue).unwrap();
+        router.remove_channel(0).unwrap();
+        radio.borrow_mut().push(response(
+            0,
+            TxMessageId::CloseChannel,
+            MessageCode::ResponseNoError,
+        ));
+        radio.borrow_mut().push(broadcast(0));
+        router.process().unwrap();
+        assert!(radio.borrow().incoming.is_empty());
+    }
+}
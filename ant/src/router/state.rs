@@ -0,0 +1,768 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Radio state shared by [super::Router] and [crate::async_router::AsyncRouter]
+//!
+//! [RouterState] does no IO. The routers pass it every message from the radio and it tracks what
+//! the radio was told and answered, then returns where the message should go. Delivery and
+//! sending stay with the routers so each can do them in its own style.
+
+use crate::callback::RxMessageCallback;
+use crate::channel::{OverflowPolicy, TxError, TxHandler};
+use crate::encryption::EncryptionKeyConfig;
+use crate::messages::channel::{ChannelResponse, CommandError, MessageCode};
+use crate::messages::config::{
+    ChannelId, ConfigError, ConfigureEventFilter, ConfigureSelectiveDataUpdates, LibConfig,
+    SetSelectiveDataUpdateMask,
+};
+use crate::messages::control::{NvmeRequest, RequestMessage, RequestableMessageId};
+use crate::messages::requested_response::{
+    AdvancedBurstCapabilities, AntVersion, ChannelStatus, EventBufferConfiguration, EventFilter,
+    RadioCapabilities, SelectiveDataUpdateMaskSetting, SerialNumber, UserNvm,
+};
+use crate::messages::{AntMessage, RxMessage, TransmitableMessage, TxMessage, TxMessageId};
+use crate::network::{KeyStatus, NetworkEntry, NetworkKey, NetworkKeys, NetworkRole, MAX_NETWORKS};
+use crate::router::{message_channel, RouterError, MAX_CHANNELS, OVERFLOW_BACKLOG};
+use crate::sdu::{SduError, SduMasks};
+use crate::stats::LinkStats;
+use arrayvec::ArrayVec;
+use std::cell::{Cell, RefCell};
+
+/// Where a message from the radio goes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Route {
+    Channel(u8),
+    Broadcast,
+    /// Answered something the router asked for, or only of interest to the rx message callback
+    Consumed,
+}
+
+/// Message a request is answered with
+pub(crate) trait Requested<const N: usize>: Sized {
+    const ID: RequestableMessageId;
+
+    fn extract(msg: RxMessage<N>) -> Option<Self>;
+}
+
+macro_rules! requested {
+    ($type:ty, $id:ident, $variant:ident) => {
+        impl<const N: usize> Requested<N> for $type {
+            const ID: RequestableMessageId = RequestableMessageId::$id;
+
+            fn extract(msg: RxMessage<N>) -> Option<Self> {
+                match msg {
+                    RxMessage::$variant(data) => Some(data.into()),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+requested!(AntVersion<N>, AntVersion, AntVersion);
+requested!(SerialNumber, SerialNumber, SerialNumber);
+requested!(RadioCapabilities, Capabilities, Capabilities);
+requested!(ChannelStatus, ChannelStatus, ChannelStatus);
+requested!(ChannelId, ChannelId, ChannelId);
+requested!(
+    EventBufferConfiguration,
+    EventBufferConfiguration,
+    EventBufferConfiguration
+);
+requested!(EventFilter, EventFilter, EventFilter);
+requested!(
+    SelectiveDataUpdateMaskSetting,
+    SelectiveDataUpdateMaskSetting,
+    SelectiveDataUpdateMaskSetting
+);
+requested!(UserNvm<N>, UserNvm, UserNvm);
+
+// Messages held back for a channel whose queue is full
+struct Overflow<const N: usize> {
+    policy: OverflowPolicy,
+    backlog: ArrayVec<AntMessage<N>, OVERFLOW_BACKLOG>,
+    dropped: u32,
+}
+
+impl<const N: usize> Overflow<N> {
+    fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            policy,
+            backlog: ArrayVec::new(),
+            dropped: 0,
+        }
+    }
+
+    fn hold(&mut self, msg: AntMessage<N>) -> Result<(), RouterError> {
+        if self.backlog.is_full() {
+            if self.policy != OverflowPolicy::DropOldest {
+                // Routing stops as soon as a blocking channel holds a message so its backlog
+                // never fills, still never discard held messages behind the user's back
+                self.drop_messages(1);
+                return Err(TxError::Full.into());
+            }
+            self.backlog.remove(0);
+            self.drop_messages(1);
+        }
+        self.backlog.push(msg);
+        Ok(())
+    }
+
+    fn drop_messages(&mut self, count: usize) {
+        self.dropped = self.dropped.saturating_add(count as u32);
+    }
+
+    fn deliver<T: TxHandler<AntMessage<N>>>(
+        &mut self,
+        handler: &T,
+        msg: AntMessage<N>,
+    ) -> Result<(), RouterError> {
+        // Anything held back goes first to keep the channel's messages in order
+        if !self.backlog.is_empty() {
+            return self.hold(msg);
+        }
+        let result = match self.policy {
+            OverflowPolicy::DropOldest | OverflowPolicy::Block => {
+                match handler.try_send(msg.clone()) {
+                    Err(TxError::Full) => return self.hold(msg),
+                    result => result,
+                }
+            }
+            OverflowPolicy::DropNewest | OverflowPolicy::Error => handler.try_send(msg),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(TxError::Full) if self.policy == OverflowPolicy::DropNewest => {
+                self.drop_messages(1);
+                Ok(())
+            }
+            Err(e) => {
+                self.drop_messages(1);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn flush<T: TxHandler<AntMessage<N>>>(&mut self, handler: &T) -> Result<(), RouterError> {
+        while let Some(msg) = self.backlog.first() {
+            match handler.try_send(msg.clone()) {
+                Ok(()) => {
+                    self.backlog.remove(0);
+                }
+                Err(TxError::Full) => break,
+                Err(e) => {
+                    self.drop_messages(self.backlog.len());
+                    self.backlog.clear();
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct RouterState<'a, const N: usize> {
+    capabilities: Cell<Option<RadioCapabilities>>, // what the hardware reports
+    advanced_burst_capabilities: Cell<Option<AdvancedBurstCapabilities>>,
+    event_filter: Cell<ConfigureEventFilter>,
+    sdu_masks: SduMasks,
+    sdu_bindings: [Option<u8>; MAX_CHANNELS], // page whose mask each channel uses
+    network_keys: RefCell<NetworkKeys>,
+    link_stats: [Cell<LinkStats>; MAX_CHANNELS],
+    masters: [Cell<bool>; MAX_CHANNELS], // channels profiles assigned as master
+    overflow: [RefCell<Overflow<N>>; MAX_CHANNELS],
+    /// Policy channels start with and return to when removed
+    default_policy: OverflowPolicy,
+    lib_config: Option<LibConfig>,
+    encryption_key: Option<EncryptionKeyConfig>,
+    /// Restore radio state on the next startup, cleared by a non-restoring reset
+    reset_restore: Cell<bool>,
+    /// Radio reset and its state needs to be replayed
+    restore_pending: Cell<bool>,
+    /// Request in flight and the channel it is for, if the response is channel specific
+    request: Cell<Option<(RequestableMessageId, Option<u8>)>>,
+    response: RefCell<Option<Result<RxMessage<N>, CommandError>>>,
+    /// Radio wide command waiting on its response
+    command: Cell<Option<TxMessageId>>,
+    command_result: Cell<Option<Result<(), CommandError>>>,
+    rx_message_callback: RefCell<Option<RxMessageCallback<'a, N>>>,
+}
+
+impl<'a, const N: usize> RouterState<'a, N> {
+    pub(crate) fn new(default_policy: OverflowPolicy) -> Self {
+        Self {
+            capabilities: Cell::new(None),
+            advanced_burst_capabilities: Cell::new(None),
+            event_filter: Cell::new(ConfigureEventFilter::default()),
+            sdu_masks: SduMasks::new(),
+            sdu_bindings: [None; MAX_CHANNELS],
+            network_keys: RefCell::new(NetworkKeys::new()),
+            link_stats: std::array::from_fn(|_| Cell::new(LinkStats::new())),
+            masters: std::array::from_fn(|_| Cell::new(false)),
+            overflow: std::array::from_fn(|_| RefCell::new(Overflow::new(default_policy))),
+            default_policy,
+            lib_config: None,
+            encryption_key: None,
+            reset_restore: Cell::new(true),
+            restore_pending: Cell::new(false),
+            request: Cell::new(None),
+            response: RefCell::new(None),
+            command: Cell::new(None),
+            command_result: Cell::new(None),
+            rx_message_callback: RefCell::new(None),
+        }
+    }
+
+    pub(crate) fn capabilities(&self) -> Option<RadioCapabilities> {
+        self.capabilities.get()
+    }
+
+    // Channels the radio reports, capped to what the router can hold
+    pub(crate) fn max_channels(&self) -> usize {
+        self.capabilities
+            .get()
+            .map_or(0, |caps| caps.max_channels() as usize)
+            .min(MAX_CHANNELS)
+    }
+
+    // Reject messages the radio would fail with an opaque channel response
+    pub(crate) fn check_message(&self, msg: &dyn TransmitableMessage) -> Result<(), RouterError> {
+        match self.capabilities.get() {
+            Some(caps) => caps.check_message(msg).map_err(RouterError::Unsupported),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a message from a profile, see [RouterState::check_message]
+    ///
+    /// Masters pace their transmissions on EVENT_TX, they can not be assigned while it is
+    /// filtered.
+    pub(crate) fn check_profile_message(&self, msg: &TxMessage<N>) -> Result<(), RouterError> {
+        self.check_message(msg)?;
+        let (channel, master) = match msg {
+            TxMessage::AssignChannel(assign) => (
+                assign.data.channel_number,
+                assign.data.channel_type.is_master(),
+            ),
+            TxMessage::UnAssignChannel(unassign) => (unassign.channel_number, false),
+            _ => return Ok(()),
+        };
+        if master && self.event_filter.get().filters(MessageCode::EventTx) {
+            return Err(RouterError::EventTxRequired(channel));
+        }
+        if let Some(slot) = self.masters.get(channel as usize) {
+            slot.set(master);
+        }
+        Ok(())
+    }
+
+    /// Reject filtering EVENT_TX while a master channel relies on it
+    pub(crate) fn check_event_filter(
+        &self,
+        filter: ConfigureEventFilter,
+    ) -> Result<(), RouterError> {
+        if !filter.filters(MessageCode::EventTx) {
+            return Ok(());
+        }
+        match self.masters.iter().position(Cell::get) {
+            Some(channel) => Err(RouterError::EventTxRequired(channel as u8)),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_channel(&self, channel: u8) -> Result<(), RouterError> {
+        if let Some(caps) = self.capabilities.get() {
+            caps.check_channel(channel)?;
+        }
+        Ok(())
+    }
+
+    /// State a channel added after startup has missed
+    pub(crate) fn welcome_messages(&self) -> impl Iterator<Item = AntMessage<N>> {
+        // Capabilities are only requested once at startup, replay them for late channels
+        let caps = self
+            .capabilities
+            .get()
+            .and_then(|caps| AntMessage::new(RxMessage::Capabilities(caps.into())).ok());
+        caps.into_iter().chain(self.event_filter_message())
+    }
+
+    // Filter for the profiles, none if nothing is filtered
+    fn event_filter_message(&self) -> Option<AntMessage<N>> {
+        let filter = self.event_filter.get();
+        if filter == ConfigureEventFilter::default() {
+            return None;
+        }
+        AntMessage::new(RxMessage::EventFilter(filter)).ok()
+    }
+
+    /// Forget the channel's stats and overflow state
+    pub(crate) fn remove_channel(&self, channel: u8) {
+        self.reset_link_stats(channel);
+        if let Some(master) = self.masters.get(channel as usize) {
+            master.set(false);
+        }
+        if let Some(overflow) = self.overflow.get(channel as usize) {
+            *overflow.borrow_mut() = Overflow::new(self.default_policy);
+        }
+    }
+
+    /// Record a reset, unless `restore` is set everything the radio was told is forgotten
+    pub(crate) fn reset(&mut self, restore: bool) {
+        self.reset_restore.set(restore);
+        if !restore {
+            self.sdu_bindings = [None; MAX_CHANNELS];
+            self.sdu_masks = SduMasks::new();
+            *self.network_keys.get_mut() = NetworkKeys::new();
+            self.link_stats = std::array::from_fn(|_| Cell::new(LinkStats::new()));
+            self.overflow =
+                std::array::from_fn(|_| RefCell::new(Overflow::new(self.default_policy)));
+            self.lib_config = None;
+            self.encryption_key = None;
+        }
+    }
+
+    /// True once after the radio restarted with state to restore
+    pub(crate) fn take_restore_pending(&self) -> bool {
+        self.restore_pending.take()
+    }
+
+    /// Messages replaying radio wide state lost in a reset
+    ///
+    /// Network key responses arrive later, the keys are pending until then.
+    pub(crate) fn restore_messages(
+        &self,
+    ) -> impl Iterator<Item = Result<TxMessage<N>, SduError>> + '_ {
+        let keys: ArrayVec<TxMessage<N>, MAX_NETWORKS> =
+            self.network_keys.borrow_mut().reload().collect();
+        let filter = self.event_filter.get();
+        let masks = &self.sdu_masks;
+        keys.into_iter()
+            .chain(self.lib_config.map(TxMessage::from))
+            .chain(
+                self.encryption_key
+                    .iter()
+                    .flat_map(|config| config.messages()),
+            )
+            .chain((filter != ConfigureEventFilter::default()).then(|| filter.into()))
+            .chain(masks.messages().map(TxMessage::from))
+            .map(Ok)
+            .chain(
+                self.sdu_bindings
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(channel, page)| {
+                        page.map(|page| masks.bind(channel as u8, page).map(Into::into))
+                    }),
+            )
+    }
+
+    /// Filter to pass on to the profiles after a restore, they clear theirs on startup
+    pub(crate) fn restored_event_filter(&self) -> Option<AntMessage<N>> {
+        self.event_filter_message()
+    }
+
+    /// Message loading `key` into `network`, record it with [RouterState::insert_network_key]
+    /// once it was sent
+    pub(crate) fn network_key_message(
+        &self,
+        network: u8,
+        key: NetworkKey,
+    ) -> Result<TxMessage<N>, RouterError> {
+        if network as usize >= MAX_NETWORKS {
+            return Err(ConfigError::InvalidNetwork {
+                network,
+                max_networks: MAX_NETWORKS as u8,
+            }
+            .into());
+        }
+        if let Some(caps) = self.capabilities.get() {
+            caps.check_network(network)?;
+        }
+        Ok(key.message(network))
+    }
+
+    /// Track a key sent to the radio, it is pending until the radio responds
+    pub(crate) fn insert_network_key(&mut self, network: u8, key: NetworkKey, role: NetworkRole) {
+        // Only keys that reached the radio are tracked, anything else would be replayed on reset
+        self.network_keys.get_mut().insert::<N>(network, key, role);
+    }
+
+    pub(crate) fn network_key_pending(&self, network: u8) -> bool {
+        self.network_keys
+            .borrow()
+            .get(network)
+            .is_some_and(|entry| entry.status == KeyStatus::Pending)
+    }
+
+    /// Outcome of loading the key at `network`, a rejected key is forgotten
+    pub(crate) fn network_key_result(&mut self, network: u8) -> Result<(), RouterError> {
+        let keys = self.network_keys.get_mut();
+        match keys.get(network).map(|entry| entry.status) {
+            Some(KeyStatus::Accepted) => Ok(()),
+            Some(KeyStatus::Rejected(e)) => {
+                keys.remove(network);
+                Err(RouterError::NetworkKeyRejected(e))
+            }
+            _ => Err(RouterError::NetworkKeyNotConfirmed()),
+        }
+    }
+
+    pub(crate) fn network_key(&self, network: u8) -> Option<NetworkEntry> {
+        self.network_keys.borrow().get(network)
+    }
+
+    pub(crate) fn network(&self, role: NetworkRole) -> Option<u8> {
+        self.network_keys.borrow().network(role)
+    }
+
+    pub(crate) fn set_lib_config(&mut self, config: LibConfig) {
+        self.lib_config = Some(config);
+    }
+
+    pub(crate) fn set_encryption_key(&mut self, config: EncryptionKeyConfig) {
+        self.encryption_key = Some(config);
+    }
+
+    /// Wait for the response to the radio wide command `id`
+    pub(crate) fn start_command(&self, id: TxMessageId) {
+        self.command.set(Some(id));
+        self.command_result.set(None);
+    }
+
+    pub(crate) fn command_answered(&self) -> bool {
+        self.command_result.get().is_some()
+    }
+
+    /// Stop waiting on the command and return its outcome
+    pub(crate) fn finish_command(&self, id: TxMessageId) -> Result<(), RouterError> {
+        self.command.set(None);
+        match self.command_result.take() {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) => Err(RouterError::CommandRejected(id, e)),
+            None => Err(RouterError::CommandNotConfirmed(id)),
+        }
+    }
+
+    /// Wait for the response to a request, returns the request to send
+    pub(crate) fn start_request(
+        &self,
+        id: RequestableMessageId,
+        channel: u8,
+        nvme_region: Option<NvmeRequest>,
+    ) -> RequestMessage {
+        let response_channel = matches!(
+            id,
+            RequestableMessageId::ChannelStatus
+                | RequestableMessageId::ChannelId
+                | RequestableMessageId::SelectiveDataUpdateMaskSetting
+        )
+        .then_some(channel);
+        self.request.set(Some((id, response_channel)));
+        *self.response.borrow_mut() = None;
+        RequestMessage::new(channel, id, nvme_region)
+    }
+
+    pub(crate) fn request_answered(&self) -> bool {
+        self.response.borrow().is_some()
+    }
+
+    /// Stop waiting on the request and return its outcome
+    pub(crate) fn finish_request<V: Requested<N>>(&self) -> Result<V, RouterError> {
+        self.request.set(None);
+        match self.response.borrow_mut().take() {
+            Some(Ok(msg)) => V::extract(msg).ok_or(RouterError::RequestTimeout(V::ID)),
+            Some(Err(e)) => Err(RouterError::RequestRejected(V::ID, e)),
+            None => Err(RouterError::RequestTimeout(V::ID)),
+        }
+    }
+
+    /// Region for reading `size` bytes of user NVM, the response must fit in `N`
+    pub(crate) fn user_nvm_region(addr: u16, size: u8) -> Result<NvmeRequest, RouterError> {
+        if size as usize > N {
+            // The response would not fit and fail to parse
+            return Err(ConfigError::OutOfRange {
+                value: size.into(),
+                min: 0,
+                max: N.min(u8::MAX as usize) as u16,
+            }
+            .into());
+        }
+        Ok(NvmeRequest::new(addr, size))
+    }
+
+    pub(crate) fn advanced_burst_capabilities(&self) -> Option<AdvancedBurstCapabilities> {
+        self.advanced_burst_capabilities.get()
+    }
+
+    pub(crate) fn event_filter(&self) -> ConfigureEventFilter {
+        self.event_filter.get()
+    }
+
+    /// Record the filter sent to the radio, returns the message passing it on to the profiles
+    pub(crate) fn set_event_filter(&self, filter: ConfigureEventFilter) -> Option<AntMessage<N>> {
+        self.event_filter.set(filter);
+        AntMessage::new(RxMessage::EventFilter(filter)).ok()
+    }
+
+    pub(crate) fn sdu_masks(&self) -> &SduMasks {
+        &self.sdu_masks
+    }
+
+    /// Masks with the mask of `page` set and the message loading it, apply them with
+    /// [RouterState::set_sdu_masks] once the message is sent
+    pub(crate) fn sdu_mask_update(
+        &self,
+        page: u8,
+        mask: [u8; 8],
+    ) -> Result<(SduMasks, SetSelectiveDataUpdateMask), RouterError> {
+        let mut masks = self.sdu_masks.clone();
+        let msg = masks.set_page_mask(page, mask)?;
+        Ok((masks, msg))
+    }
+
+    pub(crate) fn set_sdu_masks(&mut self, masks: SduMasks) {
+        self.sdu_masks = masks;
+    }
+
+    /// Release the mask of `page`, fails if a channel is still bound to it
+    pub(crate) fn remove_sdu_mask(&mut self, page: u8) -> Result<(), RouterError> {
+        if self.sdu_bindings.contains(&Some(page)) {
+            return Err(SduError::PageInUse(page).into());
+        }
+        self.sdu_masks.remove_page_mask(page)?;
+        Ok(())
+    }
+
+    /// Message binding `channel` to the mask of `page`, or unbinding it without a page. Record
+    /// the binding with [RouterState::set_sdu_binding] once it is sent.
+    pub(crate) fn sdu_binding_message(
+        &self,
+        channel: u8,
+        page: Option<u8>,
+    ) -> Result<ConfigureSelectiveDataUpdates, RouterError> {
+        if channel as usize >= self.max_channels() {
+            return Err(RouterError::ChannelOutOfBounds());
+        }
+        match page {
+            Some(page) => Ok(self.sdu_masks.bind(channel, page)?),
+            None => Ok(ConfigureSelectiveDataUpdates::disabled(channel)),
+        }
+    }
+
+    pub(crate) fn set_sdu_binding(&mut self, channel: u8, page: Option<u8>) {
+        if let Some(binding) = self.sdu_bindings.get_mut(channel as usize) {
+            *binding = page;
+        }
+    }
+
+    pub(crate) fn sdu_binding(&self, channel: u8) -> Option<u8> {
+        self.sdu_bindings.get(channel as usize).copied().flatten()
+    }
+
+    pub(crate) fn link_stats(&self, channel: u8) -> Option<LinkStats> {
+        self.link_stats.get(channel as usize).map(Cell::get)
+    }
+
+    pub(crate) fn reset_link_stats(&self, channel: u8) {
+        if let Some(stats) = self.link_stats.get(channel as usize) {
+            stats.set(LinkStats::new());
+        }
+    }
+
+    fn record_link_stats(&self, msg: &RxMessage<N>) {
+        let channel = match message_channel(msg) {
+            Some(channel) => channel,
+            None => return,
+        };
+        if let Some(stats) = self.link_stats.get(channel as usize) {
+            let mut update = stats.get();
+            update.record(msg);
+            stats.set(update);
+        }
+    }
+
+    pub(crate) fn set_overflow_policy(
+        &mut self,
+        channel: u8,
+        policy: OverflowPolicy,
+    ) -> Result<(), RouterError> {
+        let overflow = self
+            .overflow
+            .get_mut(channel as usize)
+            .ok_or(RouterError::ChannelOutOfBounds())?;
+        overflow.get_mut().policy = policy;
+        Ok(())
+    }
+
+    pub(crate) fn overflow_policy(&self, channel: u8) -> Option<OverflowPolicy> {
+        self.overflow
+            .get(channel as usize)
+            .map(|overflow| overflow.borrow().policy)
+    }
+
+    pub(crate) fn dropped_messages(&self, channel: u8) -> Option<u32> {
+        self.overflow
+            .get(channel as usize)
+            .map(|overflow| overflow.borrow().dropped)
+    }
+
+    /// Count a message for `channel` that could not be delivered
+    #[cfg(feature = "async")]
+    pub(crate) fn drop_message(&self, channel: u8) {
+        if let Some(overflow) = self.overflow.get(channel as usize) {
+            overflow.borrow_mut().drop_messages(1);
+        }
+    }
+
+    // A blocking channel is holding a message back, stop reading from the radio. Checked after
+    // every message so a blocking channel holds at most one.
+    pub(crate) fn blocked(&self) -> bool {
+        self.overflow.iter().any(|overflow| {
+            let overflow = overflow.borrow();
+            overflow.policy == OverflowPolicy::Block && !overflow.backlog.is_empty()
+        })
+    }
+
+    /// Pass `msg` to the channel's handler following its [OverflowPolicy]
+    pub(crate) fn deliver<T: TxHandler<AntMessage<N>>>(
+        &self,
+        channel: u8,
+        handler: &T,
+        msg: AntMessage<N>,
+    ) -> Result<(), RouterError> {
+        match self.overflow.get(channel as usize) {
+            Some(overflow) => overflow.borrow_mut().deliver(handler, msg),
+            None => Err(RouterError::ChannelOutOfBounds()),
+        }
+    }
+
+    /// Pass on what the channel held back as far as its queue allows
+    pub(crate) fn flush<T: TxHandler<AntMessage<N>>>(
+        &self,
+        channel: u8,
+        handler: &T,
+    ) -> Result<(), RouterError> {
+        match self.overflow.get(channel as usize) {
+            Some(overflow) => overflow.borrow_mut().flush(handler),
+            None => Err(RouterError::ChannelOutOfBounds()),
+        }
+    }
+
+    /// Oldest message the channel held back
+    #[cfg(feature = "async")]
+    pub(crate) fn take_held(&self, channel: u8) -> Option<AntMessage<N>> {
+        let mut overflow = self.overflow.get(channel as usize)?.borrow_mut();
+        (!overflow.backlog.is_empty()).then(|| overflow.backlog.remove(0))
+    }
+
+    pub(crate) fn set_rx_message_callback(&mut self, f: Option<RxMessageCallback<'a, N>>) {
+        *self.rx_message_callback.get_mut() = f;
+    }
+
+    /// Update the state from `msg` and work out where it goes
+    pub(crate) fn handle_message(&self, msg: &AntMessage<N>) -> Route {
+        if let Some(f) = self.rx_message_callback.borrow_mut().as_mut() {
+            f(msg);
+        }
+        self.capture_response(&msg.message);
+        self.record_link_stats(&msg.message);
+        match &msg.message {
+            RxMessage::ChannelResponse(data) => {
+                if self.network_keys.borrow_mut().handle_response(data) {
+                    // Channel number holds the network number, not for any channel
+                    return Route::Consumed;
+                }
+                if let Some(result) = self.command.get().and_then(|id| data.result_for(id)) {
+                    // Answers a radio wide command, not for any channel
+                    self.command.set(None);
+                    self.command_result.set(Some(result));
+                    return Route::Consumed;
+                }
+                if let Some(Err(e)) = self.request_result(data) {
+                    // The request failed, a successful one is answered by the requested message
+                    *self.response.borrow_mut() = Some(Err(e));
+                    return Route::Consumed;
+                }
+                Route::Channel(data.channel_number)
+            }
+            // These messages can all provide actionable information to the profile but are not
+            // channel specific
+            RxMessage::StartUpMessage(_) => {
+                // Radio unassigned every channel, profiles assign theirs again
+                self.masters.iter().for_each(|master| master.set(false));
+                if self.reset_restore.replace(true) {
+                    // Replayed by the router once the driver is free
+                    self.restore_pending.set(true);
+                } else {
+                    // Reset clears the filter in the radio
+                    self.event_filter.set(ConfigureEventFilter::default());
+                }
+                Route::Broadcast
+            }
+            RxMessage::Capabilities(data) => {
+                self.capabilities.set(Some((*data).into()));
+                Route::Broadcast
+            }
+            RxMessage::AdvancedBurstCapabilities(data) => {
+                self.advanced_burst_capabilities.set(Some(*data));
+                Route::Broadcast
+            }
+            RxMessage::AdvancedBurstCurrentConfiguration(_)
+            | RxMessage::EncryptionModeParameters(_) => Route::Broadcast,
+            RxMessage::EventFilter(data) => {
+                self.event_filter.set(*data);
+                Route::Broadcast
+            }
+            message => match message_channel(message) {
+                Some(channel) => Route::Channel(channel),
+                // Router scope messages, consumed by the rx message callback
+                None => Route::Consumed,
+            },
+        }
+    }
+
+    // Result of the request in flight if `response` is for it
+    fn request_result(&self, response: &ChannelResponse) -> Option<Result<(), CommandError>> {
+        let (_, channel) = self.request.get()?;
+        if channel.is_some_and(|channel| channel != response.channel_number) {
+            return None;
+        }
+        response.result_for(TxMessageId::RequestMessage)
+    }
+
+    // Hold on to `msg` if it answers the request in flight
+    fn capture_response(&self, msg: &RxMessage<N>) {
+        let Some(request) = self.request.get() else {
+            return;
+        };
+        let response = match msg {
+            RxMessage::ChannelStatus(status) => (
+                RequestableMessageId::ChannelStatus,
+                Some(status.channel_number),
+            ),
+            RxMessage::ChannelId(id) => (RequestableMessageId::ChannelId, Some(id.channel_number)),
+            RxMessage::AntVersion(_) => (RequestableMessageId::AntVersion, None),
+            RxMessage::Capabilities(_) => (RequestableMessageId::Capabilities, None),
+            RxMessage::SerialNumber(_) => (RequestableMessageId::SerialNumber, None),
+            RxMessage::EventBufferConfiguration(_) => {
+                (RequestableMessageId::EventBufferConfiguration, None)
+            }
+            RxMessage::EventFilter(_) => (RequestableMessageId::EventFilter, None),
+            RxMessage::SelectiveDataUpdateMaskSetting(mask) => (
+                RequestableMessageId::SelectiveDataUpdateMaskSetting,
+                Some(mask.sdu_mask_number),
+            ),
+            RxMessage::UserNvm(_) => (RequestableMessageId::UserNvm, None),
+            _ => return,
+        };
+        if response == request {
+            *self.response.borrow_mut() = Some(Ok(msg.clone()));
+        }
+    }
+}
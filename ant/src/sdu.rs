@@ -0,0 +1,150 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Selective Data Update mask management
+//!
+//! With selective data updates the radio only forwards a received data page when a byte covered
+//! by the channel's mask changes. The radio holds a small table of masks shared by all channels,
+//! [SduMasks] assigns those slots to data page numbers so each page can have its own mask.
+
+use crate::messages::config::{ConfigureSelectiveDataUpdates, SetSelectiveDataUpdateMask};
+
+/// Mask slots available on the radio
+pub const MAX_SDU_MASKS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SduError {
+    /// Every mask slot is assigned to another page
+    OutOfMasks,
+    /// No mask has been assigned for the page
+    UnknownPage(u8),
+    /// A channel is still bound to the page's mask
+    PageInUse(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SduSlot {
+    page: u8,
+    mask: [u8; 8],
+}
+
+/// Tracks which radio mask slot holds the mask for each data page
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SduMasks {
+    slots: [Option<SduSlot>; MAX_SDU_MASKS],
+}
+
+impl SduMasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assign `mask` to `page`, returns the message to load it in the radio
+    ///
+    /// Each bit set in the mask marks a payload bit that is compared, the first byte covers the
+    /// page number itself. Pages with a mask already assigned reuse their slot.
+    pub fn set_page_mask(
+        &mut self,
+        page: u8,
+        mask: [u8; 8],
+    ) -> Result<SetSelectiveDataUpdateMask, SduError> {
+        let index = match self.mask_number(page) {
+            Some(index) => index as usize,
+            None => self
+                .slots
+                .iter()
+                .position(|x| x.is_none())
+                .ok_or(SduError::OutOfMasks)?,
+        };
+        self.slots[index] = Some(SduSlot { page, mask });
+        Ok(SetSelectiveDataUpdateMask::new(index as u8, mask))
+    }
+
+    /// Release the slot held by `page`
+    ///
+    /// Channels bound to the page should be unbound first, the radio keeps applying the old mask
+    /// until the slot is reassigned.
+    pub fn remove_page_mask(&mut self, page: u8) -> Result<(), SduError> {
+        let index = self.mask_number(page).ok_or(SduError::UnknownPage(page))?;
+        self.slots[index as usize] = None;
+        Ok(())
+    }
+
+    /// Radio mask slot assigned to `page`
+    pub fn mask_number(&self, page: u8) -> Option<u8> {
+        self.slots
+            .iter()
+            .position(|x| x.is_some_and(|slot| slot.page == page))
+            .map(|x| x as u8)
+    }
+
+    /// Mask assigned to `page`
+    pub fn page_mask(&self, page: u8) -> Option<[u8; 8]> {
+        self.slots
+            .iter()
+            .flatten()
+            .find(|slot| slot.page == page)
+            .map(|slot| slot.mask)
+    }
+
+    /// Message to apply the mask of `page` to `channel`
+    pub fn bind(&self, channel: u8, page: u8) -> Result<ConfigureSelectiveDataUpdates, SduError> {
+        let index = self.mask_number(page).ok_or(SduError::UnknownPage(page))?;
+        Ok(ConfigureSelectiveDataUpdates::new(channel, index))
+    }
+
+    /// Messages to reload every assigned mask, e.g. after a radio reset
+    pub fn messages(&self) -> impl Iterator<Item = SetSelectiveDataUpdateMask> + '_ {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.map(|slot| SetSelectiveDataUpdateMask::new(index as u8, slot.mask))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_masks() {
+        let mut masks = SduMasks::new();
+        let msg = masks
+            .set_page_mask(0x10, [0xFF, 0, 0, 0, 0, 0, 0xFF, 0xFF])
+            .unwrap();
+        assert_eq!(msg.sdu_mask_number, 0);
+        let msg = masks.set_page_mask(0x50, [0xFF; 8]).unwrap();
+        assert_eq!(msg.sdu_mask_number, 1);
+        // Updating a page reuses its slot
+        let msg = masks.set_page_mask(0x10, [0xFF; 8]).unwrap();
+        assert_eq!(msg.sdu_mask_number, 0);
+        assert_eq!(masks.page_mask(0x10), Some([0xFF; 8]));
+
+        let bind = masks.bind(3, 0x50).unwrap();
+        assert_eq!(bind.channel_number, 3);
+        assert_eq!(bind.selected_data, 1);
+        assert_eq!(masks.bind(3, 0x51), Err(SduError::UnknownPage(0x51)));
+
+        masks.remove_page_mask(0x10).unwrap();
+        assert_eq!(masks.mask_number(0x10), None);
+        assert_eq!(masks.messages().count(), 1);
+        let msg = masks.set_page_mask(0x20, [0; 8]).unwrap();
+        assert_eq!(msg.sdu_mask_number, 0);
+    }
+
+    #[test]
+    fn out_of_masks() {
+        let mut masks = SduMasks::new();
+        for page in 0..MAX_SDU_MASKS as u8 {
+            masks.set_page_mask(page, [0xFF; 8]).unwrap();
+        }
+        assert_eq!(
+            masks.set_page_mask(0xF0, [0xFF; 8]),
+            Err(SduError::OutOfMasks)
+        );
+    }
+}
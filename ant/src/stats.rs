@@ -0,0 +1,230 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Link statistics
+//!
+//! A receiving channel gets either a message or an `EventRxFail` every channel period, counting
+//! both gives a direct measure of how well a sensor is being heard. [LinkStats] tallies these along
+//! with the transmit side outcomes and, when extended messages carry it, the signal strength.
+
+use crate::messages::channel::MessageCode;
+use crate::messages::data::ExtendedInfo;
+use crate::messages::RxMessage;
+
+/// Number of channel periods covered by [LinkStats::success_rate]
+pub const SUCCESS_WINDOW: u8 = 64;
+
+/// Signal strength of received messages in dBm
+///
+/// Only populated from extended messages with RSSI output enabled, AGC readings are not
+/// included as they are not comparable across radios.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RssiStats {
+    pub last: i8,
+    pub min: i8,
+    pub max: i8,
+    sum: i64,
+    samples: u32,
+}
+
+impl RssiStats {
+    fn new(rssi: i8) -> Self {
+        Self {
+            last: rssi,
+            min: rssi,
+            max: rssi,
+            sum: rssi.into(),
+            samples: 1,
+        }
+    }
+
+    fn record(&mut self, rssi: i8) {
+        self.last = rssi;
+        self.min = self.min.min(rssi);
+        self.max = self.max.max(rssi);
+        self.sum += i64::from(rssi);
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// Mean of all samples, rounded towards zero
+    pub fn mean(&self) -> i8 {
+        (self.sum / i64::from(self.samples)) as i8
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+/// Counters for a single channel
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    /// Broadcast, acknowledged and burst packets received
+    pub rx_messages: u32,
+    /// Channel periods where a message was expected but not received
+    pub rx_failures: u32,
+    pub collisions: u32,
+    /// Broadcasts sent and acknowledged or burst transfers completed
+    pub tx_successes: u32,
+    /// Acknowledged or burst transfers that were not acknowledged
+    pub tx_failures: u32,
+    /// Burst receptions that failed or arrived out of sequence
+    pub burst_errors: u32,
+    pub rssi: Option<RssiStats>,
+    /// Outcome of each recent channel period, least significant bit is the latest
+    history: u64,
+    history_len: u8,
+}
+
+impl LinkStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the counters from a message for this channel
+    pub fn record<const N: usize>(&mut self, msg: &RxMessage<N>) {
+        match msg {
+            RxMessage::BroadcastData(data) => self.record_rx(&data.extended_info, true),
+            RxMessage::AcknowledgedData(data) => self.record_rx(&data.extended_info, true),
+            // Bursts span many periods but only the first packet takes the place of a broadcast
+            RxMessage::BurstTransferData(data) => self.record_rx(
+                &data.extended_info,
+                u8::from(data.payload.channel_sequence.sequence_number) == 0,
+            ),
+            RxMessage::AdvancedBurstData(data) => {
+                self.record_rx(&None, u8::from(data.channel_sequence.sequence_number) == 0)
+            }
+            RxMessage::ChannelEvent(event) => self.record_code(event.payload.message_code),
+            RxMessage::ChannelResponse(response) => self.record_code(response.message_code),
+            _ => (),
+        }
+    }
+
+    fn record_rx(&mut self, extended_info: &Option<ExtendedInfo>, period: bool) {
+        self.rx_messages = self.rx_messages.saturating_add(1);
+        if period {
+            self.record_period(true);
+        }
+        let rssi = extended_info.as_ref().and_then(ExtendedInfo::rssi_dbm);
+        if let Some(rssi) = rssi {
+            match &mut self.rssi {
+                Some(stats) => stats.record(rssi),
+                None => self.rssi = Some(RssiStats::new(rssi)),
+            }
+        }
+    }
+
+    fn record_code(&mut self, code: MessageCode) {
+        let counter = match code {
+            MessageCode::EventRxFail => {
+                self.record_period(false);
+                &mut self.rx_failures
+            }
+            MessageCode::EventChannelCollision => &mut self.collisions,
+            MessageCode::EventTx | MessageCode::EventTransferTxCompleted => &mut self.tx_successes,
+            MessageCode::EventTransferTxFailed => &mut self.tx_failures,
+            MessageCode::EventTransferRxFailed
+            | MessageCode::TransferSequenceNumberError
+            | MessageCode::TransferInError => &mut self.burst_errors,
+            _ => return,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    fn record_period(&mut self, success: bool) {
+        self.history = (self.history << 1) | u64::from(success);
+        self.history_len = (self.history_len + 1).min(SUCCESS_WINDOW);
+    }
+
+    /// Fraction of the last [SUCCESS_WINDOW] channel periods in which a message was received
+    ///
+    /// None until the first period completes. Only receive periods are counted, a master channel
+    /// should look at [LinkStats::tx_successes] and [LinkStats::tx_failures] instead.
+    pub fn success_rate(&self) -> Option<f32> {
+        if self.history_len == 0 {
+            return None;
+        }
+        let mask = u64::MAX >> (u64::BITS - u32::from(self.history_len));
+        Some((self.history & mask).count_ones() as f32 / f32::from(self.history_len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::channel::ChannelEvent;
+    use crate::messages::data::BroadcastData;
+
+    fn event(code: MessageCode) -> RxMessage {
+        RxMessage::ChannelEvent(ChannelEvent::unpack_from_slice(&[0, 0x01, code as u8]).unwrap())
+    }
+
+    fn broadcast(rssi: Option<i8>) -> RxMessage {
+        match rssi {
+            Some(rssi) => RxMessage::BroadcastData(
+                BroadcastData::unpack_from_slice(&[
+                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0x20, rssi as u8, 0xB0,
+                ])
+                .unwrap(),
+            ),
+            None => RxMessage::BroadcastData(BroadcastData::new(0, [0; 8])),
+        }
+    }
+
+    #[test]
+    fn counters() {
+        let mut stats = LinkStats::new();
+        assert_eq!(stats.success_rate(), None);
+        stats.record(&broadcast(None));
+        stats.record(&event(MessageCode::EventRxFail));
+        stats.record(&event(MessageCode::EventChannelCollision));
+        stats.record(&event(MessageCode::EventTransferTxCompleted));
+        stats.record(&event(MessageCode::EventTransferTxFailed));
+        stats.record(&event(MessageCode::EventTransferRxFailed));
+        stats.record(&event(MessageCode::EventChannelClosed));
+        assert_eq!(stats.rx_messages, 1);
+        assert_eq!(stats.rx_failures, 1);
+        assert_eq!(stats.collisions, 1);
+        assert_eq!(stats.tx_successes, 1);
+        assert_eq!(stats.tx_failures, 1);
+        assert_eq!(stats.burst_errors, 1);
+        assert_eq!(stats.success_rate(), Some(0.5));
+        assert_eq!(stats.rssi, None);
+    }
+
+    #[test]
+    fn rssi() {
+        let mut stats = LinkStats::new();
+        for rssi in [-60, -70, -80] {
+            stats.record(&broadcast(Some(rssi)));
+        }
+        let rssi = stats.rssi.unwrap();
+        assert_eq!(rssi.last, -80);
+        assert_eq!(rssi.min, -80);
+        assert_eq!(rssi.max, -60);
+        assert_eq!(rssi.mean(), -70);
+        assert_eq!(rssi.samples(), 3);
+    }
+
+    #[test]
+    fn success_window() {
+        let mut stats = LinkStats::new();
+        for _ in 0..SUCCESS_WINDOW {
+            stats.record(&event(MessageCode::EventRxFail));
+        }
+        assert_eq!(stats.success_rate(), Some(0.0));
+        for _ in 0..SUCCESS_WINDOW / 4 {
+            stats.record(&broadcast(None));
+        }
+        assert_eq!(stats.success_rate(), Some(0.25));
+        for _ in 0..SUCCESS_WINDOW {
+            stats.record(&broadcast(None));
+        }
+        assert_eq!(stats.success_rate(), Some(1.0));
+    }
+}